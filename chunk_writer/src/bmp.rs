@@ -0,0 +1,477 @@
+use super::*;
+use image;
+
+pub(crate) const BMP_SIGNATURE: [u8; 2] = [0x42, 0x4d];
+const FILE_HEADER_SIZE: usize = 14;
+const CORE_HEADER_SIZE: usize = 12;
+const INFO_HEADER_SIZE: usize = 40;
+// 72 dpi
+const PIXELS_PER_METRE: u32 = 2835;
+
+const COMPRESSION_NONE: u32 = 0;
+const COMPRESSION_RLE8: u32 = 1;
+const COMPRESSION_RLE4: u32 = 2;
+
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum BitDepth {
+    One,
+    Four,
+    Eight,
+    TwentyFour,
+    ThirtyTwo,
+}
+impl BitDepth {
+    pub(crate) fn bits(&self) -> u16 {
+        match self {
+            BitDepth::One => 1,
+            BitDepth::Four => 4,
+            BitDepth::Eight => 8,
+            BitDepth::TwentyFour => 24,
+            BitDepth::ThirtyTwo => 32,
+        }
+    }
+    pub(crate) fn from_bits(bits: u16) -> Option<Self> {
+        match bits {
+            1 => Some(BitDepth::One),
+            4 => Some(BitDepth::Four),
+            8 => Some(BitDepth::Eight),
+            24 => Some(BitDepth::TwentyFour),
+            32 => Some(BitDepth::ThirtyTwo),
+            _ => None,
+        }
+    }
+    fn is_paletted(&self) -> bool {
+        self.bits() <= 8
+    }
+}
+
+// rows are padded out to a multiple of four bytes
+fn row_size(width: usize, bits: usize) -> usize {
+    (width * bits).div_ceil(32) * 4
+}
+
+fn check_palette_size(image: &image::ColorImage, bit_depth: &BitDepth) -> Result<(), std::io::Error> {
+    if bit_depth.is_paletted() && image.palette.size() > 1 << bit_depth.bits() {
+        return Err(invalid_input("palette has too many colours for the bit depth"));
+    }
+    Ok(())
+}
+
+// the info header, colour table and bottom-up pixel rows, without the file header
+// icons follow the pixels with a 1 bit transparency mask, which is counted in the height
+pub(crate) fn encode_dib(image: &image::ColorImage, bit_depth: &BitDepth, with_mask: bool) -> Result<Vec<u8>, std::io::Error> {
+    check_palette_size(image, bit_depth)?;
+    let width = image.canvas.width;
+    let height = if with_mask { image.canvas.height * 2 } else { image.canvas.height };
+    let bits = bit_depth.bits();
+    let colors = if bit_depth.is_paletted() { palette_colors(&image.palette) } else { Vec::new() };
+    let mut pixels = encode_pixels(image, bit_depth);
+    if with_mask {
+        // every pixel is opaque
+        pixels.extend(vec![0; row_size(width as usize, 1) * image.canvas.height as usize]);
+    }
+
+    let mut data = Vec::new();
+    data.extend(&(INFO_HEADER_SIZE as u32).to_le_bytes());
+    data.extend(&width.to_le_bytes());
+    data.extend(&height.to_le_bytes());
+    // colour planes
+    data.extend(&1u16.to_le_bytes());
+    data.extend(&bits.to_le_bytes());
+    data.extend(&COMPRESSION_NONE.to_le_bytes());
+    data.extend(&(pixels.len() as u32).to_le_bytes());
+    data.extend(&PIXELS_PER_METRE.to_le_bytes());
+    data.extend(&PIXELS_PER_METRE.to_le_bytes());
+    data.extend(&(colors.len() as u32).to_le_bytes());
+    // all colours are important
+    data.extend(&0u32.to_le_bytes());
+    for color in colors {
+        data.extend(&[color.blue, color.green, color.red, 0]);
+    }
+    data.extend(pixels);
+    Ok(data)
+}
+
+fn encode_pixels(image: &image::ColorImage, bit_depth: &BitDepth) -> Vec<u8> {
+    let width = image.canvas.width as usize;
+    let bits = bit_depth.bits() as usize;
+    let row_length = row_size(width, bits);
+    let mut data = Vec::new();
+    // rows are stored from the bottom of the image up
+    for y in (0..image.canvas.height).rev() {
+        let mut row = vec![0; row_length];
+        for x in 0..image.canvas.width {
+            let column = x as usize;
+            match bit_depth {
+                BitDepth::TwentyFour | BitDepth::ThirtyTwo => {
+                    let color = image.pixel(x, y);
+                    let offset = column * (bits / 8);
+                    row[offset] = color.blue;
+                    row[offset + 1] = color.green;
+                    row[offset + 2] = color.red;
+                    if bits == 32 {
+                        // reserved in plain bitmaps, but read as alpha when embedded in icons
+                        row[offset + 3] = 0xff;
+                    }
+                },
+                _ => {
+                    let index = *image.canvas.pixel(x, y) as u8;
+                    let pixels_per_byte = 8 / bits;
+                    let shift = 8 - bits * (column % pixels_per_byte + 1);
+                    row[column / pixels_per_byte] |= index << shift;
+                },
+            }
+        }
+        data.extend(row);
+    }
+    data
+}
+
+pub struct BmpImage {
+    pub image: image::ColorImage,
+    pub bit_depth: BitDepth,
+}
+impl BmpImage {
+    pub fn encode(&self) -> Result<Vec<u8>, std::io::Error> {
+        let dib = encode_dib(&self.image, &self.bit_depth, false)?;
+        let color_table_length = if self.bit_depth.is_paletted() { self.image.palette.size() * 4 } else { 0 };
+        let pixel_offset = FILE_HEADER_SIZE + INFO_HEADER_SIZE + color_table_length;
+        let mut data = Vec::new();
+        data.extend(&BMP_SIGNATURE);
+        data.extend(&((FILE_HEADER_SIZE + dib.len()) as u32).to_le_bytes());
+        // reserved
+        data.extend(&[0; 4]);
+        data.extend(&(pixel_offset as u32).to_le_bytes());
+        data.extend(dib);
+        Ok(data)
+    }
+}
+impl Image for BmpImage {
+    fn write(self, path: &str) -> Result<fs::File, std::io::Error> {
+        let data = self.encode()?;
+        let mut file_image = fs::File::create(path)?;
+        file_image.write_all(&data)?;
+        Ok(file_image)
+    }
+}
+
+pub fn read(path: &str) -> Result<image::ColorImage, std::io::Error> {
+    decode(&fs::read(path)?)
+}
+
+pub fn decode(bytes: &[u8]) -> Result<image::ColorImage, std::io::Error> {
+    decode_with_limits(bytes, &Limits::default())
+}
+
+/// Decodes a bmp, failing with `LimitExceeded` if its dimensions go past the limits.
+pub fn decode_with_limits(bytes: &[u8], limits: &Limits) -> Result<image::ColorImage, std::io::Error> {
+    if read_bytes(bytes, 0, 2)? != BMP_SIGNATURE {
+        return Err(invalid_data("missing bmp signature"));
+    }
+    let pixel_offset = read_le_u32(bytes, 10)? as usize;
+    if pixel_offset < FILE_HEADER_SIZE {
+        return Err(invalid_data("pixel data overlaps the bmp file header"));
+    }
+    decode_dib(&bytes[FILE_HEADER_SIZE..], Some(pixel_offset - FILE_HEADER_SIZE), false, limits)
+}
+
+struct DibHeader {
+    width: usize,
+    height: usize,
+    top_down: bool,
+    bits: usize,
+    compression: u32,
+    colors: Vec<image::RGB>,
+    pixel_offset: usize,
+}
+
+fn decode_dib_header(dib: &[u8], pixel_offset: Option<usize>, with_mask: bool) -> Result<DibHeader, std::io::Error> {
+    let header_size = read_le_u32(dib, 0)? as usize;
+    let (width, height, bits, compression, colors_used, palette_entry_size) = if header_size == CORE_HEADER_SIZE {
+        let width = read_le_u16(dib, 4)? as i64;
+        let height = read_le_u16(dib, 6)? as i16 as i64;
+        let bits = read_le_u16(dib, 10)? as usize;
+        (width, height, bits, COMPRESSION_NONE, 0, 3)
+    }
+    else if header_size >= INFO_HEADER_SIZE {
+        let width = read_le_u32(dib, 4)? as i32 as i64;
+        let height = read_le_u32(dib, 8)? as i32 as i64;
+        let bits = read_le_u16(dib, 14)? as usize;
+        let compression = read_le_u32(dib, 16)?;
+        let colors_used = read_le_u32(dib, 32)? as usize;
+        (width, height, bits, compression, colors_used, 4)
+    }
+    else {
+        return Err(invalid_data("unsupported bmp header size"));
+    };
+    if width <= 0 || height == 0 {
+        return Err(invalid_data("bmp has no pixels"));
+    }
+    match (bits, compression) {
+        (1, COMPRESSION_NONE) | (4, COMPRESSION_NONE) | (8, COMPRESSION_NONE) |
+        (24, COMPRESSION_NONE) | (32, COMPRESSION_NONE) |
+        (8, COMPRESSION_RLE8) | (4, COMPRESSION_RLE4) => (),
+        _ => return Err(invalid_data("unsupported bmp bit depth or compression")),
+    }
+    if compression != COMPRESSION_NONE && height < 0 {
+        return Err(invalid_data("compressed bmp cannot be stored top-down"));
+    }
+
+    let palette_length = if bits > 8 { 0 } else if colors_used == 0 { 1 << bits } else { colors_used };
+    if palette_length > 256 {
+        return Err(invalid_data("bmp colour table is too large"));
+    }
+    let mut colors = Vec::new();
+    for i in 0..palette_length {
+        let entry = read_bytes(dib, header_size + i * palette_entry_size, 3)?;
+        colors.push(image::RGB { red: entry[2], green: entry[1], blue: entry[0] });
+    }
+    let pixel_offset = pixel_offset.unwrap_or(header_size + palette_length * palette_entry_size);
+    Ok(DibHeader {
+        width: width as usize,
+        height: if with_mask { height.unsigned_abs() as usize / 2 } else { height.unsigned_abs() as usize },
+        top_down: height < 0,
+        bits,
+        compression,
+        colors,
+        pixel_offset,
+    })
+}
+
+// the transparency mask that follows the pixels of icons is ignored
+pub(crate) fn decode_dib(dib: &[u8], pixel_offset: Option<usize>, with_mask: bool, limits: &Limits) -> Result<image::ColorImage, std::io::Error> {
+    let header = decode_dib_header(dib, pixel_offset, with_mask)?;
+    // run-length data can fill a large image from a few bytes, so this has to come before anything is allocated
+    limits.check_dimensions(header.width as u32, header.height as u32)?;
+    // the whole image is held as a vec of indices, which can only be allocated if its size in bytes fits an isize
+    let pixel_count = match header.width.checked_mul(header.height) {
+        Some(pixel_count) if pixel_count.checked_mul(std::mem::size_of::<usize>()).is_some_and(|size| size <= isize::MAX as usize) => pixel_count,
+        _ => return Err(invalid_data("bmp dimensions are too large")),
+    };
+    let pixel_data = match dib.get(header.pixel_offset..) {
+        Some(pixel_data) => pixel_data,
+        None => return Err(invalid_data("bmp pixel data offset is past the end of the file")),
+    };
+    let row_length = row_size(header.width, header.bits);
+    if header.compression == COMPRESSION_NONE && row_length.checked_mul(header.height).is_none_or(|length| length > pixel_data.len()) {
+        return Err(invalid_data("bmp pixel data is shorter than its dimensions"));
+    }
+    let (width, height) = (header.width as u32, header.height as u32);
+    match header.compression {
+        COMPRESSION_RLE8 => image_from_indices(width, height, &header.colors, decode_rle(pixel_data, &header, pixel_count, false)?),
+        COMPRESSION_RLE4 => image_from_indices(width, height, &header.colors, decode_rle(pixel_data, &header, pixel_count, true)?),
+        _ if header.bits <= 8 => {
+            let mut indices = vec![0; pixel_count];
+            let pixels_per_byte = 8 / header.bits;
+            let mask = ((1u16 << header.bits) - 1) as u8;
+            for row in 0..header.height {
+                let row_data = read_bytes(pixel_data, row * row_length, row_length)?;
+                let y = if header.top_down { row } else { header.height - 1 - row };
+                for x in 0..header.width {
+                    let shift = 8 - header.bits * (x % pixels_per_byte + 1);
+                    indices[y * header.width + x] = ((row_data[x / pixels_per_byte] >> shift) & mask) as usize;
+                }
+            }
+            image_from_indices(width, height, &header.colors, indices)
+        },
+        _ => {
+            let mut pixels = vec![image::RGB::default(); pixel_count];
+            let bytes_per_pixel = header.bits / 8;
+            for row in 0..header.height {
+                let row_data = read_bytes(pixel_data, row * row_length, row_length)?;
+                let y = if header.top_down { row } else { header.height - 1 - row };
+                for x in 0..header.width {
+                    let offset = x * bytes_per_pixel;
+                    pixels[y * header.width + x] = image::RGB {
+                        red: row_data[offset + 2],
+                        green: row_data[offset + 1],
+                        blue: row_data[offset],
+                    };
+                }
+            }
+            Ok(image_from_rgb(width, height, &pixels))
+        },
+    }
+}
+
+// both run-length encodings count rows from the bottom, pixels skipped by a delta are left at index 0
+fn decode_rle(data: &[u8], header: &DibHeader, pixel_count: usize, nibbles: bool) -> Result<Vec<usize>, std::io::Error> {
+    let mut indices = vec![0; pixel_count];
+    let mut x = 0;
+    let mut row = 0;
+    let mut offset = 0;
+    let mut set_index = |x: usize, row: usize, index: u8| {
+        if x < header.width && row < header.height {
+            indices[(header.height - 1 - row) * header.width + x] = index as usize;
+        }
+    };
+    loop {
+        let count = read_u8(data, offset)? as usize;
+        let value = read_u8(data, offset + 1)?;
+        offset += 2;
+        if count > 0 {
+            for i in 0..count {
+                let index = if !nibbles { value } else if i % 2 == 0 { value >> 4 } else { value & 0x0f };
+                set_index(x, row, index);
+                x += 1;
+            }
+            continue;
+        }
+        match value {
+            // end of line
+            0 => {
+                x = 0;
+                row += 1;
+            },
+            // end of bitmap
+            1 => break,
+            // delta
+            2 => {
+                x += read_u8(data, offset)? as usize;
+                row += read_u8(data, offset + 1)? as usize;
+                offset += 2;
+            },
+            // absolute mode, a literal run padded to a 16 bit boundary
+            _ => {
+                let length = value as usize;
+                let byte_count = if nibbles { length.div_ceil(2) } else { length };
+                let literal = read_bytes(data, offset, byte_count)?;
+                for i in 0..length {
+                    let index = if !nibbles { literal[i] } else if i % 2 == 0 { literal[i / 2] >> 4 } else { literal[i / 2] & 0x0f };
+                    set_index(x, row, index);
+                    x += 1;
+                }
+                offset += byte_count + byte_count % 2;
+            },
+        }
+        if row > header.height {
+            return Err(invalid_data("run-length data extends past the end of the bmp"));
+        }
+    }
+    Ok(indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn test_image(color_count: u8) -> image::ColorImage {
+        let mut image = image::ColorImage::new(13, 7, image::RGB { red: 0, green: 255, blue: 0 });
+        for x in 0..13 {
+            for y in 0..7 {
+                let value = ((x * 7 + y * 3) % color_count as u32) as u8;
+                image.set_pixel(x, y, image::RGB { red: value, green: 255 - value, blue: value / 2 });
+            }
+        }
+        image
+    }
+    fn assert_same_pixels(a: &image::ColorImage, b: &image::ColorImage) {
+        assert_eq!(a.canvas.width, b.canvas.width);
+        assert_eq!(a.canvas.height, b.canvas.height);
+        for x in 0..a.canvas.width {
+            for y in 0..a.canvas.height {
+                assert_eq!(a.pixel(x, y), b.pixel(x, y));
+            }
+        }
+    }
+    #[test]
+    fn bmp_round_trips_every_bit_depth() {
+        let depths = vec![
+            (BitDepth::One, 2),
+            (BitDepth::Four, 16),
+            (BitDepth::Eight, 200),
+            (BitDepth::TwentyFour, 200),
+            (BitDepth::ThirtyTwo, 200),
+        ];
+        for (bit_depth, color_count) in depths {
+            let bmp = BmpImage { image: test_image(color_count), bit_depth };
+            let decoded = decode(&bmp.encode().unwrap()).unwrap();
+            assert_same_pixels(&bmp.image, &decoded);
+        }
+    }
+    #[test]
+    fn bmp_rejects_palette_too_large_for_bit_depth() {
+        let bmp = BmpImage { image: test_image(3), bit_depth: BitDepth::One };
+        assert_eq!(bmp.encode().unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+    }
+    fn rle_bitmap(bits: u16, compression: u32, width: u32, height: u32, colors: u32, pixels: &[u8]) -> Vec<u8> {
+        let mut dib: Vec<u8> = Vec::new();
+        dib.extend(&(INFO_HEADER_SIZE as u32).to_le_bytes());
+        dib.extend(&width.to_le_bytes());
+        dib.extend(&height.to_le_bytes());
+        dib.extend(&1u16.to_le_bytes());
+        dib.extend(&bits.to_le_bytes());
+        dib.extend(&compression.to_le_bytes());
+        dib.extend(&(pixels.len() as u32).to_le_bytes());
+        dib.extend(&[0; 8]);
+        dib.extend(&colors.to_le_bytes());
+        dib.extend(&[0; 4]);
+        for i in 0..colors {
+            dib.extend(&[0, 0, (i * 10) as u8, 0]);
+        }
+        dib.extend(pixels);
+        let mut data = Vec::new();
+        data.extend(&BMP_SIGNATURE);
+        data.extend(&((FILE_HEADER_SIZE + dib.len()) as u32).to_le_bytes());
+        data.extend(&[0; 4]);
+        data.extend(&((FILE_HEADER_SIZE + INFO_HEADER_SIZE) as u32 + colors * 4).to_le_bytes());
+        data.extend(dib);
+        data
+    }
+    fn red_values(image: &image::ColorImage) -> Vec<u8> {
+        let mut values = Vec::new();
+        for y in 0..image.canvas.height {
+            for x in 0..image.canvas.width {
+                values.push(image.pixel(x, y).red / 10);
+            }
+        }
+        values
+    }
+    #[test]
+    fn decode_reads_rle8() {
+        let pixels = [
+            // bottom row: run of three 1s then an absolute run of 2, 3, 4 (padded)
+            3, 1, 0, 3, 2, 3, 4, 0, 0, 0,
+            // delta one pixel right on the next row, then a run of 5s
+            0, 2, 1, 0, 5, 5, 0, 0,
+            // top row
+            6, 7, 0, 1,
+        ];
+        let image = decode(&rle_bitmap(8, COMPRESSION_RLE8, 6, 3, 8, &pixels)).unwrap();
+        assert_eq!(red_values(&image), vec![
+            7, 7, 7, 7, 7, 7,
+            0, 5, 5, 5, 5, 5,
+            1, 1, 1, 2, 3, 4,
+        ]);
+    }
+    #[test]
+    fn decode_reads_rle4() {
+        let pixels = [
+            // alternating 1 and 2, then an absolute run of 3, 4, 5 (padded)
+            3, 0x12, 0, 3, 0x34, 0x50, 0, 0,
+            // run of 6s on the top row
+            6, 0x66, 0, 1,
+        ];
+        let image = decode(&rle_bitmap(4, COMPRESSION_RLE4, 6, 2, 16, &pixels)).unwrap();
+        assert_eq!(red_values(&image), vec![
+            6, 6, 6, 6, 6, 6,
+            1, 2, 1, 3, 4, 5,
+        ]);
+    }
+    #[test]
+    fn decode_rejects_truncated_bitmap() {
+        let bmp = BmpImage { image: test_image(16), bit_depth: BitDepth::Four };
+        let data = bmp.encode().unwrap();
+        assert!(decode(&data[..data.len() - 10]).is_err());
+    }
+    #[test]
+    fn decode_checks_dimensions_before_allocating() {
+        let huge = rle_bitmap(8, COMPRESSION_RLE8, 0x7fffffff, 0x7fffffff, 2, &[0, 1]);
+        assert_eq!(decode(&huge).err().unwrap().to_string(), "bmp dimensions are too large");
+        let short = rle_bitmap(24, COMPRESSION_NONE, 40000, 40000, 0, &[0; 16]);
+        assert_eq!(decode(&short).err().unwrap().to_string(), "bmp pixel data is shorter than its dimensions");
+        // a few bytes of run-length data would otherwise claim 1.6 billion pixels
+        let bomb = rle_bitmap(8, COMPRESSION_RLE8, 40000, 40000, 2, &[0, 1]);
+        let error = decode_with_limits(&bomb, &Limits { max_pixels: 1000, ..Limits::default() }).err().unwrap();
+        assert_eq!(LimitExceeded::from_error(&error), Some(LimitExceeded::Pixels));
+    }
+}
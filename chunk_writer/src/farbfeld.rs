@@ -0,0 +1,140 @@
+use super::*;
+use image;
+
+pub(crate) const FARBFELD_SIGNATURE: [u8; 8] = *b"farbfeld";
+const HEADER_SIZE: usize = 16;
+// four big-endian 16 bit channels
+const PIXEL_SIZE: usize = 8;
+
+// 8 bit channels are widened so that 255 becomes 65535
+fn encode_pixels(width: u32, height: u32, pixels: &[image::RGBA]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(HEADER_SIZE + pixels.len() * PIXEL_SIZE);
+    data.extend(&FARBFELD_SIGNATURE);
+    data.extend(&width.to_be_bytes());
+    data.extend(&height.to_be_bytes());
+    for pixel in pixels {
+        for channel in [pixel.red, pixel.green, pixel.blue, pixel.alpha] {
+            data.extend(&(channel as u16 * 257).to_be_bytes());
+        }
+    }
+    data
+}
+
+pub struct FarbfeldImage {
+    pub image: image::ColorImage,
+}
+impl FarbfeldImage {
+    pub fn encode(&self) -> Vec<u8> {
+        let pixels: Vec<image::RGBA> = self.image.canvas.pixels.iter().map(|index| {
+            let color = self.image.palette.color(*index).unwrap();
+            image::RGBA { red: color.red, green: color.green, blue: color.blue, alpha: 255 }
+        }).collect();
+        encode_pixels(self.image.canvas.width, self.image.canvas.height, &pixels)
+    }
+}
+impl Image for FarbfeldImage {
+    fn write(self, path: &str) -> Result<fs::File, std::io::Error> {
+        let mut file_image = fs::File::create(path)?;
+        file_image.write_all(&self.encode())?;
+        Ok(file_image)
+    }
+}
+
+pub struct FarbfeldRgbaImage {
+    pub canvas: image::Canvas<image::RGBA>,
+}
+impl FarbfeldRgbaImage {
+    pub fn encode(&self) -> Vec<u8> {
+        encode_pixels(self.canvas.width, self.canvas.height, &self.canvas.pixels)
+    }
+}
+impl Image for FarbfeldRgbaImage {
+    fn write(self, path: &str) -> Result<fs::File, std::io::Error> {
+        let mut file_image = fs::File::create(path)?;
+        file_image.write_all(&self.encode())?;
+        Ok(file_image)
+    }
+}
+
+pub fn read(path: &str) -> Result<image::ColorImage, std::io::Error> {
+    decode(&fs::read(path)?)
+}
+
+/// Decodes a farbfeld image, discarding alpha.
+pub fn decode(bytes: &[u8]) -> Result<image::ColorImage, std::io::Error> {
+    decode_with_limits(bytes, &Limits::default())
+}
+
+pub fn decode_with_limits(bytes: &[u8], limits: &Limits) -> Result<image::ColorImage, std::io::Error> {
+    let canvas = decode_rgba_with_limits(bytes, limits)?;
+    let pixels: Vec<image::RGB> = canvas.pixels.iter().map(|pixel| {
+        image::RGB { red: pixel.red, green: pixel.green, blue: pixel.blue }
+    }).collect();
+    Ok(image_from_rgb(canvas.width, canvas.height, &pixels))
+}
+
+/// Decodes a farbfeld image, keeping the top 8 bits of each channel.
+pub fn decode_rgba(bytes: &[u8]) -> Result<image::Canvas<image::RGBA>, std::io::Error> {
+    decode_rgba_with_limits(bytes, &Limits::default())
+}
+
+/// Decodes a farbfeld, failing with `LimitExceeded` if its dimensions go past the limits.
+pub fn decode_rgba_with_limits(bytes: &[u8], limits: &Limits) -> Result<image::Canvas<image::RGBA>, std::io::Error> {
+    if read_bytes(bytes, 0, FARBFELD_SIGNATURE.len())? != FARBFELD_SIGNATURE {
+        return Err(invalid_data("missing farbfeld signature"));
+    }
+    let width = read_be_u32(bytes, 8)?;
+    let height = read_be_u32(bytes, 12)?;
+    limits.check_dimensions(width, height)?;
+    let data_length = (width as usize).checked_mul(height as usize).and_then(|count| count.checked_mul(PIXEL_SIZE));
+    let data = match data_length {
+        Some(length) => read_bytes(bytes, HEADER_SIZE, length)?,
+        None => return Err(invalid_data("farbfeld dimensions are too large")),
+    };
+    let pixels = data.chunks(PIXEL_SIZE).map(|pixel| {
+        // the high byte of each big-endian channel
+        image::RGBA { red: pixel[0], green: pixel[2], blue: pixel[4], alpha: pixel[6] }
+    }).collect();
+    Ok(image::Canvas { width, height, pixels })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn farbfeld_widens_channels_to_sixteen_bits() {
+        let mut canvas = image::Canvas::new(2, 1);
+        canvas.set_pixel(0, 0, image::RGBA { red: 255, green: 128, blue: 1, alpha: 0 });
+        canvas.set_pixel(1, 0, image::RGBA { red: 0, green: 0, blue: 0, alpha: 255 });
+        let data = FarbfeldRgbaImage { canvas }.encode();
+        let mut expected = b"farbfeld".to_vec();
+        expected.extend(&[0, 0, 0, 2, 0, 0, 0, 1]);
+        expected.extend(&[0xff, 0xff, 0x80, 0x80, 0x01, 0x01, 0, 0]);
+        expected.extend(&[0, 0, 0, 0, 0, 0, 0xff, 0xff]);
+        assert_eq!(data, expected);
+        let decoded = decode_rgba(&data).unwrap();
+        assert_eq!(decoded.pixel(0, 0), &image::RGBA { red: 255, green: 128, blue: 1, alpha: 0 });
+    }
+    #[test]
+    fn farbfeld_round_trips_color_images() {
+        let mut image = image::ColorImage::new(7, 5, image::RGB { red: 0, green: 0, blue: 0 });
+        for x in 0..7 {
+            for y in 0..5 {
+                image.set_pixel(x, y, image::RGB { red: (x * 30) as u8, green: (y * 50) as u8, blue: 9 });
+            }
+        }
+        let farbfeld = FarbfeldImage { image };
+        let decoded = decode(&farbfeld.encode()).unwrap();
+        for x in 0..7 {
+            for y in 0..5 {
+                assert_eq!(decoded.pixel(x, y), farbfeld.image.pixel(x, y));
+            }
+        }
+    }
+    #[test]
+    fn decode_rejects_truncated_pixels() {
+        let mut data = FarbfeldImage { image: image::ColorImage::new(3, 3, image::RGB::default()) }.encode();
+        data.pop();
+        assert_eq!(decode(&data).err().unwrap().kind(), std::io::ErrorKind::InvalidData);
+    }
+}
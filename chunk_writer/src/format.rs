@@ -0,0 +1,276 @@
+use super::*;
+use image;
+
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum Format {
+    Png,
+    Gif,
+    Bmp,
+    Pbm,
+    Pgm,
+    Ppm,
+    Pam,
+    Qoi,
+    Tiff,
+    Tga,
+    Pcx,
+    Ico,
+    Cur,
+    Farbfeld,
+    Xbm,
+    Xpm,
+}
+impl Format {
+    pub fn from_extension(path: &str) -> Option<Self> {
+        let extension = std::path::Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
+        Some(match extension.as_str() {
+            "png" => Format::Png,
+            "gif" => Format::Gif,
+            "bmp" | "dib" => Format::Bmp,
+            "pbm" => Format::Pbm,
+            "pgm" => Format::Pgm,
+            "ppm" | "pnm" => Format::Ppm,
+            "pam" => Format::Pam,
+            "qoi" => Format::Qoi,
+            "tif" | "tiff" => Format::Tiff,
+            "tga" => Format::Tga,
+            "pcx" => Format::Pcx,
+            "ico" => Format::Ico,
+            "cur" => Format::Cur,
+            "ff" => Format::Farbfeld,
+            "xbm" => Format::Xbm,
+            "xpm" => Format::Xpm,
+            _ => return None,
+        })
+    }
+    /// Recognises a format from the signature at the start of the data.
+    /// Tga only has a signature when it carries the optional footer.
+    pub fn from_magic(bytes: &[u8]) -> Option<Self> {
+        if bytes.starts_with(&png::PNG_SIGNATURE) {
+            return Some(Format::Png);
+        }
+        if bytes.starts_with(&gif::HEADER_SIGNATURE) || bytes.starts_with(&gif::GIF87_SIGNATURE) {
+            return Some(Format::Gif);
+        }
+        if bytes.starts_with(&bmp::BMP_SIGNATURE) {
+            return Some(Format::Bmp);
+        }
+        if bytes.starts_with(&farbfeld::FARBFELD_SIGNATURE) {
+            return Some(Format::Farbfeld);
+        }
+        if bytes.starts_with(xpm::XPM_SIGNATURE) {
+            return Some(Format::Xpm);
+        }
+        if bytes.starts_with(xbm::XBM_SIGNATURE) {
+            return Some(Format::Xbm);
+        }
+        if bytes.starts_with(&qoi::QOI_SIGNATURE) {
+            return Some(Format::Qoi);
+        }
+        if bytes.starts_with(&tiff::LITTLE_ENDIAN_SIGNATURE) || bytes.starts_with(&tiff::BIG_ENDIAN_SIGNATURE) {
+            return Some(Format::Tiff);
+        }
+        if bytes.len() > 2 && bytes[0] == b'P' && bytes[2].is_ascii_whitespace() {
+            match bytes[1] {
+                b'1' | b'4' => return Some(Format::Pbm),
+                b'2' | b'5' => return Some(Format::Pgm),
+                b'3' | b'6' => return Some(Format::Ppm),
+                b'7' => return Some(Format::Pam),
+                _ => (),
+            }
+        }
+        // a zero reserved field, the resource type, then a non-zero image count
+        if bytes.len() >= 6 && bytes[0..2] == [0, 0] && bytes[3] == 0 && bytes[4..6] != [0, 0] {
+            match bytes[2] as u16 {
+                ico::TYPE_ICON => return Some(Format::Ico),
+                ico::TYPE_CURSOR => return Some(Format::Cur),
+                _ => (),
+            }
+        }
+        // the manufacturer byte, a known version and an encoding of 0 or 1
+        if bytes.len() >= 3 && bytes[0] == pcx::MANUFACTURER && [0, 2, 3, 4, 5].contains(&bytes[1]) && bytes[2] <= 1 {
+            return Some(Format::Pcx);
+        }
+        if bytes.ends_with(tga::FOOTER_SIGNATURE) {
+            return Some(Format::Tga);
+        }
+        None
+    }
+}
+
+/// Encodes the image with the usual settings for the format, picking a paletted form when the colours fit.
+pub fn encode(image: &image::ColorImage, format: Format) -> Result<Vec<u8>, std::io::Error> {
+    let paletted = image.palette.size() <= 256;
+    match format {
+        Format::Png => Ok(png::PngImage::new(image).encode()),
+        Format::Gif => gif::GifImage { image: image.clone() }.encode(),
+        Format::Bmp => {
+            let bit_depth = if paletted { bmp::BitDepth::Eight } else { bmp::BitDepth::TwentyFour };
+            bmp::BmpImage { image: image.clone(), bit_depth }.encode()
+        },
+        Format::Pbm => Ok(netpbm::PbmImage { image: image.clone(), encoding: netpbm::Encoding::Binary }.encode()),
+        Format::Pgm => {
+            let mut grey = image::GreyScaleImage::new(image.canvas.width, image.canvas.height);
+            for x in 0..image.canvas.width {
+                for y in 0..image.canvas.height {
+                    grey.set_pixel(x, y, netpbm::luminance(&image.pixel(x, y)) as u8);
+                }
+            }
+            Ok(netpbm::PgmImage { image: grey, encoding: netpbm::Encoding::Binary }.encode())
+        },
+        Format::Ppm => Ok(netpbm::PpmImage { image: image.clone(), encoding: netpbm::Encoding::Binary }.encode()),
+        Format::Pam => {
+            let mut canvas = image::Canvas::new(image.canvas.width, image.canvas.height);
+            for x in 0..image.canvas.width {
+                for y in 0..image.canvas.height {
+                    let color = image.pixel(x, y);
+                    canvas.set_pixel(x, y, image::RGBA { red: color.red, green: color.green, blue: color.blue, alpha: 255 });
+                }
+            }
+            Ok(netpbm::PamImage { canvas }.encode())
+        },
+        Format::Qoi => Ok(qoi::QoiImage { image: image.clone() }.encode()),
+        Format::Tiff => tiff::TiffImage {
+            image: image.clone(),
+            byte_order: tiff::ByteOrder::LittleEndian,
+            compression: tiff::Compression::Lzw,
+            photometric: if paletted { tiff::Photometric::Palette } else { tiff::Photometric::Rgb },
+        }.encode(),
+        Format::Tga => tga::TgaImage {
+            image: image.clone(),
+            color_type: if paletted { tga::ColorType::Paletted } else { tga::ColorType::TrueColor },
+            rle: true,
+            origin: tga::Origin::BottomLeft,
+        }.encode(),
+        Format::Pcx => pcx::PcxImage { image: image.clone() }.encode(),
+        Format::Ico | Format::Cur => ico::IconImage {
+            kind: if format == Format::Ico { ico::IconKind::Icon } else { ico::IconKind::Cursor },
+            entries: vec![ico::IconEntry { image: image.clone(), format: ico::EntryFormat::Bmp(bmp::BitDepth::ThirtyTwo), hotspot_x: 0, hotspot_y: 0 }],
+        }.encode(),
+        Format::Farbfeld => Ok(farbfeld::FarbfeldImage { image: image.clone() }.encode()),
+        // c source needs an identifier, and there is no path to take one from here
+        Format::Xbm => xbm::XbmImage { image: image.clone(), name: String::from("image") }.encode(),
+        Format::Xpm => xpm::XpmImage { image: image.clone(), name: String::from("image") }.encode(),
+    }
+}
+
+/// Writes the image in the format given by the path's extension.
+pub fn save(image: &image::ColorImage, path: &str) -> Result<fs::File, std::io::Error> {
+    let format = match Format::from_extension(path) {
+        Some(format) => format,
+        None => return Err(invalid_input("unrecognised image file extension")),
+    };
+    let data = encode(image, format)?;
+    let mut file_image = fs::File::create(path)?;
+    file_image.write_all(&data)?;
+    Ok(file_image)
+}
+
+/// Decodes data in the given format. Icons and cursors give their largest image.
+pub fn decode_as(bytes: &[u8], format: Format) -> Result<image::ColorImage, std::io::Error> {
+    decode_as_with_limits(bytes, format, &Limits::default())
+}
+
+/// Decodes data in the given format, failing with `LimitExceeded` past any of the limits.
+/// Every image in an icon or cursor is held to them, not only the largest.
+pub fn decode_as_with_limits(bytes: &[u8], format: Format, limits: &Limits) -> Result<image::ColorImage, std::io::Error> {
+    match format {
+        Format::Png => png::decode_with_limits(bytes, limits),
+        Format::Gif => gif::decode_with_limits(bytes, limits),
+        Format::Bmp => bmp::decode_with_limits(bytes, limits),
+        Format::Pbm | Format::Pgm | Format::Ppm | Format::Pam => netpbm::decode_with_limits(bytes, limits),
+        Format::Qoi => qoi::decode_with_limits(bytes, limits),
+        Format::Tiff => tiff::decode_with_limits(bytes, limits),
+        Format::Tga => tga::decode_with_limits(bytes, limits),
+        Format::Pcx => pcx::decode_with_limits(bytes, limits),
+        Format::Ico | Format::Cur => {
+            let entries = ico::decode_with_limits(bytes, limits)?.entries;
+            match entries.into_iter().max_by_key(|entry| entry.image.canvas.width * entry.image.canvas.height) {
+                Some(entry) => Ok(entry.image),
+                None => Err(invalid_data("icon contains no images")),
+            }
+        },
+        Format::Farbfeld => farbfeld::decode_with_limits(bytes, limits),
+        Format::Xbm => xbm::decode_with_limits(bytes, limits),
+        Format::Xpm => xpm::decode_with_limits(bytes, limits),
+    }
+}
+
+/// Decodes data in whichever format its signature shows.
+pub fn decode(bytes: &[u8]) -> Result<image::ColorImage, std::io::Error> {
+    decode_with_limits(bytes, &Limits::default())
+}
+
+/// Decodes data in whichever format its signature shows, failing with `LimitExceeded` past any of the limits.
+/// The dimensions are checked from each header before anything is allocated for the pixels.
+pub fn decode_with_limits(bytes: &[u8], limits: &Limits) -> Result<image::ColorImage, std::io::Error> {
+    match Format::from_magic(bytes) {
+        Some(format) => decode_as_with_limits(bytes, format, limits),
+        None => Err(invalid_data("unrecognised image format")),
+    }
+}
+
+/// Reads an image, going by its signature and falling back to the extension for formats without one.
+pub fn load(path: &str) -> Result<image::ColorImage, std::io::Error> {
+    let bytes = fs::read(path)?;
+    match Format::from_magic(&bytes).or_else(|| Format::from_extension(path)) {
+        Some(format) => decode_as(&bytes, format),
+        None => Err(invalid_data("unrecognised image format")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn test_image() -> image::ColorImage {
+        let mut image = image::ColorImage::new(24, 16, image::RGB { red: 0, green: 0, blue: 0 });
+        for x in 0..24 {
+            for y in 0..16 {
+                let value = if (x / 4 + y / 4) % 2 == 0 { 255 } else { 0 };
+                image.set_pixel(x, y, image::RGB { red: value, green: value, blue: (x * 10) as u8 });
+            }
+        }
+        image
+    }
+    #[test]
+    fn from_extension_ignores_case_and_directories() {
+        assert_eq!(Format::from_extension("out/picture.GIF"), Some(Format::Gif));
+        assert_eq!(Format::from_extension("scan.tif"), Some(Format::Tiff));
+        assert_eq!(Format::from_extension("image.pnm"), Some(Format::Ppm));
+        assert_eq!(Format::from_extension("notes.txt"), None);
+        assert_eq!(Format::from_extension("png"), None);
+    }
+    #[test]
+    fn encoded_formats_are_recognised_and_decoded() {
+        let image = test_image();
+        // pbm, pgm and xbm lose colour, so only their signatures are checked
+        for format in [Format::Png, Format::Gif, Format::Bmp, Format::Ppm, Format::Pam, Format::Qoi, Format::Tiff, Format::Tga, Format::Pcx, Format::Ico, Format::Cur, Format::Farbfeld, Format::Xpm] {
+            let data = encode(&image, format).unwrap();
+            assert_eq!(Format::from_magic(&data), Some(format));
+            let decoded = decode(&data).unwrap();
+            for x in 0..24 {
+                for y in 0..16 {
+                    assert_eq!(decoded.pixel(x, y), image.pixel(x, y), "{:?}", format);
+                }
+            }
+        }
+        assert_eq!(Format::from_magic(&encode(&image, Format::Pbm).unwrap()), Some(Format::Pbm));
+        assert_eq!(Format::from_magic(&encode(&image, Format::Pgm).unwrap()), Some(Format::Pgm));
+        assert_eq!(Format::from_magic(&encode(&image, Format::Xbm).unwrap()), Some(Format::Xbm));
+    }
+    #[test]
+    fn decode_with_limits_names_the_limit() {
+        let image = test_image();
+        let limits = Limits { max_pixels: 24 * 16 - 1, ..Limits::default() };
+        for format in [Format::Png, Format::Gif, Format::Bmp, Format::Ppm, Format::Pam, Format::Qoi, Format::Tiff, Format::Tga, Format::Pcx, Format::Ico, Format::Farbfeld, Format::Xbm, Format::Xpm] {
+            let data = encode(&image, format).unwrap();
+            assert!(decode_with_limits(&data, &Limits::default()).is_ok());
+            let error = decode_with_limits(&data, &limits).err().unwrap();
+            assert_eq!(LimitExceeded::from_error(&error), Some(LimitExceeded::Pixels), "{:?}", format);
+        }
+    }
+    #[test]
+    fn unknown_data_is_rejected() {
+        assert_eq!(decode(b"not an image").err().unwrap().kind(), std::io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,323 @@
+use super::*;
+use image;
+
+const SIGNATURE: &[u8] = b"#?RADIANCE\n";
+const RLE_FORMAT: &str = "FORMAT=32-bit_rle_rgbe";
+// the widths that run length encoded scanlines can describe
+const MIN_RLE_WIDTH: usize = 8;
+const MAX_RLE_WIDTH: usize = 0x7fff;
+const RLE_SCANLINE_MARKER: [u8; 2] = [2, 2];
+// runs shorter than this are cheaper to write as literal bytes
+const MIN_RUN: usize = 4;
+const MAX_RUN: usize = 127;
+const MAX_LITERAL: usize = 128;
+// the old run length encoding repeats the previous pixel when it sees this mantissa
+const OLD_RLE_MARKER: [u8; 3] = [1, 1, 1];
+// exponents are offset by 128, and mantissas are a further 8 bits
+const EXPONENT_BIAS: i32 = 128;
+const MANTISSA_BITS: i32 = 8;
+
+// a shared exponent for the brightest channel, with the channels as 8 bit mantissas
+fn float_to_rgbe(color: &image::RGBF) -> [u8; 4] {
+    let brightest = color.red.max(color.green).max(color.blue) as f64;
+    // anything too dark for the exponent, including negatives and nan, is black
+    if brightest.is_nan() || brightest < 1e-32 {
+        return [0; 4];
+    }
+    let brightest = brightest.min(f32::MAX as f64);
+    let mut exponent = brightest.log2().floor() as i32 + 1;
+    if brightest / 2f64.powi(exponent) >= 1.0 {
+        exponent += 1;
+    }
+    let exponent = exponent.min(127);
+    let scale = 256.0 / 2f64.powi(exponent);
+    let mantissa = |channel: f32| (channel.max(0.0) as f64 * scale).min(255.0) as u8;
+    [mantissa(color.red), mantissa(color.green), mantissa(color.blue), (exponent + EXPONENT_BIAS) as u8]
+}
+
+fn rgbe_to_float(rgbe: &[u8]) -> image::RGBF {
+    if rgbe[3] == 0 {
+        return image::RGBF::default();
+    }
+    let scale = 2f32.powi(rgbe[3] as i32 - (EXPONENT_BIAS + MANTISSA_BITS));
+    image::RGBF {
+        red: rgbe[0] as f32 * scale,
+        green: rgbe[1] as f32 * scale,
+        blue: rgbe[2] as f32 * scale,
+    }
+}
+
+fn encode_rle(channel: &[u8], data: &mut Vec<u8>) {
+    let run_length = |start: usize| {
+        let mut run = 1;
+        while start + run < channel.len() && run < MAX_RUN && channel[start + run] == channel[start] {
+            run += 1;
+        }
+        run
+    };
+    let mut i = 0;
+    while i < channel.len() {
+        let run = run_length(i);
+        if run >= MIN_RUN {
+            data.push(128 + run as u8);
+            data.push(channel[i]);
+            i += run;
+            continue;
+        }
+        // literals continue until a run long enough to be worth encoding
+        let start = i;
+        while i < channel.len() && i - start < MAX_LITERAL && run_length(i) < MIN_RUN {
+            i += 1;
+        }
+        data.push((i - start) as u8);
+        data.extend(&channel[start..i]);
+    }
+}
+
+/// A radiance rgbe image, run length encoded whenever the width allows it.
+pub struct HdrImage {
+    pub canvas: image::Canvas<image::RGBF>,
+}
+impl HdrImage {
+    pub fn encode(&self) -> Vec<u8> {
+        let width = self.canvas.width as usize;
+        let mut data = SIGNATURE.to_vec();
+        data.extend(format!("{}\n\n-Y {} +X {}\n", RLE_FORMAT, self.canvas.height, width).as_bytes());
+        let rle = (MIN_RLE_WIDTH..=MAX_RLE_WIDTH).contains(&width);
+        for row in self.canvas.pixels.chunks(width.max(1)) {
+            let pixels: Vec<[u8; 4]> = row.iter().map(float_to_rgbe).collect();
+            if !rle {
+                data.extend(pixels.concat());
+                continue;
+            }
+            data.extend(&RLE_SCANLINE_MARKER);
+            data.extend(&(width as u16).to_be_bytes());
+            // each channel is encoded separately, with all of the exponents last
+            for channel in 0..4 {
+                let values: Vec<u8> = pixels.iter().map(|pixel| pixel[channel]).collect();
+                encode_rle(&values, &mut data);
+            }
+        }
+        data
+    }
+}
+impl Image for HdrImage {
+    fn write(self, path: &str) -> Result<fs::File, std::io::Error> {
+        let mut file_image = fs::File::create(path)?;
+        file_image.write_all(&self.encode())?;
+        Ok(file_image)
+    }
+}
+
+fn read_line(bytes: &[u8], offset: &mut usize) -> Result<String, std::io::Error> {
+    let length = match bytes[(*offset).min(bytes.len())..].iter().position(|byte| *byte == b'\n') {
+        Some(length) => length,
+        None => return Err(invalid_data("unexpected end of hdr header")),
+    };
+    let line = String::from_utf8_lossy(&bytes[*offset..*offset + length]).into_owned();
+    *offset += length + 1;
+    Ok(line)
+}
+
+fn decode_rle_scanline(bytes: &[u8], offset: &mut usize, width: usize) -> Result<Vec<[u8; 4]>, std::io::Error> {
+    let mut pixels = vec![[0u8; 4]; width];
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let count = read_u8(bytes, *offset)? as usize;
+            *offset += 1;
+            if count > 128 {
+                let count = count - 128;
+                if x + count > width {
+                    return Err(invalid_data("hdr run goes past the end of its scanline"));
+                }
+                let value = read_u8(bytes, *offset)?;
+                *offset += 1;
+                for pixel in &mut pixels[x..x + count] {
+                    pixel[channel] = value;
+                }
+                x += count;
+            }
+            else {
+                if count == 0 || x + count > width {
+                    return Err(invalid_data("hdr literal goes past the end of its scanline"));
+                }
+                for (pixel, value) in pixels[x..x + count].iter_mut().zip(read_bytes(bytes, *offset, count)?) {
+                    pixel[channel] = *value;
+                }
+                *offset += count;
+                x += count;
+            }
+        }
+    }
+    Ok(pixels)
+}
+
+// flat pixels, in which the old run length encoding may repeat the previous pixel
+fn decode_flat_scanline(bytes: &[u8], offset: &mut usize, width: usize) -> Result<Vec<[u8; 4]>, std::io::Error> {
+    let mut pixels: Vec<[u8; 4]> = Vec::with_capacity(width);
+    let mut shift = 0;
+    while pixels.len() < width {
+        let pixel = read_bytes(bytes, *offset, 4)?;
+        *offset += 4;
+        match pixels.last().copied() {
+            Some(previous) if pixel[..3] == OLD_RLE_MARKER => {
+                // consecutive repeat counts are successively higher bytes of the count
+                let count = match (pixel[3] as usize).checked_shl(shift) {
+                    Some(count) => count,
+                    None => return Err(invalid_data("hdr run is longer than a count can hold")),
+                };
+                if count > width - pixels.len() {
+                    return Err(invalid_data("hdr run goes past the end of its scanline"));
+                }
+                pixels.extend(std::iter::repeat_n(previous, count));
+                shift += 8;
+            },
+            _ => {
+                pixels.push([pixel[0], pixel[1], pixel[2], pixel[3]]);
+                shift = 0;
+            },
+        }
+    }
+    Ok(pixels)
+}
+
+pub fn read(path: &str) -> Result<image::Canvas<image::RGBF>, std::io::Error> {
+    decode(&fs::read(path)?)
+}
+
+/// Decodes a radiance rgbe image, dividing out any exposure given in the header.
+pub fn decode(bytes: &[u8]) -> Result<image::Canvas<image::RGBF>, std::io::Error> {
+    if !bytes.starts_with(b"#?") {
+        return Err(invalid_data("missing hdr signature"));
+    }
+    let mut offset = 0;
+    let mut exposure = 1.0;
+    loop {
+        let line = read_line(bytes, &mut offset)?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format != &RLE_FORMAT["FORMAT=".len()..] {
+                return Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "only rgbe hdr images are supported"));
+            }
+        }
+        if let Some(value) = line.strip_prefix("EXPOSURE=") {
+            match value.trim().parse::<f32>() {
+                Ok(value) if value > 0.0 => exposure *= value,
+                _ => return Err(invalid_data("invalid hdr exposure")),
+            }
+        }
+    }
+
+    let resolution = read_line(bytes, &mut offset)?;
+    let words: Vec<&str> = resolution.split_whitespace().collect();
+    let (top_down, height, width) = match words.as_slice() {
+        [y, height, "+X", width] if *y == "-Y" || *y == "+Y" => match (height.parse::<usize>(), width.parse::<usize>()) {
+            (Ok(height), Ok(width)) => (*y == "-Y", height, width),
+            _ => return Err(invalid_data("invalid hdr resolution")),
+        },
+        _ => return Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "only hdr images stored by rows from the left are supported")),
+    };
+    // rle scanlines cannot hold more than MAX_RUN pixels for each byte, and old style runs in flat
+    // scanlines are held to it here, since they could otherwise describe a scanline of any width
+    if width.checked_mul(height).is_none_or(|count| count / MAX_RUN > bytes.len()) {
+        return Err(invalid_data("hdr dimensions are larger than the data could describe"));
+    }
+
+    let mut rows = Vec::with_capacity(height);
+    for _ in 0..height {
+        let marker = read_bytes(bytes, offset, 4).ok();
+        let rle = (MIN_RLE_WIDTH..=MAX_RLE_WIDTH).contains(&width)
+            && marker.is_some_and(|marker| marker[..2] == RLE_SCANLINE_MARKER && marker[2] & 0x80 == 0);
+        let pixels = if rle {
+            let marker = marker.unwrap();
+            if ((marker[2] as usize) << 8 | marker[3] as usize) != width {
+                return Err(invalid_data("hdr scanline width does not match the image"));
+            }
+            offset += 4;
+            decode_rle_scanline(bytes, &mut offset, width)?
+        }
+        else {
+            decode_flat_scanline(bytes, &mut offset, width)?
+        };
+        rows.push(pixels);
+    }
+    if !top_down {
+        rows.reverse();
+    }
+    let pixels = rows.concat().iter().map(|rgbe| {
+        let color = rgbe_to_float(rgbe);
+        image::RGBF { red: color.red / exposure, green: color.green / exposure, blue: color.blue / exposure }
+    }).collect();
+    Ok(image::Canvas { width: width as u32, height: height as u32, pixels })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn assert_close(a: &image::RGBF, b: &image::RGBF) {
+        // 8 bit mantissas give a little under 1% precision relative to the brightest channel
+        let tolerance = a.red.max(a.green).max(a.blue) * 0.01;
+        for (a, b) in [(a.red, b.red), (a.green, b.green), (a.blue, b.blue)] {
+            assert!((a - b).abs() <= tolerance, "{} {}", a, b);
+        }
+    }
+    #[test]
+    fn float_to_rgbe_shares_the_brightest_exponent() {
+        assert_eq!(float_to_rgbe(&image::RGBF { red: 1.0, green: 0.5, blue: 0.0 }), [128, 64, 0, 129]);
+        assert_eq!(float_to_rgbe(&image::RGBF { red: 0.0, green: 0.0, blue: 0.0 }), [0, 0, 0, 0]);
+        assert_eq!(float_to_rgbe(&image::RGBF { red: 1000.0, green: -3.0, blue: 0.0 }), [250, 0, 0, 138]);
+    }
+    #[test]
+    fn encode_rle_mixes_runs_and_literals() {
+        let mut data = Vec::new();
+        encode_rle(&[1, 2, 3, 3, 3, 3, 3, 4, 4, 5], &mut data);
+        assert_eq!(data, vec![2, 1, 2, 128 + 5, 3, 3, 4, 4, 5]);
+    }
+    #[test]
+    fn hdr_round_trips_rle_and_flat_scanlines() {
+        for width in [5, 300] {
+            let mut canvas = image::Canvas::new(width, 4);
+            for x in 0..width {
+                for y in 0..4 {
+                    let value = if x < 100 { 0.25 } else { x as f32 * (y + 1) as f32 * 0.37 };
+                    canvas.set_pixel(x, y, image::RGBF { red: value, green: value / 3.0, blue: 40000.0 / (x + 1) as f32 });
+                }
+            }
+            let hdr = HdrImage { canvas };
+            let data = hdr.encode();
+            assert!(data.starts_with(b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 4 +X "));
+            let decoded = decode(&data).unwrap();
+            assert_eq!((decoded.width, decoded.height), (width, 4));
+            for (a, b) in hdr.canvas.pixels.iter().zip(decoded.pixels.iter()) {
+                assert_close(a, b);
+            }
+        }
+    }
+    #[test]
+    fn decode_reads_old_runs_bottom_up_rows_and_exposure() {
+        let mut data = b"#?RGBE\nEXPOSURE=2\n\n+Y 2 +X 3\n".to_vec();
+        // bottom row: one pixel repeated twice by an old style run
+        data.extend(&[128, 128, 128, 129, 1, 1, 1, 2]);
+        data.extend(&[128, 0, 0, 130, 0, 0, 0, 0, 0, 128, 0, 130]);
+        let canvas = decode(&data).unwrap();
+        assert_close(canvas.pixel(0, 0), &image::RGBF { red: 1.0, green: 0.0, blue: 0.0 });
+        assert_close(canvas.pixel(2, 0), &image::RGBF { red: 0.0, green: 1.0, blue: 0.0 });
+        assert_close(canvas.pixel(2, 1), &image::RGBF { red: 0.5, green: 0.5, blue: 0.5 });
+    }
+    #[test]
+    fn decode_rejects_old_runs_that_overflow() {
+        let mut data = b"#?RGBE\n\n-Y 1 +X 4\n".to_vec();
+        data.extend(&[128, 128, 128, 129]);
+        for _ in 0..20 {
+            data.extend(&[1, 1, 1, 0]);
+        }
+        data.extend(&[0; 2000]);
+        assert_eq!(decode(&data).err().unwrap().to_string(), "hdr run is longer than a count can hold");
+        let mut data = b"#?RGBE\n\n-Y 1 +X 4\n".to_vec();
+        data.extend(&[128, 128, 128, 129, 1, 1, 1, 0, 1, 1, 1, 255]);
+        assert_eq!(decode(&data).err().unwrap().to_string(), "hdr run goes past the end of its scanline");
+    }
+}
@@ -0,0 +1,220 @@
+use super::*;
+use image;
+
+const DIRECTORY_SIZE: usize = 6;
+const ENTRY_SIZE: usize = 16;
+pub(crate) const TYPE_ICON: u16 = 1;
+pub(crate) const TYPE_CURSOR: u16 = 2;
+// width and height are stored in a byte, with 0 meaning 256
+const MAX_DIMENSION: u32 = 256;
+
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum IconKind {
+    Icon,
+    Cursor,
+}
+
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum EntryFormat {
+    Bmp(bmp::BitDepth),
+    Png,
+}
+
+pub struct IconEntry {
+    pub image: image::ColorImage,
+    pub format: EntryFormat,
+    /// the click position of a cursor, ignored for icons
+    pub hotspot_x: u16,
+    pub hotspot_y: u16,
+}
+
+/// An icon or cursor holding several sizes of the same picture.
+pub struct IconImage {
+    pub kind: IconKind,
+    pub entries: Vec<IconEntry>,
+}
+impl IconImage {
+    pub fn encode(&self) -> Result<Vec<u8>, std::io::Error> {
+        let mut directory = Vec::new();
+        directory.extend(&0u16.to_le_bytes());
+        directory.extend(&match self.kind {
+            IconKind::Icon => TYPE_ICON,
+            IconKind::Cursor => TYPE_CURSOR,
+        }.to_le_bytes());
+        directory.extend(&(self.entries.len() as u16).to_le_bytes());
+
+        let mut image_data = Vec::new();
+        let mut offset = DIRECTORY_SIZE + self.entries.len() * ENTRY_SIZE;
+        for entry in self.entries.iter() {
+            let width = entry.image.canvas.width;
+            let height = entry.image.canvas.height;
+            if width == 0 || height == 0 || width > MAX_DIMENSION || height > MAX_DIMENSION {
+                return Err(invalid_input("icon images must be between 1 and 256 pixels across"));
+            }
+            let (data, bits) = match entry.format {
+                EntryFormat::Bmp(bit_depth) => (bmp::encode_dib(&entry.image, &bit_depth, true)?, bit_depth.bits()),
+                EntryFormat::Png => (png::PngImage::new(&entry.image).encode(), 32),
+            };
+            let color_count = if bits < 8 { 1 << bits } else { 0 };
+            directory.push(width as u8);
+            directory.push(height as u8);
+            directory.push(color_count);
+            // reserved
+            directory.push(0);
+            match self.kind {
+                IconKind::Icon => {
+                    // colour planes
+                    directory.extend(&1u16.to_le_bytes());
+                    directory.extend(&bits.to_le_bytes());
+                },
+                IconKind::Cursor => {
+                    directory.extend(&entry.hotspot_x.to_le_bytes());
+                    directory.extend(&entry.hotspot_y.to_le_bytes());
+                },
+            }
+            directory.extend(&(data.len() as u32).to_le_bytes());
+            directory.extend(&(offset as u32).to_le_bytes());
+            offset += data.len();
+            image_data.extend(data);
+        }
+        directory.extend(image_data);
+        Ok(directory)
+    }
+}
+impl Image for IconImage {
+    fn write(self, path: &str) -> Result<fs::File, std::io::Error> {
+        let data = self.encode()?;
+        let mut file_image = fs::File::create(path)?;
+        file_image.write_all(&data)?;
+        Ok(file_image)
+    }
+}
+
+pub fn read(path: &str) -> Result<IconImage, std::io::Error> {
+    decode(&fs::read(path)?)
+}
+
+pub fn decode(bytes: &[u8]) -> Result<IconImage, std::io::Error> {
+    decode_with_limits(bytes, &Limits::default())
+}
+
+/// Decodes every image in an icon or cursor, each of which has to keep within the limits.
+/// The directory entries count as chunks.
+pub fn decode_with_limits(bytes: &[u8], limits: &Limits) -> Result<IconImage, std::io::Error> {
+    if read_le_u16(bytes, 0)? != 0 {
+        return Err(invalid_data("icon directory reserved field is not zero"));
+    }
+    let kind = match read_le_u16(bytes, 2)? {
+        TYPE_ICON => IconKind::Icon,
+        TYPE_CURSOR => IconKind::Cursor,
+        _ => return Err(invalid_data("unknown icon resource type")),
+    };
+    let count = read_le_u16(bytes, 4)? as usize;
+    limits.check_chunks(count)?;
+    let mut entries = Vec::new();
+    for i in 0..count {
+        let entry_offset = DIRECTORY_SIZE + i * ENTRY_SIZE;
+        let (hotspot_x, hotspot_y) = match kind {
+            IconKind::Icon => (0, 0),
+            IconKind::Cursor => (read_le_u16(bytes, entry_offset + 4)?, read_le_u16(bytes, entry_offset + 6)?),
+        };
+        let size = read_le_u32(bytes, entry_offset + 8)? as usize;
+        let offset = read_le_u32(bytes, entry_offset + 12)? as usize;
+        let data = read_bytes(bytes, offset, size)?;
+        if data.starts_with(&png::PNG_SIGNATURE) {
+            let image = png::decode_with_limits(data, limits)?;
+            entries.push(IconEntry { image, format: EntryFormat::Png, hotspot_x, hotspot_y });
+            continue;
+        }
+        // the bit depth in the directory is often missing, so the one in the image is used instead
+        let bit_depth = match bmp::BitDepth::from_bits(read_le_u16(data, 14)?) {
+            Some(bit_depth) => bit_depth,
+            None => return Err(invalid_data("unsupported icon bit depth")),
+        };
+        let image = bmp::decode_dib(data, None, true, limits)?;
+        let format = EntryFormat::Bmp(bit_depth);
+        entries.push(IconEntry { image, format, hotspot_x, hotspot_y });
+    }
+    Ok(IconImage { kind, entries })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn test_image(size: u32) -> image::ColorImage {
+        let mut image = image::ColorImage::new(size, size, image::RGB { red: 0, green: 0, blue: 0 });
+        for x in 0..size {
+            for y in 0..size {
+                let value = if x > y { 200 } else { (x % 3) as u8 * 40 };
+                image.set_pixel(x, y, image::RGB { red: value, green: 0, blue: 255 - value });
+            }
+        }
+        image
+    }
+    fn assert_same_pixels(a: &image::ColorImage, b: &image::ColorImage) {
+        assert_eq!(a.canvas.width, b.canvas.width);
+        assert_eq!(a.canvas.height, b.canvas.height);
+        for x in 0..a.canvas.width {
+            for y in 0..a.canvas.height {
+                assert_eq!(a.pixel(x, y), b.pixel(x, y));
+            }
+        }
+    }
+    #[test]
+    fn icon_round_trips_multiple_sizes() {
+        let icon = IconImage {
+            kind: IconKind::Icon,
+            entries: vec![
+                IconEntry { image: test_image(16), format: EntryFormat::Bmp(bmp::BitDepth::Four), hotspot_x: 0, hotspot_y: 0 },
+                IconEntry { image: test_image(32), format: EntryFormat::Bmp(bmp::BitDepth::Eight), hotspot_x: 0, hotspot_y: 0 },
+                IconEntry { image: test_image(256), format: EntryFormat::Bmp(bmp::BitDepth::ThirtyTwo), hotspot_x: 0, hotspot_y: 0 },
+                IconEntry { image: test_image(64), format: EntryFormat::Png, hotspot_x: 0, hotspot_y: 0 },
+            ],
+        };
+        let data = icon.encode().unwrap();
+        // 256 is stored as 0
+        assert_eq!(data[DIRECTORY_SIZE + 2 * ENTRY_SIZE], 0);
+        let decoded = decode(&data).unwrap();
+        assert_eq!(decoded.kind, IconKind::Icon);
+        assert_eq!(decoded.entries.len(), 4);
+        for (original, entry) in icon.entries.iter().zip(decoded.entries.iter()) {
+            assert_eq!(original.format, entry.format);
+            assert_same_pixels(&original.image, &entry.image);
+        }
+    }
+    #[test]
+    fn cursor_round_trips_hotspot() {
+        let cursor = IconImage {
+            kind: IconKind::Cursor,
+            entries: vec![IconEntry { image: test_image(32), format: EntryFormat::Bmp(bmp::BitDepth::TwentyFour), hotspot_x: 5, hotspot_y: 9 }],
+        };
+        let decoded = decode(&cursor.encode().unwrap()).unwrap();
+        assert_eq!(decoded.kind, IconKind::Cursor);
+        assert_eq!((decoded.entries[0].hotspot_x, decoded.entries[0].hotspot_y), (5, 9));
+        assert_same_pixels(&cursor.entries[0].image, &decoded.entries[0].image);
+    }
+    #[test]
+    fn icon_rejects_images_larger_than_256() {
+        let icon = IconImage {
+            kind: IconKind::Icon,
+            entries: vec![IconEntry { image: test_image(257), format: EntryFormat::Bmp(bmp::BitDepth::TwentyFour), hotspot_x: 0, hotspot_y: 0 }],
+        };
+        assert_eq!(icon.encode().unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+    }
+    #[test]
+    fn decode_with_limits_checks_every_entry() {
+        let icon = IconImage {
+            kind: IconKind::Icon,
+            entries: vec![
+                IconEntry { image: test_image(16), format: EntryFormat::Bmp(bmp::BitDepth::Eight), hotspot_x: 0, hotspot_y: 0 },
+                IconEntry { image: test_image(64), format: EntryFormat::Png, hotspot_x: 0, hotspot_y: 0 },
+            ],
+        };
+        let data = icon.encode().unwrap();
+        let exceeded = |limits: Limits| LimitExceeded::from_error(&decode_with_limits(&data, &limits).err().unwrap());
+        assert_eq!(exceeded(Limits { max_chunks: 1, ..Limits::default() }), Some(LimitExceeded::Chunks));
+        assert_eq!(exceeded(Limits { max_pixels: 64 * 64 - 1, ..Limits::default() }), Some(LimitExceeded::Pixels));
+        assert_eq!(exceeded(Limits { max_output: 100, ..Limits::default() }), Some(LimitExceeded::Output));
+        assert_eq!(decode_with_limits(&data, &Limits { max_pixels: 64 * 64, ..Limits::default() }).unwrap().entries.len(), 2);
+    }
+}
@@ -1,6 +1,25 @@
 use std::fs;
 use std::io::Write;
 
+pub mod bmp;
+pub mod netpbm;
+pub mod qoi;
+pub mod tiff;
+pub mod tga;
+pub mod pcx;
+pub mod ico;
+pub mod farbfeld;
+pub mod y4m;
+pub mod xbm;
+pub mod xpm;
+pub mod hdr;
+pub mod format;
+pub mod terminal;
+mod limits;
+
+pub use format::{Format, save, load};
+pub use limits::{Limits, LimitExceeded};
+use limits::limit_exceeded;
 
 pub trait Image {
     fn write(self, path: &str) -> Result<fs::File, std::io::Error>;
@@ -25,14 +44,86 @@ fn bytes_to_int(val: [u8; 4]) -> u32 {
     (val[2] as u32) <<  8 |
     (val[3] as u32)
 }
+
+fn invalid_data(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+fn invalid_input(message: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, message)
+}
+
+fn read_bytes(bytes: &[u8], offset: usize, length: usize) -> Result<&[u8], std::io::Error> {
+    match bytes.get(offset..offset + length) {
+        Some(slice) => Ok(slice),
+        None => Err(invalid_data("unexpected end of image data")),
+    }
+}
+
+fn read_u8(bytes: &[u8], offset: usize) -> Result<u8, std::io::Error> {
+    Ok(read_bytes(bytes, offset, 1)?[0])
+}
+
+fn read_le_u16(bytes: &[u8], offset: usize) -> Result<u16, std::io::Error> {
+    let slice = read_bytes(bytes, offset, 2)?;
+    Ok(slice[0] as u16 | (slice[1] as u16) << 8)
+}
+
+fn read_le_u32(bytes: &[u8], offset: usize) -> Result<u32, std::io::Error> {
+    let slice = read_bytes(bytes, offset, 4)?;
+    Ok(slice[0] as u32 | (slice[1] as u32) << 8 | (slice[2] as u32) << 16 | (slice[3] as u32) << 24)
+}
+
+fn read_be_u32(bytes: &[u8], offset: usize) -> Result<u32, std::io::Error> {
+    let slice = read_bytes(bytes, offset, 4)?;
+    Ok(bytes_to_int([slice[0], slice[1], slice[2], slice[3]]))
+}
+
+fn palette_colors(palette: &image::Palette) -> Vec<image::RGB> {
+    (0..palette.size()).map(|i| *palette.color(i).unwrap()).collect()
+}
+
+// pixels are given row by row from the top left
+fn image_from_rgb(width: u32, height: u32, pixels: &[image::RGB]) -> image::ColorImage {
+    let mut image = image::ColorImage::new(width, height, pixels.first().copied().unwrap_or_default());
+    for y in 0..height {
+        for x in 0..width {
+            image.set_pixel(x, y, pixels[(y * width + x) as usize]);
+        }
+    }
+    image
+}
+
+fn image_from_indices(width: u32, height: u32, colors: &[image::RGB], indices: Vec<usize>) -> Result<image::ColorImage, std::io::Error> {
+    if indices.iter().any(|index| *index >= colors.len()) {
+        return Err(invalid_data("pixel refers to a colour outside of the palette"));
+    }
+    Ok(image::ColorImage {
+        palette: image::Palette::from_colors(colors),
+        canvas: image::Canvas { width, height, pixels: indices },
+    })
+}
     
-mod png {
+pub mod png {
     use super::*;
-    const PNG_SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+    pub(crate) const PNG_SIGNATURE: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
     const HEADER_SIGNATURE: [u8; 4] = [0x49, 0x48, 0x44, 0x52];
     const PALETTE_SIGNATURE: [u8; 4] = [0x50, 0x4c, 0x54, 0x45];
     const DATA_SIGNATURE: [u8; 4] = [0x49, 0x44, 0x41, 0x54];
     const END_SIGNATURE: [u8; 4] = [0x49, 0x45, 0x4e, 0x44];
+    const TRANSPARENCY_SIGNATURE: [u8; 4] = [0x74, 0x52, 0x4e, 0x53];
+    // chunks that decoders may skip have a lower case first letter
+    const ANCILLARY_FLAG: u8 = 0x20;
+    // the zlib stream is split across data chunks of this size
+    const DATA_CHUNK_SIZE: usize = 8192;
+    const HEADER_LENGTH: usize = 13;
+    const FILTER_NONE: u8 = 0;
+    const FILTER_SUB: u8 = 1;
+    const FILTER_UP: u8 = 2;
+    const FILTER_AVERAGE: u8 = 3;
+    const FILTER_PAETH: u8 = 4;
+    // the first pixel and the spacing between pixels in each adam7 pass, as (x, y, dx, dy)
+    const ADAM7_PASSES: [(u32, u32, u32, u32); 7] = [(0, 0, 8, 8), (4, 0, 8, 8), (0, 4, 4, 8), (2, 0, 4, 4), (0, 2, 2, 4), (1, 0, 2, 2), (0, 1, 1, 2)];
     fn get_chunk_bytes(signature: &[u8; 4], data: &Vec<u8>) -> Vec<u8> {
         let mut chunk_data: Vec<u8> = Vec::new();
         //Length
@@ -43,38 +134,81 @@ mod png {
         chunk_data.extend(data);
         //CRC
         let crc = crc::Crc::new();
-        let checksum = crc.calculate(&Vec::from(chunk_data.split_at(4).1));
+        let checksum = crc.calculate(chunk_data.split_at(4).1);
         chunk_data.extend(&int_to_bytes(checksum));
         chunk_data
     }
 
-    struct PngImage {
+    pub struct PngImage {
         header: HeaderChunk,
         palette: Option<PaletteChunk>,
         data: Vec<DataChunk>,
         end: EndChunk,
     }
-    impl Image for PngImage {
-        fn write(self, path: &str) -> Result<fs::File, std::io::Error> {
-            let file_image = match fs::File::create(path) {
-                Ok(file_image) => file_image,
-                Err(e) => return Err(e),
+    impl PngImage {
+        /// Builds an 8 bit image, paletted when there are few enough colours and true colour otherwise.
+        pub fn new(image: &image::ColorImage) -> Self {
+            let width = image.canvas.width;
+            let height = image.canvas.height;
+            let paletted = image.palette.size() <= 256;
+            let palette = if paletted {
+                let entries = palette_colors(&image.palette).iter().map(|color| {
+                    PaletteValue { red: color.red, green: color.green, blue: color.blue }
+                }).collect();
+                Some(PaletteChunk { entries })
+            }
+            else {
+                None
             };
+            let scanlines = (0..height).map(|y| {
+                let mut samples = Vec::new();
+                for x in 0..width {
+                    if paletted {
+                        samples.push(*image.canvas.pixel(x, y) as u8);
+                    }
+                    else {
+                        let color = image.pixel(x, y);
+                        samples.extend(&[color.red, color.green, color.blue]);
+                    }
+                }
+                Scanline { samples }
+            }).collect();
+            PngImage {
+                header: HeaderChunk {
+                    width,
+                    height,
+                    bit_depth: 8,
+                    color_type: if paletted { ColorType::Palette } else { ColorType::TrueColor },
+                    interlace_method: InterlaceType::None,
+                },
+                palette,
+                data: DataChunk::from_scanlines(scanlines),
+                end: EndChunk {},
+            }
+        }
+        pub fn encode(self) -> Vec<u8> {
             let mut bytes: Vec<u8> = Vec::new();
             bytes.extend(&PNG_SIGNATURE);
             self.header.append(&mut bytes);
-            match self.palette {
-                Some(palette) => palette.append(&mut bytes),
-                None => (),
+            if let Some(palette) = self.palette {
+                palette.append(&mut bytes);
             }
             for data_chunk in self.data {
                 data_chunk.append(&mut bytes);
             }
             self.end.append(&mut bytes);
+            bytes
+        }
+    }
+    impl Image for PngImage {
+        fn write(self, path: &str) -> Result<fs::File, std::io::Error> {
+            let mut file_image = fs::File::create(path)?;
+            file_image.write_all(&self.encode())?;
             Ok(file_image)
         }
     }
 
+    #[derive(Clone,Copy,PartialEq,Debug)]
     enum ColorType {
         GrayScale,
         TrueColor,
@@ -82,7 +216,35 @@ mod png {
         GrayScaleAlpha,
         TrueColorAlpha,
     }
+    impl ColorType {
+        fn from_byte(value: u8) -> Option<Self> {
+            match value {
+                0 => Some(ColorType::GrayScale),
+                2 => Some(ColorType::TrueColor),
+                3 => Some(ColorType::Palette),
+                4 => Some(ColorType::GrayScaleAlpha),
+                6 => Some(ColorType::TrueColorAlpha),
+                _ => None,
+            }
+        }
+        fn channels(&self) -> usize {
+            match self {
+                ColorType::GrayScale | ColorType::Palette => 1,
+                ColorType::GrayScaleAlpha => 2,
+                ColorType::TrueColor => 3,
+                ColorType::TrueColorAlpha => 4,
+            }
+        }
+        fn allows_bit_depth(&self, bit_depth: u8) -> bool {
+            match self {
+                ColorType::GrayScale => [1, 2, 4, 8, 16].contains(&bit_depth),
+                ColorType::Palette => [1, 2, 4, 8].contains(&bit_depth),
+                _ => [8, 16].contains(&bit_depth),
+            }
+        }
+    }
     
+    #[derive(Clone,Copy,PartialEq,Debug)]
     enum InterlaceType {
         None,
         Adam7,
@@ -91,6 +253,7 @@ mod png {
     struct HeaderChunk {
         width: u32,
         height: u32,
+        bit_depth: u8,
         color_type: ColorType,
         interlace_method: InterlaceType,
     }
@@ -99,8 +262,7 @@ mod png {
             let mut data = Vec::new();
             data.extend(&int_to_bytes(self.width));
             data.extend(&int_to_bytes(self.height));
-            // bit depth will be fixed to 8 for now
-            data.push(8);
+            data.push(self.bit_depth);
             data.push(match self.color_type {
                 ColorType::GrayScale => 0,
                 ColorType::TrueColor => 2,
@@ -145,16 +307,286 @@ mod png {
         samples: Vec<u8>,
     }
     
+    // a piece of the zlib stream, which is shared between all of the data chunks
     struct DataChunk {
-        scanlines: Vec<Scanline>,
+        data: Vec<u8>,
+    }
+    impl DataChunk {
+        fn from_scanlines(scanlines: Vec<Scanline>) -> Vec<Self> {
+            let mut encoder = zlib::ZlibEncoder::new(DataChunkWriter { chunks: Vec::new() });
+            for scanline in scanlines {
+                encoder.write_all(&[FILTER_NONE]).unwrap();
+                encoder.write_all(&scanline.samples).unwrap();
+            }
+            // the writer only fills vectors, so it cannot fail
+            encoder.finish().unwrap().chunks
+        }
+    }
+    // splits the zlib stream into data chunks as the encoder produces it
+    struct DataChunkWriter {
+        chunks: Vec<DataChunk>,
+    }
+    impl Write for DataChunkWriter {
+        fn write(&mut self, bytes: &[u8]) -> std::io::Result<usize> {
+            let last = match self.chunks.last_mut() {
+                Some(chunk) if chunk.data.len() < DATA_CHUNK_SIZE => chunk,
+                _ => {
+                    self.chunks.push(DataChunk { data: Vec::new() });
+                    self.chunks.last_mut().unwrap()
+                },
+            };
+            let length = bytes.len().min(DATA_CHUNK_SIZE - last.data.len());
+            last.data.extend(&bytes[..length]);
+            Ok(length)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
     }
     impl Chunk for DataChunk {
-        fn append(self, bytes: &mut Vec<u8>) {}
+        fn append(self, bytes: &mut Vec<u8>) {
+            bytes.extend(get_chunk_bytes(&DATA_SIGNATURE, &self.data));
+        }
     }
     
     struct EndChunk {}
     impl Chunk for EndChunk {
-        fn append(self, bytes: &mut Vec<u8>) {}
+        fn append(self, bytes: &mut Vec<u8>) {
+            bytes.extend(get_chunk_bytes(&END_SIGNATURE, &Vec::new()));
+        }
+    }
+
+    fn read_header(data: &[u8]) -> Result<HeaderChunk, std::io::Error> {
+        if data.len() != HEADER_LENGTH {
+            return Err(invalid_data("png header chunk has the wrong length"));
+        }
+        let width = read_be_u32(data, 0)?;
+        let height = read_be_u32(data, 4)?;
+        if width == 0 || height == 0 {
+            return Err(invalid_data("png dimensions must not be zero"));
+        }
+        let bit_depth = data[8];
+        let color_type = match ColorType::from_byte(data[9]) {
+            Some(color_type) => color_type,
+            None => return Err(invalid_data("unknown png colour type")),
+        };
+        if !color_type.allows_bit_depth(bit_depth) {
+            return Err(invalid_data("png bit depth is not allowed for its colour type"));
+        }
+        if data[10] != 0 || data[11] != 0 {
+            return Err(invalid_data("unknown png compression or filter method"));
+        }
+        let interlace_method = match data[12] {
+            0 => InterlaceType::None,
+            1 => InterlaceType::Adam7,
+            _ => return Err(invalid_data("unknown png interlace method")),
+        };
+        Ok(HeaderChunk { width, height, bit_depth, color_type, interlace_method })
+    }
+
+    fn paeth(left: u8, up: u8, up_left: u8) -> u8 {
+        let estimate = left as i16 + up as i16 - up_left as i16;
+        let left_distance = (estimate - left as i16).abs();
+        let up_distance = (estimate - up as i16).abs();
+        let up_left_distance = (estimate - up_left as i16).abs();
+        if left_distance <= up_distance && left_distance <= up_left_distance {
+            left
+        }
+        else if up_distance <= up_left_distance {
+            up
+        }
+        else {
+            up_left
+        }
+    }
+
+    // filters predict each byte from the one a pixel to the left, the one above, or both
+    fn unfilter(filter: u8, row: &mut [u8], previous: &[u8], bytes_per_pixel: usize) -> Result<(), std::io::Error> {
+        for i in 0..row.len() {
+            let left = if i >= bytes_per_pixel { row[i - bytes_per_pixel] } else { 0 };
+            let up_left = if i >= bytes_per_pixel { previous[i - bytes_per_pixel] } else { 0 };
+            let up = previous[i];
+            row[i] = row[i].wrapping_add(match filter {
+                FILTER_NONE => 0,
+                FILTER_SUB => left,
+                FILTER_UP => up,
+                FILTER_AVERAGE => ((left as u16 + up as u16) / 2) as u8,
+                FILTER_PAETH => paeth(left, up, up_left),
+                _ => return Err(invalid_data("unknown png filter type")),
+            });
+        }
+        Ok(())
+    }
+
+    // samples under 8 bits are packed from the highest bit of each byte
+    fn sample(row: &[u8], index: usize, bit_depth: u8) -> u16 {
+        match bit_depth {
+            16 => u16::from_be_bytes([row[index * 2], row[index * 2 + 1]]),
+            8 => row[index] as u16,
+            _ => {
+                let bit = index * bit_depth as usize;
+                ((row[bit / 8] >> (8 - bit_depth as usize - bit % 8)) & ((1 << bit_depth) - 1)) as u16
+            },
+        }
+    }
+
+    fn scale_sample(value: u16, bit_depth: u8) -> u8 {
+        match bit_depth {
+            16 => (value >> 8) as u8,
+            8 => value as u8,
+            _ => (value as u32 * 255 / ((1 << bit_depth) - 1)) as u8,
+        }
+    }
+
+    pub fn read(path: &str) -> Result<image::ColorImage, std::io::Error> {
+        decode(&fs::read(path)?)
+    }
+
+    /// Decodes a png of any colour type, bit depth and interlacing, discarding alpha.
+    pub fn decode(bytes: &[u8]) -> Result<image::ColorImage, std::io::Error> {
+        decode_with_limits(bytes, &Limits::default())
+    }
+
+    pub fn decode_with_limits(bytes: &[u8], limits: &Limits) -> Result<image::ColorImage, std::io::Error> {
+        let canvas = decode_rgba_with_limits(bytes, limits)?;
+        let pixels: Vec<image::RGB> = canvas.pixels.iter().map(|pixel| {
+            image::RGB { red: pixel.red, green: pixel.green, blue: pixel.blue }
+        }).collect();
+        Ok(image_from_rgb(canvas.width, canvas.height, &pixels))
+    }
+
+    /// Decodes a png with its alpha channel, or the transparency given by a tRNS chunk.
+    pub fn decode_rgba(bytes: &[u8]) -> Result<image::Canvas<image::RGBA>, std::io::Error> {
+        decode_rgba_with_limits(bytes, &Limits::default())
+    }
+
+    /// Decodes a png, failing with `LimitExceeded` as soon as it goes past one of the limits.
+    pub fn decode_rgba_with_limits(bytes: &[u8], limits: &Limits) -> Result<image::Canvas<image::RGBA>, std::io::Error> {
+        if read_bytes(bytes, 0, PNG_SIGNATURE.len())? != PNG_SIGNATURE {
+            return Err(invalid_data("missing png signature"));
+        }
+        let crc = crc::Crc::new();
+        let mut offset = PNG_SIGNATURE.len();
+        let mut header = None;
+        let mut palette = Vec::new();
+        let mut transparency = None;
+        let mut stream = Vec::new();
+        let mut chunk_count = 0;
+        loop {
+            chunk_count += 1;
+            limits.check_chunks(chunk_count)?;
+            let length = read_be_u32(bytes, offset)? as usize;
+            let chunk = read_bytes(bytes, offset + 4, length + 4)?;
+            if crc.calculate(chunk) != read_be_u32(bytes, offset + 8 + length)? {
+                return Err(invalid_data("png chunk has an incorrect crc"));
+            }
+            offset += 12 + length;
+            let (signature, data) = chunk.split_at(4);
+            if header.is_none() != (signature == HEADER_SIGNATURE) {
+                return Err(invalid_data("png must have one header chunk, at the start"));
+            }
+            match [signature[0], signature[1], signature[2], signature[3]] {
+                HEADER_SIGNATURE => {
+                    let chunk = read_header(data)?;
+                    limits.check_dimensions(chunk.width, chunk.height)?;
+                    header = Some(chunk);
+                },
+                PALETTE_SIGNATURE => {
+                    if data.len() % 3 != 0 || data.len() / 3 > 256 {
+                        return Err(invalid_data("png palette has the wrong length"));
+                    }
+                    palette = data.chunks(3).map(|color| {
+                        image::RGBA { red: color[0], green: color[1], blue: color[2], alpha: 255 }
+                    }).collect();
+                },
+                TRANSPARENCY_SIGNATURE => transparency = Some(data),
+                DATA_SIGNATURE => stream.extend(data),
+                END_SIGNATURE => break,
+                _ if signature[0] & ANCILLARY_FLAG != 0 => {},
+                _ => return Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "png has an unknown critical chunk")),
+            }
+        }
+        let header = header.unwrap();
+        if header.color_type == ColorType::Palette && palette.is_empty() {
+            return Err(invalid_data("paletted png has no palette"));
+        }
+        // paletted images give an alpha for each entry, and the others a single colour that is fully transparent
+        let mut transparent_color = None;
+        if let Some(transparency) = transparency {
+            match header.color_type {
+                ColorType::Palette if transparency.len() <= palette.len() => {
+                    for (color, alpha) in palette.iter_mut().zip(transparency) {
+                        color.alpha = *alpha;
+                    }
+                },
+                ColorType::GrayScale | ColorType::TrueColor if transparency.len() == header.color_type.channels() * 2 => {
+                    transparent_color = Some(transparency.chunks(2).map(|value| u16::from_be_bytes([value[0], value[1]])).collect::<Vec<u16>>());
+                },
+                _ => return Err(invalid_data("png transparency chunk does not fit the colour type")),
+            }
+        }
+        let (output_limit, limit) = limits.output_limit(bytes.len());
+        let data = match zlib::decompress_with_limits(&stream, &zlib::Limits { max_output: output_limit, ..zlib::Limits::default() }) {
+            Ok(data) => data,
+            Err(zlib::DecodeError::LimitExceeded) => return Err(limit_exceeded(limit)),
+            Err(_) => return Err(invalid_data("png image data is not a valid zlib stream")),
+        };
+
+        let channels = header.color_type.channels();
+        let bits_per_pixel = channels * header.bit_depth as usize;
+        let bytes_per_pixel = bits_per_pixel.div_ceil(8);
+        let passes = match header.interlace_method {
+            InterlaceType::None => vec![(0, 0, 1, 1)],
+            InterlaceType::Adam7 => ADAM7_PASSES.to_vec(),
+        };
+        // each row is a filter byte and its samples, so the data gives an upper bound on the image size
+        if header.width as u64 * header.height as u64 > data.len() as u64 * 8 {
+            return Err(invalid_data("png image data is shorter than its dimensions"));
+        }
+        let mut canvas = image::Canvas::new(header.width, header.height);
+        let mut position = 0;
+        let mut samples = [0u16; 4];
+        for (x_start, y_start, dx, dy) in passes {
+            if x_start >= header.width || y_start >= header.height {
+                continue;
+            }
+            let pass_width = (header.width - x_start).div_ceil(dx);
+            let pass_height = (header.height - y_start).div_ceil(dy);
+            let row_length = (pass_width as usize * bits_per_pixel).div_ceil(8);
+            let mut previous = vec![0; row_length];
+            for row_index in 0..pass_height {
+                let filter = read_u8(&data, position)?;
+                let mut row = read_bytes(&data, position + 1, row_length)?.to_vec();
+                position += 1 + row_length;
+                unfilter(filter, &mut row, &previous, bytes_per_pixel)?;
+                for i in 0..pass_width as usize {
+                    for (channel, value) in samples.iter_mut().enumerate().take(channels) {
+                        *value = sample(&row, i * channels + channel, header.bit_depth);
+                    }
+                    let opaque = if transparent_color.as_deref() == Some(&samples[..channels]) { 0 } else { 255 };
+                    let scale = |value| scale_sample(value, header.bit_depth);
+                    let pixel = match header.color_type {
+                        ColorType::Palette => match palette.get(samples[0] as usize) {
+                            Some(color) => *color,
+                            None => return Err(invalid_data("pixel refers to a colour outside of the palette")),
+                        },
+                        ColorType::GrayScale => {
+                            let grey = scale(samples[0]);
+                            image::RGBA { red: grey, green: grey, blue: grey, alpha: opaque }
+                        },
+                        ColorType::GrayScaleAlpha => {
+                            let grey = scale(samples[0]);
+                            image::RGBA { red: grey, green: grey, blue: grey, alpha: scale(samples[1]) }
+                        },
+                        ColorType::TrueColor => image::RGBA { red: scale(samples[0]), green: scale(samples[1]), blue: scale(samples[2]), alpha: opaque },
+                        ColorType::TrueColorAlpha => image::RGBA { red: scale(samples[0]), green: scale(samples[1]), blue: scale(samples[2]), alpha: scale(samples[3]) },
+                    };
+                    canvas.set_pixel(x_start + i as u32 * dx, y_start + row_index * dy, pixel);
+                }
+                previous = row;
+            }
+        }
+        Ok(canvas)
     }
 
     #[cfg(test)]
@@ -184,6 +616,138 @@ mod png {
                 assert_eq!(chunk_bytes[i + 23], checksum[i]);
             }
         }
+        #[test]
+        fn png_image_writes_palette_and_filtered_scanlines() {
+            let mut image = image::ColorImage::new(3, 2, image::RGB { red: 0, green: 0, blue: 0 });
+            image.set_pixel(2, 1, image::RGB { red: 10, green: 20, blue: 30 });
+            let bytes = PngImage::new(&image).encode();
+            assert_eq!(&bytes[0..8], &PNG_SIGNATURE);
+            let header = get_chunk_bytes(&HEADER_SIGNATURE, &vec![0, 0, 0, 3, 0, 0, 0, 2, 8, 3, 0, 0, 0]);
+            let palette = get_chunk_bytes(&PALETTE_SIGNATURE, &vec![0, 0, 0, 10, 20, 30]);
+            let stream = zlib::compressed(&[FILTER_NONE, 0, 0, 0, FILTER_NONE, 0, 0, 1]).to_bytes();
+            let data = get_chunk_bytes(&DATA_SIGNATURE, &stream);
+            let end = get_chunk_bytes(&END_SIGNATURE, &Vec::new());
+            assert_eq!(&bytes[8..], &[header, palette, data, end].concat()[..]);
+        }
+        // a png with the given header fields and filtered scanlines, built without the writer
+        fn build_png(header: &[u8], extra_chunks: &[([u8; 4], Vec<u8>)], scanlines: &[u8]) -> Vec<u8> {
+            let mut bytes = PNG_SIGNATURE.to_vec();
+            bytes.extend(get_chunk_bytes(&HEADER_SIGNATURE, &header.to_vec()));
+            for (signature, data) in extra_chunks {
+                bytes.extend(get_chunk_bytes(signature, data));
+            }
+            bytes.extend(get_chunk_bytes(&DATA_SIGNATURE, &zlib::compressed(scanlines).to_bytes()));
+            bytes.extend(get_chunk_bytes(&END_SIGNATURE, &Vec::new()));
+            bytes
+        }
+        fn filter_row(filter: u8, row: &[u8], previous: &[u8], bytes_per_pixel: usize) -> Vec<u8> {
+            (0..row.len()).map(|i| {
+                let left = if i >= bytes_per_pixel { row[i - bytes_per_pixel] } else { 0 };
+                let up_left = if i >= bytes_per_pixel { previous[i - bytes_per_pixel] } else { 0 };
+                let prediction = match filter {
+                    FILTER_SUB => left,
+                    FILTER_UP => previous[i],
+                    FILTER_AVERAGE => ((left as u16 + previous[i] as u16) / 2) as u8,
+                    FILTER_PAETH => paeth(left, previous[i], up_left),
+                    _ => 0,
+                };
+                row[i].wrapping_sub(prediction)
+            }).collect()
+        }
+        #[test]
+        fn png_round_trips_through_decode() {
+            let mut image = image::ColorImage::new(40, 30, image::RGB { red: 0, green: 0, blue: 0 });
+            for x in 0..40 {
+                for y in 0..30 {
+                    image.set_pixel(x, y, image::RGB { red: (x * 6) as u8, green: (y * 8) as u8, blue: ((x + y) % 4) as u8 });
+                }
+            }
+            // more than 256 colours are written as true colour
+            let decoded = decode(&PngImage::new(&image).encode()).unwrap();
+            let small = image::ColorImage::new(5, 3, image::RGB { red: 9, green: 8, blue: 7 });
+            let decoded_small = decode(&PngImage::new(&small).encode()).unwrap();
+            for (image, decoded) in [(image, decoded), (small, decoded_small)] {
+                assert_eq!((decoded.canvas.width, decoded.canvas.height), (image.canvas.width, image.canvas.height));
+                for x in 0..image.canvas.width {
+                    for y in 0..image.canvas.height {
+                        assert_eq!(decoded.pixel(x, y), image.pixel(x, y));
+                    }
+                }
+            }
+        }
+        #[test]
+        fn decode_reverses_filters_and_adam7_interlacing() {
+            let (width, height) = (11, 9);
+            let color = |x: u32, y: u32| [(x * 23) as u8, (y * 29) as u8, (x * y) as u8, 255 - (x + y) as u8];
+            let mut scanlines = Vec::new();
+            let mut filter = 0;
+            for (x_start, y_start, dx, dy) in ADAM7_PASSES {
+                let mut previous = Vec::new();
+                for y in (y_start..height).step_by(dy as usize) {
+                    let row: Vec<u8> = (x_start..width).step_by(dx as usize).flat_map(|x| color(x, y)).collect();
+                    previous.resize(row.len(), 0);
+                    scanlines.push(filter);
+                    scanlines.extend(filter_row(filter, &row, &previous, 4));
+                    filter = (filter + 1) % 5;
+                    previous = row;
+                }
+            }
+            let header = [0, 0, 0, width as u8, 0, 0, 0, height as u8, 8, 6, 0, 0, 1];
+            let canvas = decode_rgba(&build_png(&header, &[], &scanlines)).unwrap();
+            for x in 0..width {
+                for y in 0..height {
+                    let [red, green, blue, alpha] = color(x, y);
+                    assert_eq!(*canvas.pixel(x, y), image::RGBA { red, green, blue, alpha });
+                }
+            }
+        }
+        #[test]
+        fn decode_reads_low_bit_depths_and_transparency() {
+            // 2 bit grey samples 0, 1, 2, 3, 0 with value 2 transparent
+            let header = [0, 0, 0, 5, 0, 0, 0, 1, 2, 0, 0, 0, 0];
+            let transparency = (TRANSPARENCY_SIGNATURE, vec![0, 2]);
+            let canvas = decode_rgba(&build_png(&header, &[transparency], &[FILTER_NONE, 0b0001_1011, 0b0000_0000])).unwrap();
+            let grey = |value: u8, alpha: u8| image::RGBA { red: value, green: value, blue: value, alpha };
+            assert_eq!(canvas.pixels, vec![grey(0, 255), grey(85, 255), grey(170, 0), grey(255, 255), grey(0, 255)]);
+            // 16 bit grey keeps the high byte
+            let header = [0, 0, 0, 2, 0, 0, 0, 1, 16, 0, 0, 0, 0];
+            let canvas = decode_rgba(&build_png(&header, &[], &[FILTER_NONE, 0x12, 0x34, 0xab, 0xcd])).unwrap();
+            assert_eq!(canvas.pixels, vec![grey(0x12, 255), grey(0xab, 255)]);
+            // a 1 bit palette with alpha for the first entry only
+            let header = [0, 0, 0, 3, 0, 0, 0, 1, 1, 3, 0, 0, 0];
+            let palette = (PALETTE_SIGNATURE, vec![10, 20, 30, 40, 50, 60]);
+            let transparency = (TRANSPARENCY_SIGNATURE, vec![128]);
+            let canvas = decode_rgba(&build_png(&header, &[palette, transparency], &[FILTER_NONE, 0b1010_0000])).unwrap();
+            let first = image::RGBA { red: 10, green: 20, blue: 30, alpha: 128 };
+            let second = image::RGBA { red: 40, green: 50, blue: 60, alpha: 255 };
+            assert_eq!(canvas.pixels, vec![second, first, second]);
+        }
+        #[test]
+        fn decode_rejects_corrupt_chunks() {
+            let mut bytes = PngImage::new(&image::ColorImage::new(2, 2, image::RGB { red: 1, green: 2, blue: 3 })).encode();
+            let end = bytes.len();
+            bytes[end - 20] ^= 0xff;
+            assert_eq!(decode(&bytes).err().unwrap().to_string(), "png chunk has an incorrect crc");
+            let header = [0, 0, 0, 1, 0, 0, 0, 1, 4, 2, 0, 0, 0];
+            assert_eq!(decode(&build_png(&header, &[], &[0, 0])).err().unwrap().to_string(), "png bit depth is not allowed for its colour type");
+            let header = [0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0];
+            assert_eq!(decode(&build_png(&header, &[], &[5, 0])).err().unwrap().to_string(), "unknown png filter type");
+            assert_eq!(decode(&build_png(&header, &[], &[0])).err().unwrap().to_string(), "unexpected end of image data");
+        }
+        #[test]
+        fn decode_stops_at_the_limits() {
+            // a 1000x1000 black greyscale image is a megabyte of scanlines, but only a few kilobytes compressed
+            let header = [0, 0, 0x03, 0xe8, 0, 0, 0x03, 0xe8, 8, 0, 0, 0, 0];
+            let bytes = build_png(&header, &[], &vec![0; 1001 * 1000]);
+            assert!(bytes.len() < 10000);
+            let exceeded = |limits: Limits| LimitExceeded::from_error(&decode_with_limits(&bytes, &limits).err().unwrap());
+            assert_eq!(exceeded(Limits { max_width: 999, ..Limits::default() }), Some(LimitExceeded::Width));
+            assert_eq!(exceeded(Limits { max_output: 1 << 19, ..Limits::default() }), Some(LimitExceeded::Output));
+            assert_eq!(exceeded(Limits { max_ratio: 100, ..Limits::default() }), Some(LimitExceeded::Ratio));
+            assert_eq!(exceeded(Limits { max_chunks: 2, ..Limits::default() }), Some(LimitExceeded::Chunks));
+            let limits = Limits { max_pixels: 1000 * 1000, max_chunks: 3, max_output: 1001 * 1000, ..Limits::default() };
+            assert_eq!(decode_with_limits(&bytes, &limits).unwrap().canvas.width, 1000);
+        }
     }
 }
 
@@ -192,14 +756,42 @@ pub mod gif {
     use std::fs;
     use image;
     use lzw;
-    const HEADER_SIGNATURE: [u8; 6] = [0x47,0x49,0x46,0x38,0x39,0x61];
+    pub(crate) const HEADER_SIGNATURE: [u8; 6] = [0x47,0x49,0x46,0x38,0x39,0x61];
+    pub(crate) const GIF87_SIGNATURE: [u8; 6] = [0x47,0x49,0x46,0x38,0x37,0x61];
     // 256 RGB colours in the palette
     const COLOR_TABLE_FIELDS: u8 = 0xf7;
+    // 256 RGB colours in a local colour table
+    const LOCAL_COLOR_TABLE_FIELDS: u8 = 0x87;
+    // colour resolution of 8 bits but no global colour table
+    const NO_COLOR_TABLE_FIELDS: u8 = 0x70;
+    const EXTENSION_SENTINEL: u8 = 0x21;
+    const GRAPHIC_CONTROL_LABEL: u8 = 0xf9;
+    const APPLICATION_LABEL: u8 = 0xff;
+    const NETSCAPE_IDENTIFIER: [u8; 11] = [0x4e,0x45,0x54,0x53,0x43,0x41,0x50,0x45,0x32,0x2e,0x30];
     const IMAGE_DATA_SENTINEL: u8 = 0x2c;
     const EOF_SENTINEL: u8 = 0x3b;
+    // the signature and logical screen descriptor
+    const SCREEN_DESCRIPTOR_END: usize = 13;
+    const COLOR_TABLE_FLAG: u8 = 0x80;
+    const INTERLACE_FLAG: u8 = 0x40;
+    // a table holds 2 ** (n + 1) colours
+    const TABLE_SIZE_MASK: u8 = 0x07;
 
+    fn u16_to_bytes(val: u16) -> [u8; 2] {
+        [val as u8, (val >> 8) as u8]
+    }
+    fn insert_screen_descriptor(width: &[u8; 2], height: &[u8; 2], fields: u8, data: &mut Vec<u8>) {
+        data.extend(&HEADER_SIGNATURE);
+        data.extend(width);
+        data.extend(height);
+        data.push(fields);
+        // background color: 0
+        data.push(0);
+        // default pixel aspect ratio
+        data.push(0);
+    }
     fn insert_color_table(palette: &image::Palette, data: &mut Vec<u8>) {
-        if palette.size() > 255 {
+        if palette.size() > 256 {
             panic!("attempted to write a gif with too many colours");
         }
         for i in 0..256 {
@@ -217,17 +809,62 @@ pub mod gif {
             }
         }
     }
-    fn insert_color_data(pixels: &Vec<u8>, height: &[u8; 2], width: &[u8; 2], data: &mut Vec<u8>) {
+    fn insert_color_list(colors: &[image::RGB], data: &mut Vec<u8>) {
+        if colors.len() > 256 {
+            panic!("attempted to write a gif with too many colours");
+        }
+        for color in colors {
+            data.push(color.red);
+            data.push(color.green);
+            data.push(color.blue);
+        }
+        // pad the table out to its full 256 entries
+        for _ in colors.len()..256 {
+            data.extend(&[0, 0, 0]);
+        }
+    }
+    fn insert_image_descriptor(height: &[u8; 2], width: &[u8; 2], fields: u8, data: &mut Vec<u8>) {
         data.push(IMAGE_DATA_SENTINEL);
         // top right is the origin
         data.extend(&[0; 4]);
         data.extend(width);
         data.extend(height);
+        data.push(fields);
+    }
+    fn insert_color_data(pixels: &[u8], height: &[u8; 2], width: &[u8; 2], data: &mut Vec<u8>) {
         // no local color table
-        data.push(0);
+        insert_image_descriptor(height, width, 0, data);
+        data.extend(encode_color_data(pixels));
+    }
+    // the minimum code length followed by the lzw data sub-blocks, which are independent of the colour table in use
+    fn encode_color_data(pixels: &[u8]) -> Vec<u8> {
+        let mut data = Vec::new();
         // minimum code length of 8
         data.push(8);
-        pack_encodings_into_bytes(lzw::encode_all(pixels, MAX_ENCODINGS, 2), data);
+        pack_encodings_into_bytes(lzw::encode_all(pixels, MAX_ENCODINGS, 2), &mut data);
+        data
+    }
+    fn insert_loop_extension(loop_count: u16, data: &mut Vec<u8>) {
+        data.push(EXTENSION_SENTINEL);
+        data.push(APPLICATION_LABEL);
+        data.push(NETSCAPE_IDENTIFIER.len() as u8);
+        data.extend(&NETSCAPE_IDENTIFIER);
+        data.push(3);
+        // sub-block id for the loop count
+        data.push(1);
+        data.extend(&u16_to_bytes(loop_count));
+        data.push(0);
+    }
+    fn insert_graphic_control_extension(delay: u16, data: &mut Vec<u8>) {
+        data.push(EXTENSION_SENTINEL);
+        data.push(GRAPHIC_CONTROL_LABEL);
+        data.push(4);
+        // disposal method 1: leave the frame in place, no transparency
+        data.push(0x04);
+        data.extend(&u16_to_bytes(delay));
+        // transparent colour index, unused
+        data.push(0);
+        data.push(0);
     }
     fn truncate_usize_vec(data: &Vec<usize>) -> Vec<u8> {
         let mut result = Vec::new();
@@ -278,7 +915,7 @@ pub mod gif {
         //add bits starting with the lowest and working from the back to start of the byte
         for i in 0..code.length {
             if (code.value >> i) & 1 == 1 {
-                current.byte = current.byte | bit_mask[current.bit_index];
+                current.byte |= bit_mask[current.bit_index];
             }
             current.bit_index += 1;
             if current.bit_index == 8 {
@@ -292,33 +929,334 @@ pub mod gif {
     pub struct GifImage {
         pub image: image::ColorImage,
     }
-    impl Image for GifImage {
-        fn write(self, path: &str) -> Result<fs::File, std::io::Error> {
-            let mut file_image = match fs::File::create(path) {
-                Ok(file_image) => file_image,
-                Err(e) => return Err(e),
-            };
+    impl GifImage {
+        pub fn encode(&self) -> Result<Vec<u8>, std::io::Error> {
+            if self.image.canvas.width > 0xffff || self.image.canvas.height > 0xffff {
+                return Err(invalid_input("gif images cannot be larger than 65535 pixels across"));
+            }
+            if self.image.palette.size() > 256 {
+                return Err(invalid_input("gif cannot have more than 256 colours"));
+            }
             let mut data: Vec<u8> = vec![];
-            // png is little-endian, which means that all multi-byte values will occur in reverse order
-            data.extend(&HEADER_SIGNATURE);
-            let width = self.image.canvas.width as u16;
-            let width = [width as u8, (width >> 8) as u8];
-            data.extend(&width);
-            let height = self.image.canvas.height as u16;
-            let height = [height as u8, (height >> 8) as u8];
-            data.extend(&height);
-            data.push(COLOR_TABLE_FIELDS);
-            // background color: 0
-            data.push(0);
-            // default pixel aspect ratio
-            data.push(0);
+            // gif is little-endian, which means that all multi-byte values will occur in reverse order
+            let width = u16_to_bytes(self.image.canvas.width as u16);
+            let height = u16_to_bytes(self.image.canvas.height as u16);
+            insert_screen_descriptor(&width, &height, COLOR_TABLE_FIELDS, &mut data);
             insert_color_table(&self.image.palette, &mut data);
             // skip Graphic Control Extension
             let truncated_vec = truncate_usize_vec(&self.image.canvas.pixels);
             insert_color_data(&truncated_vec, &height, &width, &mut data);
             data.push(EOF_SENTINEL);
-            file_image.write_all(&data).unwrap();
+            Ok(data)
+        }
+    }
+    impl Image for GifImage {
+        fn write(self, path: &str) -> Result<fs::File, std::io::Error> {
+            let data = self.encode()?;
+            let mut file_image = fs::File::create(path)?;
+            file_image.write_all(&data)?;
+            Ok(file_image)
+        }
+    }
+
+    /// An animation in which every frame shows the same pixel indices through a different colour table.
+    ///
+    /// The pixel data is lzw encoded once and repeated for each frame, only the local colour tables differ.
+    pub struct PaletteCycleGifImage {
+        pub image: image::ColorImage,
+        /// the colour table used by each frame, indexed in the same way as the image palette
+        pub frames: Vec<Vec<image::RGB>>,
+        /// time between frames in hundredths of a second
+        pub delay: u16,
+        /// number of times to repeat the animation, 0 loops forever
+        pub loop_count: u16,
+    }
+    impl PaletteCycleGifImage {
+        /// Creates an animation where each frame shifts the palette along by one entry,
+        /// so that frame `n` shows index `i` using the colour originally at `i + n`.
+        pub fn rotate(image: image::ColorImage, frame_count: usize, delay: u16) -> Self {
+            let size = image.palette.size();
+            PaletteCycleGifImage::remap(image, frame_count, delay, |frame, index, palette| {
+                *palette.color((index + frame) % size).unwrap()
+            })
+        }
+        /// Creates an animation where `mapping(frame, index, palette)` gives the colour shown for each palette index in each frame.
+        pub fn remap<F>(image: image::ColorImage, frame_count: usize, delay: u16, mapping: F) -> Self
+            where F: Fn(usize, usize, &image::Palette) -> image::RGB {
+            let mut frames = Vec::new();
+            for frame in 0..frame_count {
+                let colors = (0..image.palette.size()).map(|index| mapping(frame, index, &image.palette)).collect();
+                frames.push(colors);
+            }
+            PaletteCycleGifImage {
+                image,
+                frames,
+                delay,
+                loop_count: 0,
+            }
+        }
+        /// Fails if a frame has more than 256 colours, or too few for the indices in the image.
+        pub fn encode(&self) -> Result<Vec<u8>, std::io::Error> {
+            if self.image.canvas.width > 0xffff || self.image.canvas.height > 0xffff {
+                return Err(invalid_input("gif images cannot be larger than 65535 pixels across"));
+            }
+            if self.frames.iter().any(|colors| colors.len() > 256) {
+                return Err(invalid_input("gif cannot have more than 256 colours"));
+            }
+            let index_count = self.image.canvas.pixels.iter().max().map_or(0, |index| index + 1);
+            if self.frames.iter().any(|colors| colors.len() < index_count) {
+                return Err(invalid_input("gif frame has fewer colours than the image uses"));
+            }
+            let mut data: Vec<u8> = vec![];
+            let width = u16_to_bytes(self.image.canvas.width as u16);
+            let height = u16_to_bytes(self.image.canvas.height as u16);
+            insert_screen_descriptor(&width, &height, NO_COLOR_TABLE_FIELDS, &mut data);
+            insert_loop_extension(self.loop_count, &mut data);
+            let truncated_vec = truncate_usize_vec(&self.image.canvas.pixels);
+            let color_data = encode_color_data(&truncated_vec);
+            for colors in self.frames.iter() {
+                insert_graphic_control_extension(self.delay, &mut data);
+                insert_image_descriptor(&height, &width, LOCAL_COLOR_TABLE_FIELDS, &mut data);
+                insert_color_list(colors, &mut data);
+                data.extend(&color_data);
+            }
+            data.push(EOF_SENTINEL);
+            Ok(data)
+        }
+    }
+    impl Image for PaletteCycleGifImage {
+        fn write(self, path: &str) -> Result<fs::File, std::io::Error> {
+            let data = self.encode()?;
+            let mut file_image = fs::File::create(path)?;
+            file_image.write_all(&data)?;
             Ok(file_image)
         }
     }
+
+    fn color_table_length(fields: u8) -> usize {
+        1 << ((fields & TABLE_SIZE_MASK) + 1)
+    }
+    fn read_color_table(bytes: &[u8], offset: usize, fields: u8) -> Result<Vec<image::RGB>, std::io::Error> {
+        let table = read_bytes(bytes, offset, color_table_length(fields) * 3)?;
+        Ok(table.chunks(3).map(|entry| image::RGB { red: entry[0], green: entry[1], blue: entry[2] }).collect())
+    }
+    // returns the joined sub-blocks and the offset after the empty block that ends them
+    fn read_sub_blocks(bytes: &[u8], mut offset: usize) -> Result<(Vec<u8>, usize), std::io::Error> {
+        let mut data = Vec::new();
+        loop {
+            let length = read_u8(bytes, offset)? as usize;
+            offset += 1;
+            if length == 0 {
+                return Ok((data, offset));
+            }
+            data.extend(read_bytes(bytes, offset, length)?);
+            offset += length;
+        }
+    }
+    // interlaced rows are stored in four passes: every 8th row from 0, every 8th from 4, every 4th from 2, then every 2nd from 1
+    fn interlaced_rows(height: usize) -> Vec<usize> {
+        let mut rows = Vec::with_capacity(height);
+        for (start, step) in [(0, 8), (4, 8), (2, 4), (1, 2)] {
+            rows.extend((start..height).step_by(step));
+        }
+        rows
+    }
+
+    pub fn read(path: &str) -> Result<image::ColorImage, std::io::Error> {
+        decode(&fs::read(path)?)
+    }
+
+    /// Decodes the first frame of a gif, clipped to the logical screen.
+    pub fn decode(bytes: &[u8]) -> Result<image::ColorImage, std::io::Error> {
+        decode_with_limits(bytes, &Limits::default())
+    }
+
+    /// Decodes the first frame of a gif, failing with `LimitExceeded` as soon as it goes past one of the limits.
+    pub fn decode_with_limits(bytes: &[u8], limits: &Limits) -> Result<image::ColorImage, std::io::Error> {
+        let signature = read_bytes(bytes, 0, HEADER_SIGNATURE.len())?;
+        if signature != HEADER_SIGNATURE && signature != GIF87_SIGNATURE {
+            return Err(invalid_data("missing gif signature"));
+        }
+        let screen_width = read_le_u16(bytes, 6)? as usize;
+        let screen_height = read_le_u16(bytes, 8)? as usize;
+        let screen_fields = read_u8(bytes, 10)?;
+        limits.check_dimensions(screen_width as u32, screen_height as u32)?;
+        let mut offset = SCREEN_DESCRIPTOR_END;
+        let mut colors = Vec::new();
+        if screen_fields & COLOR_TABLE_FLAG != 0 {
+            colors = read_color_table(bytes, offset, screen_fields)?;
+            offset += colors.len() * 3;
+        }
+
+        let mut block_count = 0;
+        loop {
+            block_count += 1;
+            limits.check_chunks(block_count)?;
+            match read_u8(bytes, offset)? {
+                // the extension label is skipped along with its sub-blocks
+                EXTENSION_SENTINEL => offset = read_sub_blocks(bytes, offset + 2)?.1,
+                IMAGE_DATA_SENTINEL => break,
+                EOF_SENTINEL => return Err(invalid_data("gif contains no images")),
+                _ => return Err(invalid_data("unknown gif block")),
+            }
+        }
+        let left = read_le_u16(bytes, offset + 1)? as usize;
+        let top = read_le_u16(bytes, offset + 3)? as usize;
+        let width = read_le_u16(bytes, offset + 5)? as usize;
+        let height = read_le_u16(bytes, offset + 7)? as usize;
+        let image_fields = read_u8(bytes, offset + 9)?;
+        limits.check_dimensions(width as u32, height as u32)?;
+        offset += 10;
+        if image_fields & COLOR_TABLE_FLAG != 0 {
+            colors = read_color_table(bytes, offset, image_fields)?;
+            offset += colors.len() * 3;
+        }
+        if colors.is_empty() {
+            return Err(invalid_data("gif has no colour table"));
+        }
+
+        let min_code_length = read_u8(bytes, offset)? as usize;
+        if !(2..=8).contains(&min_code_length) {
+            return Err(invalid_data("invalid gif minimum code length"));
+        }
+        let (color_data, _) = read_sub_blocks(bytes, offset + 1)?;
+        let (output_limit, limit) = limits.output_limit(bytes.len());
+        let frame = match lzw::decode_packed_with_limit(&color_data, min_code_length, lzw::BitOrder::LsbFirst, false, output_limit) {
+            Ok(frame) => frame,
+            Err(lzw::DecodeError::LimitExceeded) => return Err(limit_exceeded(limit)),
+            Err(lzw::DecodeError::InvalidCode(_)) => return Err(invalid_data("invalid gif lzw code")),
+        };
+        if frame.len() < width * height {
+            return Err(invalid_data("gif image data is shorter than its frame"));
+        }
+
+        // the screen descriptor alone says nothing about how much data there is, so the canvas
+        // is only the part of the screen the frame covers rather than the whole screen
+        if (width > 0 && left >= screen_width) || (height > 0 && top >= screen_height) {
+            return Err(invalid_data("gif frame lies outside of its logical screen"));
+        }
+        let canvas_width = width.min(screen_width - left.min(screen_width));
+        let canvas_height = height.min(screen_height - top.min(screen_height));
+        let mut indices = vec![0; canvas_width * canvas_height];
+        let rows: Vec<usize> = if image_fields & INTERLACE_FLAG != 0 { interlaced_rows(height) } else { (0..height).collect() };
+        for (i, y) in rows.into_iter().enumerate() {
+            if y < canvas_height {
+                for x in 0..canvas_width {
+                    indices[y * canvas_width + x] = frame[i * width + x] as usize;
+                }
+            }
+        }
+        image_from_indices(canvas_width as u32, canvas_height as u32, &colors, indices)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        fn striped_image() -> image::ColorImage {
+            let mut image = image::ColorImage::new(20, 10, image::RGB { red: 0, green: 0, blue: 0 });
+            for x in 0..20 {
+                for y in 0..10 {
+                    image.set_pixel(x, y, image::RGB { red: (x % 4) as u8 * 60, green: 0, blue: 0 });
+                }
+            }
+            image
+        }
+        #[test]
+        fn rotate_shifts_palette_by_frame() {
+            let animation = PaletteCycleGifImage::rotate(striped_image(), 4, 10);
+            assert_eq!(animation.frames.len(), 4);
+            for frame in 0..4 {
+                for index in 0..4 {
+                    assert_eq!(animation.frames[frame][index], *animation.image.palette.color((index + frame) % 4).unwrap());
+                }
+            }
+        }
+        #[test]
+        fn palette_cycle_frames_reuse_color_data() {
+            let animation = PaletteCycleGifImage::rotate(striped_image(), 3, 10);
+            let color_data = encode_color_data(&truncate_usize_vec(&animation.image.canvas.pixels));
+            let data = animation.encode().unwrap();
+            let occurrences = data.windows(color_data.len()).filter(|window| *window == &color_data[..]).count();
+            assert_eq!(occurrences, 3);
+            assert_eq!(&data[0..6], &HEADER_SIGNATURE);
+            assert_eq!(*data.last().unwrap(), EOF_SENTINEL);
+        }
+        #[test]
+        fn palette_cycle_encode_checks_sizes_and_colours() {
+            let mut animation = PaletteCycleGifImage::rotate(striped_image(), 2, 10);
+            animation.frames[1].pop();
+            assert_eq!(animation.encode().err().unwrap().to_string(), "gif frame has fewer colours than the image uses");
+            animation.frames[1] = vec![image::RGB::default(); 257];
+            assert_eq!(animation.encode().err().unwrap().kind(), std::io::ErrorKind::InvalidInput);
+            let wide = PaletteCycleGifImage::rotate(image::ColorImage::new(0x10000, 1, image::RGB::default()), 2, 10);
+            assert_eq!(wide.encode().err().unwrap().kind(), std::io::ErrorKind::InvalidInput);
+        }
+        #[test]
+        fn gif_round_trips_through_decode() {
+            // enough distinct runs to fill the lzw dictionary and force clear codes
+            let mut image = image::ColorImage::new(200, 150, image::RGB { red: 0, green: 0, blue: 0 });
+            for x in 0..200 {
+                for y in 0..150 {
+                    image.set_pixel(x, y, image::RGB { red: ((x * 7 + y * 13) % 16) as u8 * 16, green: ((x ^ y) % 16) as u8 * 16, blue: 0 });
+                }
+            }
+            let gif = GifImage { image };
+            let decoded = decode(&gif.encode().unwrap()).unwrap();
+            for x in 0..200 {
+                for y in 0..150 {
+                    assert_eq!(decoded.pixel(x, y), gif.image.pixel(x, y));
+                }
+            }
+        }
+        #[test]
+        fn decode_uses_the_first_frame_of_an_animation() {
+            let animation = PaletteCycleGifImage::rotate(striped_image(), 3, 10);
+            let decoded = decode(&animation.encode().unwrap()).unwrap();
+            for x in 0..20 {
+                for y in 0..10 {
+                    assert_eq!(decoded.pixel(x, y), animation.image.pixel(x, y));
+                }
+            }
+        }
+        #[test]
+        fn decode_stops_at_the_limits() {
+            let bytes = GifImage { image: image::ColorImage::new(300, 200, image::RGB { red: 0, green: 0, blue: 0 }) }.encode().unwrap();
+            let exceeded = |limits: Limits| LimitExceeded::from_error(&decode_with_limits(&bytes, &limits).err().unwrap());
+            assert_eq!(exceeded(Limits { max_height: 199, ..Limits::default() }), Some(LimitExceeded::Height));
+            assert_eq!(exceeded(Limits { max_output: 1000, ..Limits::default() }), Some(LimitExceeded::Output));
+            assert_eq!(exceeded(Limits { max_ratio: 2, ..Limits::default() }), Some(LimitExceeded::Ratio));
+            assert!(decode_with_limits(&bytes, &Limits { max_output: 300 * 200, ..Limits::default() }).is_ok());
+        }
+        #[test]
+        fn decode_sizes_the_canvas_by_the_frame_not_the_screen() {
+            // a 65535x65535 screen holding a single 1x1 frame
+            let mut data = HEADER_SIGNATURE.to_vec();
+            data.extend(&[0xff, 0xff, 0xff, 0xff, 0x80, 0, 0, 0, 0, 0, 255, 255, 255]);
+            data.extend(&[IMAGE_DATA_SENTINEL, 0, 0, 0, 0, 1, 0, 1, 0, 0]);
+            // 3 bit codes for clear, 1 and end of information
+            data.extend(&[2, 2, 0x4c, 0x01, 0, EOF_SENTINEL]);
+            let decoded = decode(&data).unwrap();
+            assert_eq!((decoded.canvas.width, decoded.canvas.height), (1, 1));
+            assert_eq!(decoded.pixel(0, 0), image::RGB { red: 255, green: 255, blue: 255 });
+            // a frame starting past the edge of the screen
+            data[6..10].copy_from_slice(&[1, 0, 1, 0]);
+            data[20] = 1;
+            assert_eq!(decode(&data).err().unwrap().to_string(), "gif frame lies outside of its logical screen");
+        }
+        #[test]
+        fn decode_reorders_interlaced_rows() {
+            // a 1x5 interlaced frame storing rows 0, 4, 2, 1, 3 as indices 0 to 4
+            let mut data = GIF87_SIGNATURE.to_vec();
+            data.extend(&[1, 0, 5, 0, 0x82, 0, 0]);
+            for value in 0..8 {
+                data.extend(&[value * 30, 0, 0]);
+            }
+            data.extend(&[IMAGE_DATA_SENTINEL, 0, 0, 0, 0, 1, 0, 5, 0, INTERLACE_FLAG]);
+            // 4 bit codes for clear, 0, 1, 2, 3, 4 and end of information
+            data.extend(&[3, 4, 0x08, 0x21, 0x43, 0x09, 0, EOF_SENTINEL]);
+            let decoded = decode(&data).unwrap();
+            let rows: Vec<u8> = (0..5).map(|y| decoded.pixel(0, y).red / 30).collect();
+            assert_eq!(rows, vec![0, 3, 2, 4, 1]);
+        }
+    }
 }
@@ -0,0 +1,114 @@
+use std::fmt;
+
+/// The limit a file went past, carried inside the `std::io::Error` a decoder returns.
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum LimitExceeded {
+    Width,
+    Height,
+    Pixels,
+    Chunks,
+    Output,
+    Ratio,
+}
+impl fmt::Display for LimitExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let limit = match self {
+            LimitExceeded::Width => "width",
+            LimitExceeded::Height => "height",
+            LimitExceeded::Pixels => "pixel count",
+            LimitExceeded::Chunks => "chunk count",
+            LimitExceeded::Output => "decompressed size",
+            LimitExceeded::Ratio => "expansion ratio",
+        };
+        write!(f, "image exceeds the {} limit", limit)
+    }
+}
+impl std::error::Error for LimitExceeded {}
+
+impl LimitExceeded {
+    /// Which limit an error from a decoder stopped at, or None when it failed for some other reason.
+    pub fn from_error(error: &std::io::Error) -> Option<Self> {
+        error.get_ref().and_then(|inner| inner.downcast_ref::<LimitExceeded>()).copied()
+    }
+}
+
+pub(crate) fn limit_exceeded(limit: LimitExceeded) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, limit)
+}
+
+/// Bounds on what decoding a file may cost, for files that cannot be trusted.
+/// The default sets no bounds beyond what each format allows.
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub struct Limits {
+    pub max_width: u32,
+    pub max_height: u32,
+    pub max_pixels: u64,
+    /// the most png chunks, tiff strips, or gif blocks before the first image
+    pub max_chunks: usize,
+    /// the most bytes a compressed stream in the file may decode to
+    pub max_output: usize,
+    /// the most bytes a compressed stream may decode to for each byte of the file
+    pub max_ratio: usize,
+}
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_width: u32::MAX,
+            max_height: u32::MAX,
+            max_pixels: u64::MAX,
+            max_chunks: usize::MAX,
+            max_output: usize::MAX,
+            max_ratio: usize::MAX,
+        }
+    }
+}
+impl Limits {
+    pub(crate) fn check_dimensions(&self, width: u32, height: u32) -> Result<(), std::io::Error> {
+        if width > self.max_width {
+            return Err(limit_exceeded(LimitExceeded::Width));
+        }
+        if height > self.max_height {
+            return Err(limit_exceeded(LimitExceeded::Height));
+        }
+        if width as u64 * height as u64 > self.max_pixels {
+            return Err(limit_exceeded(LimitExceeded::Pixels));
+        }
+        Ok(())
+    }
+    pub(crate) fn check_chunks(&self, count: usize) -> Result<(), std::io::Error> {
+        if count > self.max_chunks {
+            return Err(limit_exceeded(LimitExceeded::Chunks));
+        }
+        Ok(())
+    }
+    /// The most bytes a compressed stream in a file of `file_length` bytes may decode to,
+    /// and which limit that comes from.
+    pub(crate) fn output_limit(&self, file_length: usize) -> (usize, LimitExceeded) {
+        let by_ratio = file_length.saturating_mul(self.max_ratio);
+        if by_ratio < self.max_output {
+            (by_ratio, LimitExceeded::Ratio)
+        } else {
+            (self.max_output, LimitExceeded::Output)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn checks_name_the_limit_they_stop_at() {
+        let limits = Limits { max_width: 100, max_height: 50, max_pixels: 1000, ..Limits::default() };
+        assert!(limits.check_dimensions(100, 10).is_ok());
+        let exceeded = |width, height| LimitExceeded::from_error(&limits.check_dimensions(width, height).unwrap_err());
+        assert_eq!(exceeded(101, 1), Some(LimitExceeded::Width));
+        assert_eq!(exceeded(1, 51), Some(LimitExceeded::Height));
+        assert_eq!(exceeded(100, 11), Some(LimitExceeded::Pixels));
+        assert_eq!(LimitExceeded::from_error(&crate::invalid_data("truncated")), None);
+
+        let limits = Limits { max_output: 5000, max_ratio: 10, ..Limits::default() };
+        assert_eq!(limits.output_limit(100), (1000, LimitExceeded::Ratio));
+        assert_eq!(limits.output_limit(1000), (5000, LimitExceeded::Output));
+        assert_eq!(limit_exceeded(LimitExceeded::Output).to_string(), "image exceeds the decompressed size limit");
+    }
+}
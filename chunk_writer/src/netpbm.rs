@@ -0,0 +1,521 @@
+use super::*;
+use image;
+
+// the spec asks for ascii lines to be no longer than 70 characters
+const MAX_LINE_LENGTH: usize = 70;
+
+pub enum Encoding {
+    Ascii,
+    Binary,
+}
+
+fn write_bytes(path: &str, data: &[u8]) -> Result<fs::File, std::io::Error> {
+    let mut file_image = fs::File::create(path)?;
+    file_image.write_all(data)?;
+    Ok(file_image)
+}
+
+fn insert_header(magic: &str, width: u32, height: u32, maxval: Option<u16>, data: &mut Vec<u8>) {
+    let header = match maxval {
+        Some(maxval) => format!("{}\n{} {}\n{}\n", magic, width, height, maxval),
+        None => format!("{}\n{} {}\n", magic, width, height),
+    };
+    data.extend(header.as_bytes());
+}
+
+fn insert_ascii_samples<I: Iterator<Item = u8>>(samples: I, data: &mut Vec<u8>) {
+    let mut line_length = 0;
+    for sample in samples {
+        let text = sample.to_string();
+        if line_length > 0 && line_length + text.len() + 1 > MAX_LINE_LENGTH {
+            data.push(b'\n');
+            line_length = 0;
+        }
+        else if line_length > 0 {
+            data.push(b' ');
+            line_length += 1;
+        }
+        data.extend(text.as_bytes());
+        line_length += text.len();
+    }
+    data.push(b'\n');
+}
+
+pub(crate) fn luminance(color: &image::RGB) -> u32 {
+    (color.red as u32 * 299 + color.green as u32 * 587 + color.blue as u32 * 114) / 1000
+}
+
+/// A bitmap where every pixel darker than mid grey is written as black.
+pub struct PbmImage {
+    pub image: image::ColorImage,
+    pub encoding: Encoding,
+}
+impl PbmImage {
+    pub fn encode(&self) -> Vec<u8> {
+        let width = self.image.canvas.width;
+        let height = self.image.canvas.height;
+        let mut data = Vec::new();
+        // 1 is black in a bitmap
+        let bit = |x: u32, y: u32| if luminance(&self.image.pixel(x, y)) < 128 { 1 } else { 0 };
+        match self.encoding {
+            Encoding::Ascii => {
+                insert_header("P1", width, height, None, &mut data);
+                for y in 0..height {
+                    insert_ascii_samples((0..width).map(|x| bit(x, y)), &mut data);
+                }
+            },
+            Encoding::Binary => {
+                insert_header("P4", width, height, None, &mut data);
+                for y in 0..height {
+                    let mut row = vec![0; (width as usize).div_ceil(8)];
+                    for x in 0..width {
+                        row[x as usize / 8] |= bit(x, y) << (7 - x % 8);
+                    }
+                    data.extend(row);
+                }
+            },
+        }
+        data
+    }
+}
+impl Image for PbmImage {
+    fn write(self, path: &str) -> Result<fs::File, std::io::Error> {
+        write_bytes(path, &self.encode())
+    }
+}
+
+pub struct PgmImage {
+    pub image: image::GreyScaleImage,
+    pub encoding: Encoding,
+}
+impl PgmImage {
+    pub fn encode(&self) -> Vec<u8> {
+        let canvas = &self.image.canvas;
+        let mut data = Vec::new();
+        match self.encoding {
+            Encoding::Ascii => {
+                insert_header("P2", canvas.width, canvas.height, Some(255), &mut data);
+                for row in canvas.pixels.chunks(canvas.width.max(1) as usize) {
+                    insert_ascii_samples(row.iter().copied(), &mut data);
+                }
+            },
+            Encoding::Binary => {
+                insert_header("P5", canvas.width, canvas.height, Some(255), &mut data);
+                data.extend(&canvas.pixels);
+            },
+        }
+        data
+    }
+}
+impl Image for PgmImage {
+    fn write(self, path: &str) -> Result<fs::File, std::io::Error> {
+        write_bytes(path, &self.encode())
+    }
+}
+
+pub struct PpmImage {
+    pub image: image::ColorImage,
+    pub encoding: Encoding,
+}
+impl PpmImage {
+    pub fn encode(&self) -> Vec<u8> {
+        let width = self.image.canvas.width;
+        let height = self.image.canvas.height;
+        let mut data = Vec::new();
+        let row_samples = |y: u32| (0..width).flat_map(move |x| {
+            let color = self.image.pixel(x, y);
+            vec![color.red, color.green, color.blue]
+        });
+        match self.encoding {
+            Encoding::Ascii => {
+                insert_header("P3", width, height, Some(255), &mut data);
+                for y in 0..height {
+                    insert_ascii_samples(row_samples(y), &mut data);
+                }
+            },
+            Encoding::Binary => {
+                insert_header("P6", width, height, Some(255), &mut data);
+                for y in 0..height {
+                    data.extend(row_samples(y));
+                }
+            },
+        }
+        data
+    }
+}
+impl Image for PpmImage {
+    fn write(self, path: &str) -> Result<fs::File, std::io::Error> {
+        write_bytes(path, &self.encode())
+    }
+}
+
+/// A portable arbitrary map of RGB pixels with alpha, which only has a binary encoding.
+pub struct PamImage {
+    pub canvas: image::Canvas<image::RGBA>,
+}
+impl PamImage {
+    pub fn encode(&self) -> Vec<u8> {
+        let header = format!(
+            "P7\nWIDTH {}\nHEIGHT {}\nDEPTH 4\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n",
+            self.canvas.width, self.canvas.height,
+        );
+        let mut data = Vec::from(header.as_bytes());
+        for pixel in self.canvas.pixels.iter() {
+            data.extend(&[pixel.red, pixel.green, pixel.blue, pixel.alpha]);
+        }
+        data
+    }
+}
+impl Image for PamImage {
+    fn write(self, path: &str) -> Result<fs::File, std::io::Error> {
+        write_bytes(path, &self.encode())
+    }
+}
+
+// samples from any of the formats, scaled to 8 bits, with bitmaps already converted to black as 0
+struct Raster {
+    width: u32,
+    height: u32,
+    depth: usize,
+    has_alpha: bool,
+    samples: Vec<u8>,
+}
+
+struct HeaderReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+impl<'a> HeaderReader<'a> {
+    fn skip_whitespace_and_comments(&mut self) {
+        while let Some(byte) = self.bytes.get(self.position) {
+            if *byte == b'#' {
+                while self.position < self.bytes.len() && self.bytes[self.position] != b'\n' {
+                    self.position += 1;
+                }
+            }
+            else if byte.is_ascii_whitespace() {
+                self.position += 1;
+            }
+            else {
+                break;
+            }
+        }
+    }
+    fn token(&mut self) -> Result<&'a str, std::io::Error> {
+        self.skip_whitespace_and_comments();
+        let start = self.position;
+        while self.position < self.bytes.len() && !self.bytes[self.position].is_ascii_whitespace() {
+            self.position += 1;
+        }
+        if start == self.position {
+            return Err(invalid_data("unexpected end of netpbm header"));
+        }
+        match std::str::from_utf8(&self.bytes[start..self.position]) {
+            Ok(token) => Ok(token),
+            Err(_) => Err(invalid_data("netpbm header is not ascii")),
+        }
+    }
+    fn number(&mut self) -> Result<u32, std::io::Error> {
+        match self.token()?.parse() {
+            Ok(number) => Ok(number),
+            Err(_) => Err(invalid_data("expected a number in netpbm header")),
+        }
+    }
+    fn maxval(&mut self) -> Result<u32, std::io::Error> {
+        let maxval = self.number()?;
+        if maxval == 0 || maxval > 65535 {
+            return Err(invalid_data("netpbm maxval must be between 1 and 65535"));
+        }
+        Ok(maxval)
+    }
+    // a single whitespace character separates the header from the binary raster
+    fn raster(&self) -> &'a [u8] {
+        &self.bytes[(self.position + 1).min(self.bytes.len())..]
+    }
+}
+
+fn scale_sample(value: u32, maxval: u32) -> Result<u8, std::io::Error> {
+    if value > maxval {
+        return Err(invalid_data("netpbm sample is larger than maxval"));
+    }
+    Ok(((value * 255 + maxval / 2) / maxval) as u8)
+}
+
+fn sample_count(width: u32, height: u32, depth: usize) -> Result<usize, std::io::Error> {
+    match (width as usize).checked_mul(height as usize).and_then(|count| count.checked_mul(depth)) {
+        Some(count) => Ok(count),
+        None => Err(invalid_data("netpbm dimensions are too large")),
+    }
+}
+
+// the raster is checked to be long enough before anything is reserved for it
+fn read_binary_samples(raster: &[u8], count: usize, maxval: u32) -> Result<Vec<u8>, std::io::Error> {
+    let sample_size = if maxval < 256 { 1 } else { 2 };
+    let data = match count.checked_mul(sample_size) {
+        Some(length) => read_bytes(raster, 0, length)?,
+        None => return Err(invalid_data("netpbm dimensions are too large")),
+    };
+    let mut samples = Vec::with_capacity(count);
+    if maxval < 256 {
+        for byte in data {
+            samples.push(scale_sample(*byte as u32, maxval)?);
+        }
+    }
+    else {
+        for pair in data.chunks(2) {
+            samples.push(scale_sample((pair[0] as u32) << 8 | pair[1] as u32, maxval)?);
+        }
+    }
+    Ok(samples)
+}
+
+// ascii samples take at least a byte each, so no more can be left than there are bytes
+fn ascii_capacity(header: &HeaderReader, count: usize) -> usize {
+    count.min(header.bytes.len() - header.position)
+}
+
+fn read_ascii_samples(header: &mut HeaderReader, count: usize, maxval: u32) -> Result<Vec<u8>, std::io::Error> {
+    let mut samples = Vec::with_capacity(ascii_capacity(header, count));
+    for _ in 0..count {
+        samples.push(scale_sample(header.number()?, maxval)?);
+    }
+    Ok(samples)
+}
+
+// bitmap samples are converted so that black is 0, the same as the other formats
+fn read_ascii_bits(header: &mut HeaderReader, count: usize) -> Result<Vec<u8>, std::io::Error> {
+    let mut samples = Vec::with_capacity(ascii_capacity(header, count));
+    while samples.len() < count {
+        header.skip_whitespace_and_comments();
+        match header.bytes.get(header.position) {
+            Some(b'0') => samples.push(255),
+            Some(b'1') => samples.push(0),
+            Some(_) => return Err(invalid_data("unexpected character in ascii bitmap")),
+            None => return Err(invalid_data("unexpected end of image data")),
+        }
+        header.position += 1;
+    }
+    Ok(samples)
+}
+
+fn read_binary_bits(raster: &[u8], width: u32, height: u32) -> Result<Vec<u8>, std::io::Error> {
+    let row_length = (width as usize).div_ceil(8);
+    let mut samples = Vec::new();
+    for y in 0..height as usize {
+        let row = read_bytes(raster, y * row_length, row_length)?;
+        for x in 0..width as usize {
+            let bit = (row[x / 8] >> (7 - x % 8)) & 1;
+            samples.push(if bit == 1 { 0 } else { 255 });
+        }
+    }
+    Ok(samples)
+}
+
+fn decode_pam_header(header: &mut HeaderReader) -> Result<(u32, u32, usize, u32, bool), std::io::Error> {
+    let (mut width, mut height, mut depth, mut maxval, mut tuple_type) = (None, None, None, None, String::new());
+    loop {
+        match header.token()? {
+            "ENDHDR" => break,
+            "WIDTH" => width = Some(header.number()?),
+            "HEIGHT" => height = Some(header.number()?),
+            "DEPTH" => depth = Some(header.number()? as usize),
+            "MAXVAL" => maxval = Some(header.maxval()?),
+            "TUPLTYPE" => tuple_type = String::from(header.token()?),
+            _ => return Err(invalid_data("unknown field in pam header")),
+        }
+    }
+    match (width, height, depth, maxval) {
+        (Some(width), Some(height), Some(depth), Some(maxval)) => {
+            if !(1..=4).contains(&depth) {
+                return Err(invalid_data("unsupported pam depth"));
+            }
+            // the channels follow DEPTH, and the standard tuple types have to agree with it
+            let tuple_depth = match tuple_type.as_str() {
+                "BLACKANDWHITE" | "GRAYSCALE" => Some(1),
+                "BLACKANDWHITE_ALPHA" | "GRAYSCALE_ALPHA" => Some(2),
+                "RGB" => Some(3),
+                "RGB_ALPHA" => Some(4),
+                _ => None,
+            };
+            if tuple_depth.is_some_and(|tuple_depth| tuple_depth != depth) {
+                return Err(invalid_data("pam tuple type does not match its depth"));
+            }
+            let has_alpha = depth == 2 || depth == 4;
+            Ok((width, height, depth, maxval, has_alpha))
+        },
+        _ => Err(invalid_data("pam header is missing a required field")),
+    }
+}
+
+fn decode_raster(bytes: &[u8], limits: &Limits) -> Result<Raster, std::io::Error> {
+    let mut header = HeaderReader { bytes, position: 0 };
+    let magic = header.token()?;
+    if magic == "P7" {
+        let (width, height, depth, maxval, has_alpha) = decode_pam_header(&mut header)?;
+        limits.check_dimensions(width, height)?;
+        let count = sample_count(width, height, depth)?;
+        // ENDHDR is followed by a newline rather than arbitrary whitespace
+        let samples = read_binary_samples(header.raster(), count, maxval)?;
+        return Ok(Raster { width, height, depth, has_alpha, samples });
+    }
+    let width = header.number()?;
+    let height = header.number()?;
+    limits.check_dimensions(width, height)?;
+    let (depth, samples) = match magic {
+        "P1" => (1, read_ascii_bits(&mut header, sample_count(width, height, 1)?)?),
+        "P4" => (1, read_binary_bits(header.raster(), width, height)?),
+        "P2" | "P3" | "P5" | "P6" => {
+            let maxval = header.maxval()?;
+            let depth = if magic == "P2" || magic == "P5" { 1 } else { 3 };
+            let count = sample_count(width, height, depth)?;
+            if magic == "P2" || magic == "P3" {
+                (depth, read_ascii_samples(&mut header, count, maxval)?)
+            }
+            else {
+                (depth, read_binary_samples(header.raster(), count, maxval)?)
+            }
+        },
+        _ => return Err(invalid_data("unknown netpbm magic number")),
+    };
+    Ok(Raster { width, height, depth, has_alpha: false, samples })
+}
+
+fn raster_to_rgba(raster: &Raster) -> Vec<image::RGBA> {
+    raster.samples.chunks(raster.depth).map(|sample| {
+        let alpha = if raster.has_alpha { sample[raster.depth - 1] } else { 255 };
+        match (raster.depth, raster.has_alpha) {
+            (1, _) | (2, true) => image::RGBA { red: sample[0], green: sample[0], blue: sample[0], alpha },
+            _ => image::RGBA { red: sample[0], green: sample[1], blue: sample[2], alpha },
+        }
+    }).collect()
+}
+
+pub fn read(path: &str) -> Result<image::ColorImage, std::io::Error> {
+    decode(&fs::read(path)?)
+}
+
+/// Decodes any of the netpbm formats, discarding alpha.
+pub fn decode(bytes: &[u8]) -> Result<image::ColorImage, std::io::Error> {
+    decode_with_limits(bytes, &Limits::default())
+}
+
+/// Decodes any of the netpbm formats, failing with `LimitExceeded` if the dimensions go past the limits.
+pub fn decode_with_limits(bytes: &[u8], limits: &Limits) -> Result<image::ColorImage, std::io::Error> {
+    let raster = decode_raster(bytes, limits)?;
+    let pixels: Vec<image::RGB> = raster_to_rgba(&raster).iter().map(|pixel| {
+        image::RGB { red: pixel.red, green: pixel.green, blue: pixel.blue }
+    }).collect();
+    Ok(image_from_rgb(raster.width, raster.height, &pixels))
+}
+
+/// Decodes a graymap or bitmap.
+pub fn decode_greyscale(bytes: &[u8]) -> Result<image::GreyScaleImage, std::io::Error> {
+    let raster = decode_raster(bytes, &Limits::default())?;
+    if raster.depth != 1 {
+        return Err(invalid_data("netpbm image is not greyscale"));
+    }
+    let mut image = image::GreyScaleImage::new(raster.width, raster.height);
+    image.canvas.pixels = raster.samples;
+    Ok(image)
+}
+
+/// Decodes any of the netpbm formats, pixels without alpha are opaque.
+pub fn decode_rgba(bytes: &[u8]) -> Result<image::Canvas<image::RGBA>, std::io::Error> {
+    let raster = decode_raster(bytes, &Limits::default())?;
+    Ok(image::Canvas {
+        width: raster.width,
+        height: raster.height,
+        pixels: raster_to_rgba(&raster),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn test_image() -> image::ColorImage {
+        let mut image = image::ColorImage::new(23, 5, image::RGB { red: 0, green: 0, blue: 0 });
+        for x in 0..23 {
+            for y in 0..5 {
+                image.set_pixel(x, y, image::RGB { red: (x * 11) as u8, green: (y * 50) as u8, blue: ((x + y) % 2) as u8 * 255 });
+            }
+        }
+        image
+    }
+    #[test]
+    fn ppm_round_trips_in_both_encodings() {
+        for encoding in [Encoding::Ascii, Encoding::Binary] {
+            let ppm = PpmImage { image: test_image(), encoding };
+            let decoded = decode(&ppm.encode()).unwrap();
+            for x in 0..23 {
+                for y in 0..5 {
+                    assert_eq!(decoded.pixel(x, y), ppm.image.pixel(x, y));
+                }
+            }
+        }
+    }
+    #[test]
+    fn ascii_lines_are_limited_to_70_characters() {
+        let ppm = PpmImage { image: test_image(), encoding: Encoding::Ascii };
+        let data = ppm.encode();
+        for line in String::from_utf8(data).unwrap().lines() {
+            assert!(line.len() <= MAX_LINE_LENGTH);
+        }
+    }
+    #[test]
+    fn pbm_round_trips_in_both_encodings() {
+        for encoding in [Encoding::Ascii, Encoding::Binary] {
+            let pbm = PbmImage { image: test_image(), encoding };
+            let decoded = decode(&pbm.encode()).unwrap();
+            for x in 0..23 {
+                for y in 0..5 {
+                    let expected = if luminance(&pbm.image.pixel(x, y)) < 128 { 0 } else { 255 };
+                    assert_eq!(decoded.pixel(x, y), image::RGB { red: expected, green: expected, blue: expected });
+                }
+            }
+        }
+    }
+    #[test]
+    fn pgm_round_trips_in_both_encodings() {
+        for encoding in [Encoding::Ascii, Encoding::Binary] {
+            let mut image = image::GreyScaleImage::new(40, 3);
+            for x in 0..40 {
+                for y in 0..3 {
+                    image.set_pixel(x, y, (x * 6 + y) as u8);
+                }
+            }
+            let pgm = PgmImage { image, encoding };
+            let decoded = decode_greyscale(&pgm.encode()).unwrap();
+            assert_eq!(decoded.canvas.pixels, pgm.image.canvas.pixels);
+        }
+    }
+    #[test]
+    fn pam_round_trips_alpha() {
+        let mut canvas = image::Canvas::new(4, 3);
+        canvas.set_pixel(2, 1, image::RGBA { red: 1, green: 2, blue: 3, alpha: 4 });
+        let pam = PamImage { canvas };
+        let decoded = decode_rgba(&pam.encode()).unwrap();
+        assert_eq!(decoded.pixels, pam.canvas.pixels);
+    }
+    #[test]
+    fn decode_handles_comments_and_sixteen_bit_samples() {
+        let mut data = Vec::from("P5 # a comment\n2 # width\n1\n65535\n".as_bytes());
+        data.extend(&[0xff, 0xff, 0x80, 0x00]);
+        let image = decode_greyscale(&data).unwrap();
+        assert_eq!(image.canvas.pixels, vec![255, 128]);
+    }
+    #[test]
+    fn decode_reads_ascii_bitmap_without_separators() {
+        let image = decode_greyscale("P1\n4 2\n0110\n1 0 0 1\n".as_bytes()).unwrap();
+        assert_eq!(image.canvas.pixels, vec![255, 0, 0, 255, 0, 255, 255, 0]);
+    }
+    #[test]
+    fn decode_rejects_headers_the_data_cannot_fill() {
+        let error = decode("P2\n100000 100000\n255\n1 2 3".as_bytes()).err().unwrap();
+        assert_eq!(error.to_string(), "unexpected end of netpbm header");
+        assert!(decode("P5\n100000 100000\n65535\n\x01\x02".as_bytes()).is_err());
+        let pam = "P7\nWIDTH 1\nHEIGHT 1\nDEPTH 2\nMAXVAL 255\nTUPLTYPE RGB\nENDHDR\n\x01\x02";
+        assert_eq!(decode(pam.as_bytes()).err().unwrap().to_string(), "pam tuple type does not match its depth");
+        let pam = "P7\nWIDTH 1\nHEIGHT 1\nDEPTH 2\nMAXVAL 255\nENDHDR\n\x01\x02";
+        assert_eq!(decode_rgba(pam.as_bytes()).unwrap().pixels, vec![image::RGBA { red: 1, green: 1, blue: 1, alpha: 2 }]);
+    }
+}
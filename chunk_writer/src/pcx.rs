@@ -0,0 +1,216 @@
+use super::*;
+use image;
+
+const HEADER_SIZE: usize = 128;
+pub(crate) const MANUFACTURER: u8 = 0x0a;
+// version 3.0 and later, which supports the trailing 256 colour palette
+const VERSION: u8 = 5;
+const RLE_ENCODING: u8 = 1;
+const PALETTE_SENTINEL: u8 = 0x0c;
+const PALETTE_SIZE: usize = 256 * 3;
+// bytes with the top two bits set are run counts
+const RUN_FLAG: u8 = 0xc0;
+const MAX_RUN: usize = 63;
+
+fn encode_rle(line: &[u8], data: &mut Vec<u8>) {
+    let mut i = 0;
+    while i < line.len() {
+        let mut run = 1;
+        while i + run < line.len() && run < MAX_RUN && line[i + run] == line[i] {
+            run += 1;
+        }
+        // single bytes that look like a run count have to be written as a run of one
+        if run > 1 || line[i] & RUN_FLAG == RUN_FLAG {
+            data.push(RUN_FLAG | run as u8);
+        }
+        data.push(line[i]);
+        i += run;
+    }
+}
+
+/// An 8 bit paletted pcx image, with the palette stored after the pixel data.
+pub struct PcxImage {
+    pub image: image::ColorImage,
+}
+impl PcxImage {
+    pub fn encode(&self) -> Result<Vec<u8>, std::io::Error> {
+        let width = self.image.canvas.width;
+        let height = self.image.canvas.height;
+        if width == 0 || height == 0 || width > 0x10000 || height > 0x10000 {
+            return Err(invalid_input("pcx images must be between 1 and 65536 pixels across"));
+        }
+        if self.image.palette.size() > 256 {
+            return Err(invalid_input("pcx cannot have more than 256 colours"));
+        }
+        // scanlines are padded to an even number of bytes, which leaves 65534 as the widest that fits
+        let bytes_per_line = width as usize + width as usize % 2;
+        if bytes_per_line > u16::MAX as usize {
+            return Err(invalid_input("pcx scanlines cannot be wider than 65534 pixels"));
+        }
+
+        let mut data = vec![0; HEADER_SIZE];
+        data[0] = MANUFACTURER;
+        data[1] = VERSION;
+        data[2] = RLE_ENCODING;
+        // bits per pixel per plane
+        data[3] = 8;
+        // window from 0, 0 to the inclusive bottom right
+        data[8..10].copy_from_slice(&((width - 1) as u16).to_le_bytes());
+        data[10..12].copy_from_slice(&((height - 1) as u16).to_le_bytes());
+        // 72 dpi
+        data[12..14].copy_from_slice(&72u16.to_le_bytes());
+        data[14..16].copy_from_slice(&72u16.to_le_bytes());
+        // a single plane of palette indices
+        data[65] = 1;
+        data[66..68].copy_from_slice(&(bytes_per_line as u16).to_le_bytes());
+        // palette is colour rather than greyscale
+        data[68..70].copy_from_slice(&1u16.to_le_bytes());
+
+        for y in 0..height {
+            let mut line = vec![0; bytes_per_line];
+            for x in 0..width {
+                line[x as usize] = *self.image.canvas.pixel(x, y) as u8;
+            }
+            encode_rle(&line, &mut data);
+        }
+
+        data.push(PALETTE_SENTINEL);
+        let mut palette = vec![0; PALETTE_SIZE];
+        for (i, color) in palette_colors(&self.image.palette).iter().enumerate() {
+            palette[i * 3] = color.red;
+            palette[i * 3 + 1] = color.green;
+            palette[i * 3 + 2] = color.blue;
+        }
+        data.extend(palette);
+        Ok(data)
+    }
+}
+impl Image for PcxImage {
+    fn write(self, path: &str) -> Result<fs::File, std::io::Error> {
+        let data = self.encode()?;
+        let mut file_image = fs::File::create(path)?;
+        file_image.write_all(&data)?;
+        Ok(file_image)
+    }
+}
+
+pub fn read(path: &str) -> Result<image::ColorImage, std::io::Error> {
+    decode(&fs::read(path)?)
+}
+
+/// Decodes 8 bit pcx images, either paletted with a single plane or true colour with three.
+pub fn decode(bytes: &[u8]) -> Result<image::ColorImage, std::io::Error> {
+    decode_with_limits(bytes, &Limits::default())
+}
+
+/// Decodes a pcx, failing with `LimitExceeded` if its dimensions go past the limits.
+pub fn decode_with_limits(bytes: &[u8], limits: &Limits) -> Result<image::ColorImage, std::io::Error> {
+    if read_u8(bytes, 0)? != MANUFACTURER {
+        return Err(invalid_data("missing pcx manufacturer byte"));
+    }
+    let encoding = read_u8(bytes, 2)?;
+    let bits_per_pixel = read_u8(bytes, 3)?;
+    let x_min = read_le_u16(bytes, 4)? as usize;
+    let y_min = read_le_u16(bytes, 6)? as usize;
+    let x_max = read_le_u16(bytes, 8)? as usize;
+    let y_max = read_le_u16(bytes, 10)? as usize;
+    let planes = read_u8(bytes, 65)? as usize;
+    let bytes_per_line = read_le_u16(bytes, 66)? as usize;
+    if x_max < x_min || y_max < y_min {
+        return Err(invalid_data("pcx window is empty"));
+    }
+    let width = x_max - x_min + 1;
+    let height = y_max - y_min + 1;
+    limits.check_dimensions(width as u32, height as u32)?;
+    if bits_per_pixel != 8 || (planes != 1 && planes != 3) {
+        return Err(invalid_data("only 8 bit paletted or 24 bit pcx images are supported"));
+    }
+    if bytes_per_line < width {
+        return Err(invalid_data("pcx scanlines are shorter than the image width"));
+    }
+
+    // runs may cross planes but the spec asks for them to stop at the end of each scanline
+    let line_length = bytes_per_line * planes;
+    let expected_length = line_length * height;
+    let mut decoded = Vec::with_capacity(expected_length.min(bytes.len() * MAX_RUN));
+    let mut offset = HEADER_SIZE;
+    while decoded.len() < expected_length {
+        let byte = read_u8(bytes, offset)?;
+        offset += 1;
+        if encoding == RLE_ENCODING && byte & RUN_FLAG == RUN_FLAG {
+            let value = read_u8(bytes, offset)?;
+            offset += 1;
+            for _ in 0..(byte & !RUN_FLAG) {
+                decoded.push(value);
+            }
+        }
+        else {
+            decoded.push(byte);
+        }
+    }
+
+    if planes == 3 {
+        let mut pixels = Vec::with_capacity(width * height);
+        for line in decoded.chunks(line_length).take(height) {
+            for x in 0..width {
+                pixels.push(image::RGB { red: line[x], green: line[bytes_per_line + x], blue: line[2 * bytes_per_line + x] });
+            }
+        }
+        return Ok(image_from_rgb(width as u32, height as u32, &pixels));
+    }
+    let palette_offset = bytes.len().saturating_sub(PALETTE_SIZE + 1);
+    if palette_offset < offset || bytes[palette_offset] != PALETTE_SENTINEL {
+        return Err(invalid_data("pcx is missing its 256 colour palette"));
+    }
+    let colors: Vec<image::RGB> = bytes[palette_offset + 1..].chunks(3).map(|entry| {
+        image::RGB { red: entry[0], green: entry[1], blue: entry[2] }
+    }).collect();
+    let mut indices = Vec::with_capacity(width * height);
+    for line in decoded.chunks(line_length).take(height) {
+        indices.extend(line[..width].iter().map(|index| *index as usize));
+    }
+    image_from_indices(width as u32, height as u32, &colors, indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn encode_rle_escapes_bytes_that_look_like_runs() {
+        let mut data = Vec::new();
+        encode_rle(&[1, 0xc5, 2, 2, 2], &mut data);
+        assert_eq!(data, vec![1, 0xc1, 0xc5, 0xc3, 2]);
+    }
+    #[test]
+    fn encode_rle_splits_long_runs() {
+        let mut data = Vec::new();
+        encode_rle(&[9; 100], &mut data);
+        assert_eq!(data, vec![0xff, 9, 0xc0 | 37, 9]);
+    }
+    #[test]
+    fn pcx_round_trips_odd_widths() {
+        let mut image = image::ColorImage::new(33, 12, image::RGB { red: 0, green: 0, blue: 0 });
+        for x in 0..33 {
+            for y in 0..12 {
+                let value = ((x / 3) * 20 + y) as u8;
+                image.set_pixel(x, y, image::RGB { red: value, green: value / 2, blue: 200 });
+            }
+        }
+        let pcx = PcxImage { image };
+        let data = pcx.encode().unwrap();
+        assert_eq!(data[data.len() - PALETTE_SIZE - 1], PALETTE_SENTINEL);
+        let decoded = decode(&data).unwrap();
+        for x in 0..33 {
+            for y in 0..12 {
+                assert_eq!(decoded.pixel(x, y), pcx.image.pixel(x, y));
+            }
+        }
+    }
+    #[test]
+    fn encode_stops_where_bytes_per_line_overflows() {
+        let pcx = PcxImage { image: image::ColorImage::new(65535, 1, image::RGB::default()) };
+        assert_eq!(pcx.encode().err().unwrap().kind(), std::io::ErrorKind::InvalidInput);
+        let pcx = PcxImage { image: image::ColorImage::new(65534, 1, image::RGB::default()) };
+        assert_eq!(decode(&pcx.encode().unwrap()).unwrap().canvas.width, 65534);
+    }
+}
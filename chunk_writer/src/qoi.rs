@@ -0,0 +1,269 @@
+use super::*;
+use image;
+
+pub(crate) const QOI_SIGNATURE: [u8; 4] = [0x71, 0x6f, 0x69, 0x66];
+const HEADER_SIZE: usize = 14;
+const END_MARKER: [u8; 8] = [0, 0, 0, 0, 0, 0, 0, 1];
+// channels are stored as sRGB with linear alpha
+const SRGB_COLORSPACE: u8 = 0;
+
+const OP_INDEX: u8 = 0x00;
+const OP_DIFF: u8 = 0x40;
+const OP_LUMA: u8 = 0x80;
+const OP_RUN: u8 = 0xc0;
+const OP_RGB: u8 = 0xfe;
+const OP_RGBA: u8 = 0xff;
+const OP_MASK: u8 = 0xc0;
+const MAX_RUN: u8 = 62;
+
+fn color_hash(pixel: &image::RGBA) -> usize {
+    (pixel.red as usize * 3 + pixel.green as usize * 5 + pixel.blue as usize * 7 + pixel.alpha as usize * 11) % 64
+}
+
+fn encode_pixels(width: u32, height: u32, channels: u8, pixels: &[image::RGBA]) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend(&QOI_SIGNATURE);
+    data.extend(&int_to_bytes(width));
+    data.extend(&int_to_bytes(height));
+    data.push(channels);
+    data.push(SRGB_COLORSPACE);
+
+    let mut index = [image::RGBA::default(); 64];
+    let mut previous = image::RGBA { red: 0, green: 0, blue: 0, alpha: 255 };
+    let mut run = 0;
+    for (i, pixel) in pixels.iter().enumerate() {
+        if *pixel == previous {
+            run += 1;
+            if run == MAX_RUN || i == pixels.len() - 1 {
+                data.push(OP_RUN | (run - 1));
+                run = 0;
+            }
+            continue;
+        }
+        if run > 0 {
+            data.push(OP_RUN | (run - 1));
+            run = 0;
+        }
+        let hash = color_hash(pixel);
+        if index[hash] == *pixel {
+            data.push(OP_INDEX | hash as u8);
+        }
+        else {
+            index[hash] = *pixel;
+            if pixel.alpha == previous.alpha {
+                // differences wrap around, so 0 - 255 is a difference of 1
+                let red = pixel.red.wrapping_sub(previous.red) as i8;
+                let green = pixel.green.wrapping_sub(previous.green) as i8;
+                let blue = pixel.blue.wrapping_sub(previous.blue) as i8;
+                let red_green = red.wrapping_sub(green);
+                let blue_green = blue.wrapping_sub(green);
+                if (-2..=1).contains(&red) && (-2..=1).contains(&green) && (-2..=1).contains(&blue) {
+                    data.push(OP_DIFF | ((red + 2) as u8) << 4 | ((green + 2) as u8) << 2 | (blue + 2) as u8);
+                }
+                else if (-32..=31).contains(&green) && (-8..=7).contains(&red_green) && (-8..=7).contains(&blue_green) {
+                    data.push(OP_LUMA | (green + 32) as u8);
+                    data.push(((red_green + 8) as u8) << 4 | (blue_green + 8) as u8);
+                }
+                else {
+                    data.extend(&[OP_RGB, pixel.red, pixel.green, pixel.blue]);
+                }
+            }
+            else {
+                data.extend(&[OP_RGBA, pixel.red, pixel.green, pixel.blue, pixel.alpha]);
+            }
+        }
+        previous = *pixel;
+    }
+    data.extend(&END_MARKER);
+    data
+}
+
+pub struct QoiImage {
+    pub image: image::ColorImage,
+}
+impl QoiImage {
+    pub fn encode(&self) -> Vec<u8> {
+        let pixels: Vec<image::RGBA> = self.image.canvas.pixels.iter().map(|index| {
+            let color = self.image.palette.color(*index).unwrap();
+            image::RGBA { red: color.red, green: color.green, blue: color.blue, alpha: 255 }
+        }).collect();
+        encode_pixels(self.image.canvas.width, self.image.canvas.height, 3, &pixels)
+    }
+}
+impl Image for QoiImage {
+    fn write(self, path: &str) -> Result<fs::File, std::io::Error> {
+        let mut file_image = fs::File::create(path)?;
+        file_image.write_all(&self.encode())?;
+        Ok(file_image)
+    }
+}
+
+pub struct QoiRgbaImage {
+    pub canvas: image::Canvas<image::RGBA>,
+}
+impl QoiRgbaImage {
+    pub fn encode(&self) -> Vec<u8> {
+        encode_pixels(self.canvas.width, self.canvas.height, 4, &self.canvas.pixels)
+    }
+}
+impl Image for QoiRgbaImage {
+    fn write(self, path: &str) -> Result<fs::File, std::io::Error> {
+        let mut file_image = fs::File::create(path)?;
+        file_image.write_all(&self.encode())?;
+        Ok(file_image)
+    }
+}
+
+pub fn read(path: &str) -> Result<image::ColorImage, std::io::Error> {
+    decode(&fs::read(path)?)
+}
+
+/// Decodes a qoi image of either channel count, discarding alpha.
+pub fn decode(bytes: &[u8]) -> Result<image::ColorImage, std::io::Error> {
+    decode_with_limits(bytes, &Limits::default())
+}
+
+pub fn decode_with_limits(bytes: &[u8], limits: &Limits) -> Result<image::ColorImage, std::io::Error> {
+    let canvas = decode_rgba_with_limits(bytes, limits)?;
+    let pixels: Vec<image::RGB> = canvas.pixels.iter().map(|pixel| {
+        image::RGB { red: pixel.red, green: pixel.green, blue: pixel.blue }
+    }).collect();
+    Ok(image_from_rgb(canvas.width, canvas.height, &pixels))
+}
+
+pub fn decode_rgba(bytes: &[u8]) -> Result<image::Canvas<image::RGBA>, std::io::Error> {
+    decode_rgba_with_limits(bytes, &Limits::default())
+}
+
+/// Decodes a qoi, failing with `LimitExceeded` if its dimensions go past the limits.
+pub fn decode_rgba_with_limits(bytes: &[u8], limits: &Limits) -> Result<image::Canvas<image::RGBA>, std::io::Error> {
+    if read_bytes(bytes, 0, 4)? != QOI_SIGNATURE {
+        return Err(invalid_data("missing qoi signature"));
+    }
+    let width = read_be_u32(bytes, 4)?;
+    let height = read_be_u32(bytes, 8)?;
+    limits.check_dimensions(width, height)?;
+    let channels = read_u8(bytes, 12)?;
+    if channels != 3 && channels != 4 {
+        return Err(invalid_data("qoi images must have 3 or 4 channels"));
+    }
+    let pixel_count = match (width as usize).checked_mul(height as usize) {
+        Some(pixel_count) => pixel_count,
+        None => return Err(invalid_data("qoi dimensions are too large")),
+    };
+    // every chunk is at least one byte, so the pixel count cannot exceed the data size by more than the longest run
+    if pixel_count / MAX_RUN as usize > bytes.len() {
+        return Err(invalid_data("qoi dimensions are larger than the data could describe"));
+    }
+
+    let mut pixels = Vec::with_capacity(pixel_count);
+    let mut index = [image::RGBA::default(); 64];
+    let mut pixel = image::RGBA { red: 0, green: 0, blue: 0, alpha: 255 };
+    let mut offset = HEADER_SIZE;
+    while pixels.len() < pixel_count {
+        let op = read_u8(bytes, offset)?;
+        offset += 1;
+        let mut run = 1;
+        if op == OP_RGB {
+            let rgb = read_bytes(bytes, offset, 3)?;
+            pixel = image::RGBA { red: rgb[0], green: rgb[1], blue: rgb[2], alpha: pixel.alpha };
+            offset += 3;
+        }
+        else if op == OP_RGBA {
+            let rgba = read_bytes(bytes, offset, 4)?;
+            pixel = image::RGBA { red: rgba[0], green: rgba[1], blue: rgba[2], alpha: rgba[3] };
+            offset += 4;
+        }
+        else {
+            match op & OP_MASK {
+                OP_INDEX => pixel = index[op as usize],
+                OP_DIFF => {
+                    pixel.red = pixel.red.wrapping_add((op >> 4) & 0x03).wrapping_sub(2);
+                    pixel.green = pixel.green.wrapping_add((op >> 2) & 0x03).wrapping_sub(2);
+                    pixel.blue = pixel.blue.wrapping_add(op & 0x03).wrapping_sub(2);
+                },
+                OP_LUMA => {
+                    let second = read_u8(bytes, offset)?;
+                    offset += 1;
+                    let green = (op & 0x3f).wrapping_sub(32);
+                    pixel.red = pixel.red.wrapping_add(green).wrapping_add(second >> 4).wrapping_sub(8);
+                    pixel.green = pixel.green.wrapping_add(green);
+                    pixel.blue = pixel.blue.wrapping_add(green).wrapping_add(second & 0x0f).wrapping_sub(8);
+                },
+                _ => run = (op & 0x3f) as usize + 1,
+            }
+        }
+        index[color_hash(&pixel)] = pixel;
+        for _ in 0..run.min(pixel_count - pixels.len()) {
+            pixels.push(pixel);
+        }
+    }
+    if read_bytes(bytes, offset, END_MARKER.len())? != END_MARKER {
+        return Err(invalid_data("missing qoi end marker"));
+    }
+    Ok(image::Canvas { width, height, pixels })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn encode_matches_reference_chunks() {
+        let mut image = image::ColorImage::new(2, 2, image::RGB { red: 0, green: 0, blue: 0 });
+        image.set_pixel(0, 1, image::RGB { red: 1, green: 1, blue: 1 });
+        image.set_pixel(1, 1, image::RGB { red: 255, green: 0, blue: 0 });
+        let data = QoiImage { image }.encode();
+        assert_eq!(data, vec![
+            0x71, 0x6f, 0x69, 0x66, 0, 0, 0, 2, 0, 0, 0, 2, 3, 0,
+            // run of two, difference of +1 on every channel, difference of -2, -1, -1
+            0xc1, 0x7f, 0x45,
+            0, 0, 0, 0, 0, 0, 0, 1,
+        ]);
+    }
+    #[test]
+    fn encode_uses_index_luma_and_long_runs() {
+        let mut canvas = image::Canvas::new(100, 1);
+        for x in 0..100 {
+            canvas.set_pixel(x, 0, image::RGBA { red: 10, green: 20, blue: 30, alpha: 255 });
+        }
+        canvas.set_pixel(80, 0, image::RGBA { red: 27, green: 30, blue: 35, alpha: 255 });
+        let data = QoiRgbaImage { canvas }.encode();
+        assert_eq!(&data[HEADER_SIZE..data.len() - END_MARKER.len()], &[
+            // red +10, green +20, blue +30 is too far for luma
+            OP_RGB, 10, 20, 30,
+            // the remaining 79 pixels before the change are split into runs of 62 and 17
+            OP_RUN | 61, OP_RUN | 16,
+            // green +10 with red 7 further and blue 5 less than green
+            OP_LUMA | 42, 0xf3,
+            OP_INDEX | color_hash(&image::RGBA { red: 10, green: 20, blue: 30, alpha: 255 }) as u8,
+            OP_RUN | 17,
+        ][..]);
+    }
+    #[test]
+    fn qoi_round_trips_rgb_and_rgba() {
+        let mut image = image::ColorImage::new(37, 19, image::RGB { red: 0, green: 0, blue: 0 });
+        let mut canvas = image::Canvas::new(37, 19);
+        for x in 0..37 {
+            for y in 0..19 {
+                let color = image::RGB { red: (x * 7) as u8, green: (y * x) as u8, blue: (x / 4 + y) as u8 };
+                image.set_pixel(x, y, color);
+                canvas.set_pixel(x, y, image::RGBA { red: color.red, green: color.green, blue: color.blue, alpha: (y * 13) as u8 });
+            }
+        }
+        let decoded = decode(&QoiImage { image }.encode()).unwrap();
+        let decoded_rgba = decode_rgba(&QoiRgbaImage { canvas }.encode()).unwrap();
+        for x in 0..37 {
+            for y in 0..19 {
+                let rgba = decoded_rgba.pixel(x, y);
+                assert_eq!(decoded.pixel(x, y), image::RGB { red: rgba.red, green: rgba.green, blue: rgba.blue });
+                assert_eq!(rgba.alpha, (y * 13) as u8);
+            }
+        }
+    }
+    #[test]
+    fn decode_rejects_missing_end_marker() {
+        let image = image::ColorImage::new(3, 3, image::RGB { red: 4, green: 5, blue: 6 });
+        let data = QoiImage { image }.encode();
+        assert!(decode(&data[..data.len() - 1]).is_err());
+    }
+}
@@ -0,0 +1,145 @@
+use super::*;
+use image;
+use std::fmt::Write;
+
+// device control string introducing sixel data, and the string terminator closing it
+const SIXEL_START: &str = "\x1bPq";
+const SIXEL_END: &str = "\x1b\\";
+// sixels are offset from this character by the six bits they set
+const SIXEL_OFFSET: u8 = 0x3f;
+// repeats shorter than this are cheaper to write out
+const MIN_SIXEL_REPEAT: usize = 4;
+const MAX_COLOR_REGISTERS: usize = 256;
+const UPPER_HALF_BLOCK: char = '\u{2580}';
+const RESET: &str = "\x1b[0m";
+
+fn percent(channel: u8) -> u32 {
+    (channel as u32 * 100 + 127) / 255
+}
+
+fn push_sixel_run(sixel: u8, count: usize, output: &mut String) {
+    let character = (SIXEL_OFFSET + sixel) as char;
+    if count >= MIN_SIXEL_REPEAT {
+        write!(output, "!{}{}", count, character).unwrap();
+    }
+    else {
+        for _ in 0..count {
+            output.push(character);
+        }
+    }
+}
+
+/// Renders the image as dec sixel graphics, with each palette index used as the colour register of the same number.
+pub fn sixel(image: &image::ColorImage) -> Result<String, std::io::Error> {
+    let colors = palette_colors(&image.palette);
+    if colors.len() > MAX_COLOR_REGISTERS {
+        return Err(invalid_input("sixel cannot have more than 256 colour registers"));
+    }
+    let width = image.canvas.width;
+    let height = image.canvas.height;
+    // writing to a string cannot fail
+    let mut output = String::from(SIXEL_START);
+    // square pixels and the image size
+    write!(output, "\"1;1;{};{}", width, height).unwrap();
+    for (index, color) in colors.iter().enumerate() {
+        write!(output, "#{};2;{};{};{}", index, percent(color.red), percent(color.green), percent(color.blue)).unwrap();
+    }
+
+    // each band covers six rows, drawn once per colour with a carriage return between them
+    for band in 0..height.div_ceil(6) {
+        let rows = (band * 6)..(band * 6 + 6).min(height);
+        let mut band_sixels = vec![vec![0u8; width as usize]; colors.len()];
+        for y in rows.clone() {
+            for x in 0..width {
+                band_sixels[*image.canvas.pixel(x, y)][x as usize] |= 1 << (y - rows.start);
+            }
+        }
+        let mut first = true;
+        for (index, sixels) in band_sixels.iter().enumerate() {
+            if sixels.iter().all(|sixel| *sixel == 0) {
+                continue;
+            }
+            if !first {
+                output.push('$');
+            }
+            first = false;
+            write!(output, "#{}", index).unwrap();
+            let mut x = 0;
+            while x < sixels.len() {
+                let mut run = 1;
+                while x + run < sixels.len() && sixels[x + run] == sixels[x] {
+                    run += 1;
+                }
+                push_sixel_run(sixels[x], run, &mut output);
+                x += run;
+            }
+        }
+        output.push('-');
+    }
+    output.push_str(SIXEL_END);
+    Ok(output)
+}
+
+/// Renders the image as ansi 24 bit colour text, with each character showing two rows through an upper half block.
+pub fn half_blocks(image: &image::ColorImage) -> String {
+    let mut output = String::new();
+    for y in (0..image.canvas.height).step_by(2) {
+        let mut foreground = None;
+        let mut background = None;
+        for x in 0..image.canvas.width {
+            let upper = image.pixel(x, y);
+            if foreground != Some(upper) {
+                write!(output, "\x1b[38;2;{};{};{}m", upper.red, upper.green, upper.blue).unwrap();
+                foreground = Some(upper);
+            }
+            // an odd final row leaves the lower half as the terminal background
+            if y + 1 < image.canvas.height {
+                let lower = image.pixel(x, y + 1);
+                if background != Some(lower) {
+                    write!(output, "\x1b[48;2;{};{};{}m", lower.red, lower.green, lower.blue).unwrap();
+                    background = Some(lower);
+                }
+            }
+            output.push(UPPER_HALF_BLOCK);
+        }
+        output.push_str(RESET);
+        output.push('\n');
+    }
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const RED: image::RGB = image::RGB { red: 255, green: 0, blue: 0 };
+    const BLUE: image::RGB = image::RGB { red: 0, green: 0, blue: 255 };
+    #[test]
+    fn sixel_draws_each_colour_in_every_band() {
+        // red with a blue bottom row, which falls in the second band
+        let mut image = image::ColorImage::new(5, 7, RED);
+        for x in 0..5 {
+            image.set_pixel(x, 6, BLUE);
+        }
+        image.set_pixel(1, 0, BLUE);
+        let expected = "\x1bPq\"1;1;5;7#0;2;100;0;0#1;2;0;0;100#0~}~~~$#1?@???-#1!5@-\x1b\\";
+        assert_eq!(sixel(&image).unwrap(), expected);
+    }
+    #[test]
+    fn sixel_rejects_too_many_colours() {
+        let mut image = image::ColorImage::new(300, 1, RED);
+        for x in 0..300 {
+            image.set_pixel(x, 0, image::RGB { red: x as u8, green: (x >> 8) as u8, blue: 0 });
+        }
+        assert_eq!(sixel(&image).unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+    }
+    #[test]
+    fn half_blocks_only_change_colours_when_needed() {
+        let mut image = image::ColorImage::new(3, 3, RED);
+        image.set_pixel(2, 1, BLUE);
+        let expected = concat!(
+            "\x1b[38;2;255;0;0m\x1b[48;2;255;0;0m\u{2580}\u{2580}\x1b[48;2;0;0;255m\u{2580}\x1b[0m\n",
+            "\x1b[38;2;255;0;0m\u{2580}\u{2580}\u{2580}\x1b[0m\n",
+        );
+        assert_eq!(half_blocks(&image), expected);
+    }
+}
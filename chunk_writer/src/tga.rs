@@ -0,0 +1,294 @@
+use super::*;
+use image;
+
+const HEADER_SIZE: usize = 18;
+pub(crate) const FOOTER_SIGNATURE: &[u8; 18] = b"TRUEVISION-XFILE.\0";
+
+const TYPE_COLOR_MAPPED: u8 = 1;
+const TYPE_TRUE_COLOR: u8 = 2;
+const TYPE_GREYSCALE: u8 = 3;
+// run-length encoded types have the 8 bit set
+const TYPE_RLE_FLAG: u8 = 8;
+
+// image descriptor bits for the origin
+const RIGHT_TO_LEFT: u8 = 0x10;
+const TOP_TO_BOTTOM: u8 = 0x20;
+
+const MAX_PACKET_LENGTH: usize = 128;
+
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum ColorType {
+    Paletted,
+    TrueColor,
+}
+
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum Origin {
+    BottomLeft,
+    TopLeft,
+}
+
+// pixels are written as 1 byte palette indices or 3 byte BGR values
+fn encode_rle(pixels: &[Vec<u8>], data: &mut Vec<u8>) {
+    let mut i = 0;
+    while i < pixels.len() {
+        let mut run = 1;
+        while i + run < pixels.len() && run < MAX_PACKET_LENGTH && pixels[i + run] == pixels[i] {
+            run += 1;
+        }
+        if run > 1 {
+            data.push(0x80 | (run - 1) as u8);
+            data.extend(&pixels[i]);
+            i += run;
+        }
+        else {
+            let start = i;
+            i += 1;
+            while i < pixels.len() && i - start < MAX_PACKET_LENGTH && !(i + 1 < pixels.len() && pixels[i] == pixels[i + 1]) {
+                i += 1;
+            }
+            data.push((i - start - 1) as u8);
+            for pixel in &pixels[start..i] {
+                data.extend(pixel);
+            }
+        }
+    }
+}
+
+pub struct TgaImage {
+    pub image: image::ColorImage,
+    pub color_type: ColorType,
+    pub rle: bool,
+    pub origin: Origin,
+}
+impl TgaImage {
+    pub fn encode(&self) -> Result<Vec<u8>, std::io::Error> {
+        let width = self.image.canvas.width;
+        let height = self.image.canvas.height;
+        if width > 0xffff || height > 0xffff {
+            return Err(invalid_input("tga images cannot be larger than 65535 pixels across"));
+        }
+        let paletted = self.color_type == ColorType::Paletted;
+        if paletted && self.image.palette.size() > 256 {
+            return Err(invalid_input("paletted tga cannot have more than 256 colours"));
+        }
+        let colors = if paletted { palette_colors(&self.image.palette) } else { Vec::new() };
+
+        let mut data = Vec::new();
+        // no image id
+        data.push(0);
+        data.push(if paletted { 1 } else { 0 });
+        let image_type = if paletted { TYPE_COLOR_MAPPED } else { TYPE_TRUE_COLOR };
+        data.push(if self.rle { image_type | TYPE_RLE_FLAG } else { image_type });
+        // colour map starting at index 0 with 24 bit entries
+        data.extend(&0u16.to_le_bytes());
+        data.extend(&(colors.len() as u16).to_le_bytes());
+        data.push(if paletted { 24 } else { 0 });
+        // x and y origin
+        data.extend(&[0; 4]);
+        data.extend(&(width as u16).to_le_bytes());
+        data.extend(&(height as u16).to_le_bytes());
+        data.push(if paletted { 8 } else { 24 });
+        data.push(match self.origin {
+            Origin::BottomLeft => 0,
+            Origin::TopLeft => TOP_TO_BOTTOM,
+        });
+        for color in colors {
+            data.extend(&[color.blue, color.green, color.red]);
+        }
+
+        for row in 0..height {
+            let y = match self.origin {
+                Origin::BottomLeft => height - 1 - row,
+                Origin::TopLeft => row,
+            };
+            let pixels: Vec<Vec<u8>> = (0..width).map(|x| {
+                if paletted {
+                    vec![*self.image.canvas.pixel(x, y) as u8]
+                }
+                else {
+                    let color = self.image.pixel(x, y);
+                    vec![color.blue, color.green, color.red]
+                }
+            }).collect();
+            // packets are kept within a scanline
+            if self.rle {
+                encode_rle(&pixels, &mut data);
+            }
+            else {
+                data.extend(pixels.concat());
+            }
+        }
+        // no extension or developer areas
+        data.extend(&[0; 8]);
+        data.extend(FOOTER_SIGNATURE);
+        Ok(data)
+    }
+}
+impl Image for TgaImage {
+    fn write(self, path: &str) -> Result<fs::File, std::io::Error> {
+        let data = self.encode()?;
+        let mut file_image = fs::File::create(path)?;
+        file_image.write_all(&data)?;
+        Ok(file_image)
+    }
+}
+
+fn decode_color(bytes: &[u8]) -> image::RGB {
+    match bytes.len() {
+        1 => image::RGB { red: bytes[0], green: bytes[0], blue: bytes[0] },
+        // 5 bits per channel, expanded to 8
+        2 => {
+            let value = bytes[0] as u16 | (bytes[1] as u16) << 8;
+            let expand = |channel: u16| ((channel & 0x1f) * 255 / 31) as u8;
+            image::RGB { red: expand(value >> 10), green: expand(value >> 5), blue: expand(value) }
+        },
+        _ => image::RGB { red: bytes[2], green: bytes[1], blue: bytes[0] },
+    }
+}
+
+pub fn read(path: &str) -> Result<image::ColorImage, std::io::Error> {
+    decode(&fs::read(path)?)
+}
+
+pub fn decode(bytes: &[u8]) -> Result<image::ColorImage, std::io::Error> {
+    decode_with_limits(bytes, &Limits::default())
+}
+
+/// Decodes a tga, failing with `LimitExceeded` if its dimensions go past the limits.
+pub fn decode_with_limits(bytes: &[u8], limits: &Limits) -> Result<image::ColorImage, std::io::Error> {
+    let id_length = read_u8(bytes, 0)? as usize;
+    let color_map_type = read_u8(bytes, 1)?;
+    let image_type = read_u8(bytes, 2)?;
+    let color_map_start = read_le_u16(bytes, 3)? as usize;
+    let color_map_length = read_le_u16(bytes, 5)? as usize;
+    let color_map_entry_size = read_u8(bytes, 7)? as usize;
+    let width = read_le_u16(bytes, 12)? as usize;
+    let height = read_le_u16(bytes, 14)? as usize;
+    let pixel_depth = read_u8(bytes, 16)? as usize;
+    let descriptor = read_u8(bytes, 17)?;
+    limits.check_dimensions(width as u32, height as u32)?;
+
+    let rle = image_type & TYPE_RLE_FLAG != 0;
+    let base_type = image_type & !TYPE_RLE_FLAG;
+    let supported = match base_type {
+        TYPE_COLOR_MAPPED => color_map_type == 1 && pixel_depth == 8,
+        TYPE_TRUE_COLOR => [15, 16, 24, 32].contains(&pixel_depth),
+        TYPE_GREYSCALE => pixel_depth == 8,
+        _ => false,
+    };
+    if !supported {
+        return Err(invalid_data("unsupported tga image type or pixel depth"));
+    }
+
+    let mut offset = HEADER_SIZE + id_length;
+    let entry_bytes = color_map_entry_size.div_ceil(8);
+    let mut colors = Vec::new();
+    if color_map_type == 1 {
+        if ![15, 16, 24, 32].contains(&color_map_entry_size) {
+            return Err(invalid_data("unsupported tga colour map entry size"));
+        }
+        // indices below the first entry have no colour, so they are left black
+        colors.resize(color_map_start, image::RGB::default());
+        for i in 0..color_map_length {
+            colors.push(decode_color(read_bytes(bytes, offset + i * entry_bytes, entry_bytes.min(3))?));
+        }
+        offset += color_map_length * entry_bytes;
+    }
+
+    let pixel_bytes = pixel_depth.div_ceil(8);
+    let pixel_count = width * height;
+    let mut pixels: Vec<&[u8]> = Vec::with_capacity(pixel_count.min(bytes.len()));
+    while pixels.len() < pixel_count {
+        if !rle {
+            pixels.push(read_bytes(bytes, offset, pixel_bytes)?);
+            offset += pixel_bytes;
+            continue;
+        }
+        let packet = read_u8(bytes, offset)?;
+        offset += 1;
+        let count = (packet & 0x7f) as usize + 1;
+        if packet & 0x80 != 0 {
+            let pixel = read_bytes(bytes, offset, pixel_bytes)?;
+            offset += pixel_bytes;
+            for _ in 0..count {
+                pixels.push(pixel);
+            }
+        }
+        else {
+            for _ in 0..count {
+                pixels.push(read_bytes(bytes, offset, pixel_bytes)?);
+                offset += pixel_bytes;
+            }
+        }
+    }
+    pixels.truncate(pixel_count);
+
+    // reorder from the stored origin into rows from the top left
+    let position = |i: usize| {
+        let (row, column) = (i / width, i % width);
+        let y = if descriptor & TOP_TO_BOTTOM != 0 { row } else { height - 1 - row };
+        let x = if descriptor & RIGHT_TO_LEFT != 0 { width - 1 - column } else { column };
+        y * width + x
+    };
+    if base_type == TYPE_COLOR_MAPPED {
+        let mut indices = vec![0; pixel_count];
+        for (i, pixel) in pixels.iter().enumerate() {
+            indices[position(i)] = pixel[0] as usize;
+        }
+        return image_from_indices(width as u32, height as u32, &colors, indices);
+    }
+    let mut colors = vec![image::RGB::default(); pixel_count];
+    for (i, pixel) in pixels.iter().enumerate() {
+        // alpha in 32 bit pixels is dropped
+        colors[position(i)] = decode_color(&pixel[..pixel.len().min(3)]);
+    }
+    Ok(image_from_rgb(width as u32, height as u32, &colors))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn test_image() -> image::ColorImage {
+        let mut image = image::ColorImage::new(200, 9, image::RGB { red: 0, green: 0, blue: 0 });
+        for x in 0..200 {
+            for y in 0..9 {
+                let value = if x < 150 { (y * 20) as u8 } else { (x * 3 + y) as u8 };
+                image.set_pixel(x, y, image::RGB { red: value, green: 100, blue: 255 - value });
+            }
+        }
+        image
+    }
+    #[test]
+    fn tga_round_trips_every_combination() {
+        for color_type in [ColorType::Paletted, ColorType::TrueColor] {
+            for rle in [false, true] {
+                for origin in [Origin::BottomLeft, Origin::TopLeft] {
+                    let tga = TgaImage { image: test_image(), color_type, rle, origin };
+                    let decoded = decode(&tga.encode().unwrap()).unwrap();
+                    for x in 0..200 {
+                        for y in 0..9 {
+                            assert_eq!(decoded.pixel(x, y), tga.image.pixel(x, y));
+                        }
+                    }
+                }
+            }
+        }
+    }
+    #[test]
+    fn rle_packets_are_limited_to_128_pixels() {
+        let pixels = vec![vec![7]; 300];
+        let mut data = Vec::new();
+        encode_rle(&pixels, &mut data);
+        assert_eq!(data, vec![0xff, 7, 0xff, 7, 0x80 | 43, 7]);
+    }
+    #[test]
+    fn decode_reads_right_to_left_sixteen_bit_pixels() {
+        let mut data = vec![0, 0, TYPE_TRUE_COLOR, 0, 0, 0, 0, 0, 0, 0, 0, 0, 2, 0, 1, 0, 16, RIGHT_TO_LEFT | TOP_TO_BOTTOM];
+        // pure red then pure blue in 5-5-5
+        data.extend(&[0x00, 0x7c, 0x1f, 0x00]);
+        let image = decode(&data).unwrap();
+        assert_eq!(image.pixel(0, 0), image::RGB { red: 0, green: 0, blue: 255 });
+        assert_eq!(image.pixel(1, 0), image::RGB { red: 255, green: 0, blue: 0 });
+    }
+}
@@ -0,0 +1,635 @@
+use super::*;
+use image;
+use lzw;
+
+pub(crate) const LITTLE_ENDIAN_SIGNATURE: [u8; 4] = [0x49, 0x49, 42, 0];
+pub(crate) const BIG_ENDIAN_SIGNATURE: [u8; 4] = [0x4d, 0x4d, 0, 42];
+// strips are kept to roughly 8KiB of uncompressed data, as recommended by the spec
+const STRIP_SIZE: usize = 8192;
+
+const TAG_IMAGE_WIDTH: u16 = 256;
+const TAG_IMAGE_LENGTH: u16 = 257;
+const TAG_BITS_PER_SAMPLE: u16 = 258;
+const TAG_COMPRESSION: u16 = 259;
+const TAG_PHOTOMETRIC_INTERPRETATION: u16 = 262;
+const TAG_STRIP_OFFSETS: u16 = 273;
+const TAG_SAMPLES_PER_PIXEL: u16 = 277;
+const TAG_ROWS_PER_STRIP: u16 = 278;
+const TAG_STRIP_BYTE_COUNTS: u16 = 279;
+const TAG_X_RESOLUTION: u16 = 282;
+const TAG_Y_RESOLUTION: u16 = 283;
+const TAG_PLANAR_CONFIGURATION: u16 = 284;
+const TAG_RESOLUTION_UNIT: u16 = 296;
+const TAG_PREDICTOR: u16 = 317;
+const TAG_COLOR_MAP: u16 = 320;
+
+const TYPE_BYTE: u16 = 1;
+const TYPE_SHORT: u16 = 3;
+const TYPE_LONG: u16 = 4;
+const TYPE_RATIONAL: u16 = 5;
+
+const COMPRESSION_NONE: u32 = 1;
+const COMPRESSION_LZW: u32 = 5;
+const COMPRESSION_DEFLATE: u32 = 8;
+// the value used for deflate before it was given an official code
+const COMPRESSION_DEFLATE_OBSOLETE: u32 = 32946;
+const COMPRESSION_PACKBITS: u32 = 32773;
+
+const PHOTOMETRIC_RGB: u32 = 2;
+const PHOTOMETRIC_PALETTE: u32 = 3;
+
+const LZW_CLEAR_CODE: u32 = 256;
+const LZW_EOI_CODE: u32 = 257;
+// one less than the gif limit, since early change would need a 13 bit code for 4095
+const LZW_MAX_ENCODINGS: u32 = 4094;
+const LZW_MAX_CODE_LENGTH: usize = 12;
+
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum ByteOrder {
+    LittleEndian,
+    BigEndian,
+}
+impl ByteOrder {
+    fn u16_bytes(&self, val: u16) -> [u8; 2] {
+        match self {
+            ByteOrder::LittleEndian => val.to_le_bytes(),
+            ByteOrder::BigEndian => val.to_be_bytes(),
+        }
+    }
+    fn u32_bytes(&self, val: u32) -> [u8; 4] {
+        match self {
+            ByteOrder::LittleEndian => val.to_le_bytes(),
+            ByteOrder::BigEndian => val.to_be_bytes(),
+        }
+    }
+    fn read_u16(&self, bytes: &[u8], offset: usize) -> Result<u16, std::io::Error> {
+        match self {
+            ByteOrder::LittleEndian => read_le_u16(bytes, offset),
+            ByteOrder::BigEndian => {
+                let slice = read_bytes(bytes, offset, 2)?;
+                Ok((slice[0] as u16) << 8 | slice[1] as u16)
+            },
+        }
+    }
+    fn read_u32(&self, bytes: &[u8], offset: usize) -> Result<u32, std::io::Error> {
+        match self {
+            ByteOrder::LittleEndian => read_le_u32(bytes, offset),
+            ByteOrder::BigEndian => read_be_u32(bytes, offset),
+        }
+    }
+}
+
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum Compression {
+    None,
+    PackBits,
+    Lzw,
+    Deflate,
+}
+impl Compression {
+    fn code(&self) -> u32 {
+        match self {
+            Compression::None => COMPRESSION_NONE,
+            Compression::PackBits => COMPRESSION_PACKBITS,
+            Compression::Lzw => COMPRESSION_LZW,
+            Compression::Deflate => COMPRESSION_DEFLATE,
+        }
+    }
+}
+
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum Photometric {
+    Rgb,
+    Palette,
+}
+
+fn packbits_encode(row: &[u8], data: &mut Vec<u8>) {
+    let mut i = 0;
+    while i < row.len() {
+        let mut run = 1;
+        while i + run < row.len() && run < 128 && row[i + run] == row[i] {
+            run += 1;
+        }
+        if run > 1 {
+            // a repeated byte is stored with a count of 1 - n
+            data.push((1 - run as i32) as u8);
+            data.push(row[i]);
+            i += run;
+        }
+        else {
+            // literals continue until the next repeated byte or the 128 byte limit
+            let start = i;
+            i += 1;
+            while i < row.len() && i - start < 128 && !(i + 1 < row.len() && row[i] == row[i + 1]) {
+                i += 1;
+            }
+            data.push((i - start - 1) as u8);
+            data.extend(&row[start..i]);
+        }
+    }
+}
+
+fn packbits_decode(data: &[u8], expected_length: usize) -> Result<Vec<u8>, std::io::Error> {
+    let mut output = Vec::with_capacity(expected_length.min(data.len().saturating_mul(max_expansion(COMPRESSION_PACKBITS))));
+    let mut offset = 0;
+    while output.len() < expected_length {
+        let count = read_u8(data, offset)? as i8;
+        offset += 1;
+        if count >= 0 {
+            let length = count as usize + 1;
+            output.extend(read_bytes(data, offset, length)?);
+            offset += length;
+        }
+        // -128 is a no-op
+        else if count != -128 {
+            let value = read_u8(data, offset)?;
+            offset += 1;
+            for _ in 0..(1 - count as i32) {
+                output.push(value);
+            }
+        }
+    }
+    output.truncate(expected_length);
+    Ok(output)
+}
+
+fn lzw_compress(raw: &[u8]) -> Vec<u8> {
+    let mut bits = lzw::BitArray::new(&[]);
+    bits.append(&lzw::BitArray::from_u32(LZW_CLEAR_CODE, 9));
+    let encodings = lzw::encode_all_early_change(raw, LZW_MAX_ENCODINGS, 2);
+    for (i, encoding) in encodings.iter().enumerate() {
+        for code in encoding.codes.iter() {
+            bits.append(&lzw::BitArray::from_u32(code.value, code.length));
+        }
+        if i != encodings.len() - 1 {
+            bits.append(&lzw::BitArray::from_u32(LZW_CLEAR_CODE, LZW_MAX_CODE_LENGTH));
+        }
+    }
+    // the decoder adds an entry for every code after the first, and lengthens codes one entry early
+    let eoi_length = match encodings.last() {
+        Some(encoding) => {
+            let next_code = LZW_EOI_CODE + encoding.codes.len() as u32;
+            ((32 - (next_code + 1).leading_zeros()) as usize).min(LZW_MAX_CODE_LENGTH)
+        },
+        None => 9,
+    };
+    bits.append(&lzw::BitArray::from_u32(LZW_EOI_CODE, eoi_length));
+    bits.packed_bits
+}
+
+fn compress_strip(strip: &[u8], row_length: usize, compression: &Compression) -> Result<Vec<u8>, std::io::Error> {
+    match compression {
+        Compression::None => Ok(Vec::from(strip)),
+        // each row is packed separately
+        Compression::PackBits => {
+            let mut data = Vec::new();
+            for row in strip.chunks(row_length) {
+                packbits_encode(row, &mut data);
+            }
+            Ok(data)
+        },
+        Compression::Lzw => Ok(lzw_compress(strip)),
+        Compression::Deflate => Ok(zlib::compressed(strip).to_bytes()),
+    }
+}
+
+// the most bytes that each byte of a strip can decode to
+fn max_expansion(compression: u32) -> usize {
+    match compression {
+        COMPRESSION_NONE => 1,
+        // a two byte run repeats its value at most 128 times
+        COMPRESSION_PACKBITS => 64,
+        // codes take at least 9 bits and stand for fewer than 4096 bytes
+        COMPRESSION_LZW => 4096,
+        // a length and a distance code of a bit each copy at most 258 bytes
+        COMPRESSION_DEFLATE | COMPRESSION_DEFLATE_OBSOLETE => 1032,
+        _ => usize::MAX,
+    }
+}
+
+// `output_limit` bounds what lzw and deflate strips may decode to, and names the limit that sets it
+fn decompress_strip(strip: &[u8], expected_length: usize, compression: u32, output_limit: (usize, LimitExceeded)) -> Result<Vec<u8>, std::io::Error> {
+    let (max_output, limit) = output_limit;
+    let mut data = match compression {
+        COMPRESSION_NONE => Vec::from(strip),
+        COMPRESSION_PACKBITS => packbits_decode(strip, expected_length)?,
+        COMPRESSION_LZW => match lzw::decode_packed_with_limit(strip, 8, lzw::BitOrder::MsbFirst, true, max_output) {
+            Ok(data) => data,
+            Err(lzw::DecodeError::LimitExceeded) => return Err(limit_exceeded(limit)),
+            Err(lzw::DecodeError::InvalidCode(_)) => return Err(invalid_data("invalid code in lzw compressed strip")),
+        },
+        COMPRESSION_DEFLATE | COMPRESSION_DEFLATE_OBSOLETE => match zlib::decompress_with_limits(strip, &zlib::Limits { max_output, ..zlib::Limits::default() }) {
+            Ok(data) => data,
+            Err(zlib::DecodeError::LimitExceeded) => return Err(limit_exceeded(limit)),
+            Err(_) => return Err(invalid_data("invalid deflate compressed strip")),
+        },
+        _ => return Err(invalid_data("unsupported tiff compression")),
+    };
+    if data.len() < expected_length {
+        return Err(invalid_data("tiff strip is shorter than its rows"));
+    }
+    data.truncate(expected_length);
+    Ok(data)
+}
+
+struct Entry {
+    tag: u16,
+    field_type: u16,
+    // rationals take two values each
+    values: Vec<u32>,
+}
+impl Entry {
+    fn short(tag: u16, values: Vec<u32>) -> Self {
+        Entry { tag, field_type: TYPE_SHORT, values }
+    }
+    fn long(tag: u16, values: Vec<u32>) -> Self {
+        Entry { tag, field_type: TYPE_LONG, values }
+    }
+    fn count(&self) -> usize {
+        if self.field_type == TYPE_RATIONAL { self.values.len() / 2 } else { self.values.len() }
+    }
+    fn value_bytes(&self, byte_order: &ByteOrder) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for value in self.values.iter() {
+            if self.field_type == TYPE_SHORT {
+                bytes.extend(&byte_order.u16_bytes(*value as u16));
+            }
+            else {
+                bytes.extend(&byte_order.u32_bytes(*value));
+            }
+        }
+        bytes
+    }
+}
+
+pub struct TiffImage {
+    pub image: image::ColorImage,
+    pub byte_order: ByteOrder,
+    pub compression: Compression,
+    pub photometric: Photometric,
+}
+impl TiffImage {
+    fn samples(&self) -> Result<Vec<u8>, std::io::Error> {
+        match self.photometric {
+            Photometric::Rgb => Ok((0..self.image.canvas.pixels.len()).flat_map(|i| {
+                let color = self.image.palette.color(self.image.canvas.pixels[i]).unwrap();
+                vec![color.red, color.green, color.blue]
+            }).collect()),
+            Photometric::Palette => {
+                if self.image.palette.size() > 256 {
+                    return Err(invalid_input("palette tiff cannot have more than 256 colours"));
+                }
+                Ok(self.image.canvas.pixels.iter().map(|index| *index as u8).collect())
+            },
+        }
+    }
+    pub fn encode(&self) -> Result<Vec<u8>, std::io::Error> {
+        let byte_order = &self.byte_order;
+        let width = self.image.canvas.width;
+        let height = self.image.canvas.height;
+        let samples_per_pixel = match self.photometric {
+            Photometric::Rgb => 3,
+            Photometric::Palette => 1,
+        };
+        let row_length = width as usize * samples_per_pixel;
+        let rows_per_strip = (STRIP_SIZE / row_length.max(1)).max(1);
+        let samples = self.samples()?;
+
+        let mut data = Vec::new();
+        data.extend(match byte_order {
+            ByteOrder::LittleEndian => &LITTLE_ENDIAN_SIGNATURE,
+            ByteOrder::BigEndian => &BIG_ENDIAN_SIGNATURE,
+        });
+        // filled in once the strips are written
+        data.extend(&[0; 4]);
+        let mut strip_offsets = Vec::new();
+        let mut strip_byte_counts = Vec::new();
+        for strip in samples.chunks((rows_per_strip * row_length).max(1)) {
+            let compressed = compress_strip(strip, row_length, &self.compression)?;
+            strip_offsets.push(data.len() as u32);
+            strip_byte_counts.push(compressed.len() as u32);
+            data.extend(compressed);
+        }
+        // the ifd has to start on a word boundary
+        if data.len() % 2 == 1 {
+            data.push(0);
+        }
+
+        let mut entries = vec![
+            Entry::long(TAG_IMAGE_WIDTH, vec![width]),
+            Entry::long(TAG_IMAGE_LENGTH, vec![height]),
+            Entry::short(TAG_BITS_PER_SAMPLE, vec![8; samples_per_pixel]),
+            Entry::short(TAG_COMPRESSION, vec![self.compression.code()]),
+            Entry::short(TAG_PHOTOMETRIC_INTERPRETATION, vec![match self.photometric {
+                Photometric::Rgb => PHOTOMETRIC_RGB,
+                Photometric::Palette => PHOTOMETRIC_PALETTE,
+            }]),
+            Entry::long(TAG_STRIP_OFFSETS, strip_offsets),
+            Entry::short(TAG_SAMPLES_PER_PIXEL, vec![samples_per_pixel as u32]),
+            Entry::long(TAG_ROWS_PER_STRIP, vec![rows_per_strip as u32]),
+            Entry::long(TAG_STRIP_BYTE_COUNTS, strip_byte_counts),
+            Entry { tag: TAG_X_RESOLUTION, field_type: TYPE_RATIONAL, values: vec![72, 1] },
+            Entry { tag: TAG_Y_RESOLUTION, field_type: TYPE_RATIONAL, values: vec![72, 1] },
+            Entry::short(TAG_PLANAR_CONFIGURATION, vec![1]),
+            // inches
+            Entry::short(TAG_RESOLUTION_UNIT, vec![2]),
+        ];
+        if let Photometric::Palette = self.photometric {
+            // all of the reds, then greens, then blues, as 16 bit values
+            let colors = palette_colors(&self.image.palette);
+            let mut color_map = vec![0; 3 * 256];
+            for (i, color) in colors.iter().enumerate() {
+                color_map[i] = color.red as u32 * 257;
+                color_map[256 + i] = color.green as u32 * 257;
+                color_map[512 + i] = color.blue as u32 * 257;
+            }
+            entries.push(Entry::short(TAG_COLOR_MAP, color_map));
+        }
+
+        let ifd_offset = data.len();
+        let ifd_offset_bytes = byte_order.u32_bytes(ifd_offset as u32);
+        data[4..8].copy_from_slice(&ifd_offset_bytes);
+        let mut extra_offset = ifd_offset + 2 + entries.len() * 12 + 4;
+        let mut extra_data = Vec::new();
+        data.extend(&byte_order.u16_bytes(entries.len() as u16));
+        for entry in entries.iter() {
+            data.extend(&byte_order.u16_bytes(entry.tag));
+            data.extend(&byte_order.u16_bytes(entry.field_type));
+            data.extend(&byte_order.u32_bytes(entry.count() as u32));
+            let mut value_bytes = entry.value_bytes(byte_order);
+            if value_bytes.len() <= 4 {
+                // small values are stored in the entry itself, left justified
+                value_bytes.resize(4, 0);
+                data.extend(value_bytes);
+            }
+            else {
+                data.extend(&byte_order.u32_bytes(extra_offset as u32));
+                extra_offset += value_bytes.len();
+                extra_data.extend(value_bytes);
+            }
+        }
+        // no further ifds
+        data.extend(&[0; 4]);
+        data.extend(extra_data);
+        Ok(data)
+    }
+}
+impl Image for TiffImage {
+    fn write(self, path: &str) -> Result<fs::File, std::io::Error> {
+        let data = self.encode()?;
+        let mut file_image = fs::File::create(path)?;
+        file_image.write_all(&data)?;
+        Ok(file_image)
+    }
+}
+
+fn type_size(field_type: u16) -> Option<usize> {
+    match field_type {
+        TYPE_BYTE => Some(1),
+        TYPE_SHORT => Some(2),
+        TYPE_LONG => Some(4),
+        _ => None,
+    }
+}
+
+// reads the integer valued tags of the first ifd, other tags are skipped
+fn read_tags(bytes: &[u8], byte_order: &ByteOrder) -> Result<std::collections::HashMap<u16, Vec<u32>>, std::io::Error> {
+    let mut tags = std::collections::HashMap::new();
+    let ifd_offset = byte_order.read_u32(bytes, 4)? as usize;
+    let entry_count = byte_order.read_u16(bytes, ifd_offset)? as usize;
+    for i in 0..entry_count {
+        let entry_offset = ifd_offset + 2 + i * 12;
+        let tag = byte_order.read_u16(bytes, entry_offset)?;
+        let field_type = byte_order.read_u16(bytes, entry_offset + 2)?;
+        let count = byte_order.read_u32(bytes, entry_offset + 4)? as usize;
+        let size = match type_size(field_type) {
+            Some(size) => size,
+            None => continue,
+        };
+        let total_size = match count.checked_mul(size) {
+            Some(total_size) => total_size,
+            None => return Err(invalid_data("tiff tag is too large")),
+        };
+        let value_offset = if total_size <= 4 { entry_offset + 8 } else { byte_order.read_u32(bytes, entry_offset + 8)? as usize };
+        let value_bytes = read_bytes(bytes, value_offset, total_size)?;
+        let values = value_bytes.chunks(size).map(|value| match field_type {
+            TYPE_BYTE => Ok(value[0] as u32),
+            TYPE_SHORT => byte_order.read_u16(value, 0).map(|value| value as u32),
+            _ => byte_order.read_u32(value, 0),
+        }).collect::<Result<Vec<u32>, std::io::Error>>()?;
+        tags.insert(tag, values);
+    }
+    Ok(tags)
+}
+
+fn single_tag(tags: &std::collections::HashMap<u16, Vec<u32>>, tag: u16, default: Option<u32>) -> Result<u32, std::io::Error> {
+    match tags.get(&tag).and_then(|values| values.first()) {
+        Some(value) => Ok(*value),
+        None => match default {
+            Some(default) => Ok(default),
+            None => Err(invalid_data("tiff is missing a required tag")),
+        },
+    }
+}
+
+pub fn read(path: &str) -> Result<image::ColorImage, std::io::Error> {
+    decode(&fs::read(path)?)
+}
+
+pub fn decode(bytes: &[u8]) -> Result<image::ColorImage, std::io::Error> {
+    decode_with_limits(bytes, &Limits::default())
+}
+
+/// Decodes a tiff, failing with `LimitExceeded` as soon as it goes past one of the limits.
+pub fn decode_with_limits(bytes: &[u8], limits: &Limits) -> Result<image::ColorImage, std::io::Error> {
+    let byte_order = match read_bytes(bytes, 0, 4)? {
+        signature if signature == LITTLE_ENDIAN_SIGNATURE => ByteOrder::LittleEndian,
+        signature if signature == BIG_ENDIAN_SIGNATURE => ByteOrder::BigEndian,
+        _ => return Err(invalid_data("missing tiff signature")),
+    };
+    let tags = read_tags(bytes, &byte_order)?;
+    let width = single_tag(&tags, TAG_IMAGE_WIDTH, None)?;
+    let height = single_tag(&tags, TAG_IMAGE_LENGTH, None)?;
+    limits.check_dimensions(width, height)?;
+    let bits_per_sample = single_tag(&tags, TAG_BITS_PER_SAMPLE, Some(1))? as usize;
+    let compression = single_tag(&tags, TAG_COMPRESSION, Some(COMPRESSION_NONE))?;
+    let photometric = single_tag(&tags, TAG_PHOTOMETRIC_INTERPRETATION, None)?;
+    let samples_per_pixel = single_tag(&tags, TAG_SAMPLES_PER_PIXEL, Some(1))? as usize;
+    let rows_per_strip = single_tag(&tags, TAG_ROWS_PER_STRIP, Some(height))?.clamp(1, height.max(1)) as usize;
+    let planar_configuration = single_tag(&tags, TAG_PLANAR_CONFIGURATION, Some(1))?;
+    let predictor = single_tag(&tags, TAG_PREDICTOR, Some(1))?;
+    let strip_offsets = tags.get(&TAG_STRIP_OFFSETS).ok_or_else(|| invalid_data("tiff is missing strip offsets"))?;
+    let strip_byte_counts = tags.get(&TAG_STRIP_BYTE_COUNTS).ok_or_else(|| invalid_data("tiff is missing strip byte counts"))?;
+
+    if planar_configuration != 1 {
+        return Err(invalid_data("only chunky tiff planar configuration is supported"));
+    }
+    let supported = match photometric {
+        PHOTOMETRIC_RGB => bits_per_sample == 8 && samples_per_pixel >= 3,
+        PHOTOMETRIC_PALETTE => [1, 2, 4, 8].contains(&bits_per_sample) && samples_per_pixel == 1,
+        _ => false,
+    };
+    if !supported {
+        return Err(invalid_data("unsupported tiff photometric interpretation or bit depth"));
+    }
+    if predictor == 2 && bits_per_sample != 8 {
+        return Err(invalid_data("tiff horizontal differencing is only supported for 8 bit samples"));
+    }
+    let row_length = match (width as usize).checked_mul(samples_per_pixel * bits_per_sample) {
+        Some(row_bits) => row_bits.div_ceil(8),
+        None => return Err(invalid_data("tiff dimensions are too large")),
+    };
+    if row_length.checked_mul(height as usize).is_none() {
+        return Err(invalid_data("tiff dimensions are too large"));
+    }
+
+    let mut samples = Vec::new();
+    let strip_count = (height as usize).div_ceil(rows_per_strip);
+    limits.check_chunks(strip_count)?;
+    // each strip is cut to the rows it holds, so only the total of those can outgrow the limit
+    let output_limit = limits.output_limit(bytes.len());
+    if row_length * height as usize > output_limit.0 {
+        return Err(limit_exceeded(output_limit.1));
+    }
+    if strip_offsets.len() < strip_count || strip_byte_counts.len() < strip_count {
+        return Err(invalid_data("tiff has fewer strips than its rows need"));
+    }
+    for strip in 0..strip_count {
+        let rows = rows_per_strip.min(height as usize - strip * rows_per_strip);
+        let strip_data = read_bytes(bytes, strip_offsets[strip] as usize, strip_byte_counts[strip] as usize)?;
+        if rows * row_length > strip_data.len().saturating_mul(max_expansion(compression)) {
+            return Err(invalid_data("tiff strip is too short to hold its rows"));
+        }
+        let mut strip_samples = decompress_strip(strip_data, rows * row_length, compression, output_limit)?;
+        if predictor == 2 {
+            // each sample is stored as the difference from the same sample of the previous pixel
+            for row in strip_samples.chunks_mut(row_length) {
+                for i in samples_per_pixel..row.len() {
+                    row[i] = row[i].wrapping_add(row[i - samples_per_pixel]);
+                }
+            }
+        }
+        samples.extend(strip_samples);
+    }
+
+    if photometric == PHOTOMETRIC_RGB {
+        let pixels: Vec<image::RGB> = samples.chunks(samples_per_pixel).map(|sample| {
+            image::RGB { red: sample[0], green: sample[1], blue: sample[2] }
+        }).collect();
+        return Ok(image_from_rgb(width, height, &pixels));
+    }
+    let color_map = tags.get(&TAG_COLOR_MAP).ok_or_else(|| invalid_data("palette tiff is missing its colour map"))?;
+    let color_count = 1 << bits_per_sample;
+    if color_map.len() < color_count * 3 {
+        return Err(invalid_data("tiff colour map is too short"));
+    }
+    let colors: Vec<image::RGB> = (0..color_count).map(|i| image::RGB {
+        red: (color_map[i] >> 8) as u8,
+        green: (color_map[color_count + i] >> 8) as u8,
+        blue: (color_map[2 * color_count + i] >> 8) as u8,
+    }).collect();
+    let mut indices = Vec::with_capacity(width as usize * height as usize);
+    let pixels_per_byte = 8 / bits_per_sample;
+    let mask = ((1u16 << bits_per_sample) - 1) as u8;
+    for row in samples.chunks(row_length) {
+        for x in 0..width as usize {
+            let shift = 8 - bits_per_sample * (x % pixels_per_byte + 1);
+            indices.push(((row[x / pixels_per_byte] >> shift) & mask) as usize);
+        }
+    }
+    image_from_indices(width, height, &colors, indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn test_image() -> image::ColorImage {
+        let mut image = image::ColorImage::new(150, 70, image::RGB { red: 0, green: 0, blue: 0 });
+        for x in 0..150 {
+            for y in 0..70 {
+                let band = ((x / 10 + y / 7) % 12) as u8;
+                image.set_pixel(x, y, image::RGB { red: band * 20, green: 255 - band, blue: (y % 2) as u8 * 100 });
+            }
+        }
+        image
+    }
+    #[test]
+    fn packbits_matches_reference_example() {
+        let row = [
+            0xaa, 0xaa, 0xaa, 0x80, 0x00, 0x2a, 0xaa, 0xaa, 0xaa, 0xaa, 0x80, 0x00, 0x2a, 0x22,
+            0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa, 0xaa,
+        ];
+        let mut packed = Vec::new();
+        packbits_encode(&row, &mut packed);
+        assert_eq!(packed, vec![0xfe, 0xaa, 0x02, 0x80, 0x00, 0x2a, 0xfd, 0xaa, 0x03, 0x80, 0x00, 0x2a, 0x22, 0xf7, 0xaa]);
+        assert_eq!(packbits_decode(&packed, row.len()).unwrap(), row);
+    }
+    #[test]
+    fn tiff_round_trips_each_compression_and_byte_order() {
+        for compression in [Compression::None, Compression::PackBits, Compression::Lzw, Compression::Deflate] {
+            for byte_order in [ByteOrder::LittleEndian, ByteOrder::BigEndian] {
+                for photometric in [Photometric::Rgb, Photometric::Palette] {
+                    let tiff = TiffImage { image: test_image(), byte_order, compression, photometric };
+                    let decoded = decode(&tiff.encode().unwrap()).unwrap();
+                    for x in 0..150 {
+                        for y in 0..70 {
+                            assert_eq!(decoded.pixel(x, y), tiff.image.pixel(x, y));
+                        }
+                    }
+                }
+            }
+        }
+    }
+    #[test]
+    fn tiff_writes_multiple_strips_for_large_images() {
+        let tiff = TiffImage { image: test_image(), byte_order: ByteOrder::LittleEndian, compression: Compression::None, photometric: Photometric::Rgb };
+        let data = tiff.encode().unwrap();
+        let tags = read_tags(&data, &ByteOrder::LittleEndian).unwrap();
+        assert_eq!(tags[&TAG_STRIP_OFFSETS].len(), 4);
+        assert_eq!(tags[&TAG_ROWS_PER_STRIP], vec![18]);
+    }
+    #[test]
+    fn decode_undoes_horizontal_differencing() {
+        let mut image = image::ColorImage::new(3, 1, image::RGB { red: 10, green: 20, blue: 30 });
+        image.set_pixel(2, 0, image::RGB { red: 15, green: 25, blue: 35 });
+        let tiff = TiffImage { image, byte_order: ByteOrder::LittleEndian, compression: Compression::None, photometric: Photometric::Rgb };
+        let mut data = tiff.encode().unwrap();
+        // rewrite the samples as differences and add a predictor tag in place of the resolution unit
+        let strip_offset = read_tags(&data, &ByteOrder::LittleEndian).unwrap()[&TAG_STRIP_OFFSETS][0] as usize;
+        data[strip_offset..strip_offset + 9].copy_from_slice(&[10, 20, 30, 0, 0, 0, 5, 5, 5]);
+        let ifd_offset = read_le_u32(&data, 4).unwrap() as usize;
+        let last_entry = ifd_offset + 2 + 12 * 12;
+        data[last_entry..last_entry + 12].copy_from_slice(&[0x3d, 0x01, 3, 0, 1, 0, 0, 0, 2, 0, 0, 0]);
+        let decoded = decode(&data).unwrap();
+        assert_eq!(decoded.pixel(1, 0), image::RGB { red: 10, green: 20, blue: 30 });
+        assert_eq!(decoded.pixel(2, 0), image::RGB { red: 15, green: 25, blue: 35 });
+    }
+    #[test]
+    fn decode_rejects_strips_too_short_for_the_dimensions() {
+        // a little-endian rgb packbits tiff of 1048576 by 1048576 pixels in a single 2 byte strip
+        let tags: [(u16, u16, u32); 8] = [
+            (TAG_IMAGE_WIDTH, TYPE_LONG, 1 << 20),
+            (TAG_IMAGE_LENGTH, TYPE_LONG, 1 << 20),
+            (TAG_BITS_PER_SAMPLE, TYPE_SHORT, 8),
+            (TAG_COMPRESSION, TYPE_SHORT, COMPRESSION_PACKBITS),
+            (TAG_PHOTOMETRIC_INTERPRETATION, TYPE_SHORT, PHOTOMETRIC_RGB),
+            (TAG_STRIP_OFFSETS, TYPE_LONG, 8),
+            (TAG_SAMPLES_PER_PIXEL, TYPE_SHORT, 3),
+            (TAG_STRIP_BYTE_COUNTS, TYPE_LONG, 2),
+        ];
+        let mut bytes = LITTLE_ENDIAN_SIGNATURE.to_vec();
+        bytes.extend(&12u32.to_le_bytes());
+        bytes.extend(&[0x81, 0]);
+        bytes.extend(&[0; 2]);
+        bytes.extend(&(tags.len() as u16).to_le_bytes());
+        for (tag, field_type, value) in tags {
+            bytes.extend(&tag.to_le_bytes());
+            bytes.extend(&field_type.to_le_bytes());
+            bytes.extend(&1u32.to_le_bytes());
+            bytes.extend(&value.to_le_bytes());
+        }
+        bytes.extend(&0u32.to_le_bytes());
+        assert_eq!(decode(&bytes).err().unwrap().to_string(), "tiff strip is too short to hold its rows");
+        assert!(packbits_decode(&[0x81, 7], usize::MAX).is_err());
+    }
+}
@@ -0,0 +1,158 @@
+use super::*;
+use image;
+
+pub(crate) const XBM_SIGNATURE: &[u8] = b"#define";
+// the layout used by the x11 bitmap tools
+const VALUES_PER_LINE: usize = 12;
+const BLACK: image::RGB = image::RGB { red: 0, green: 0, blue: 0 };
+const WHITE: image::RGB = image::RGB { red: 255, green: 255, blue: 255 };
+
+pub(crate) fn is_c_identifier(name: &str) -> bool {
+    let mut characters = name.chars();
+    match characters.next() {
+        Some(first) if first.is_ascii_alphabetic() || first == '_' => characters.all(|c| c.is_ascii_alphanumeric() || c == '_'),
+        _ => false,
+    }
+}
+
+/// A bitmap written as c source, where every pixel darker than mid grey is a set bit.
+pub struct XbmImage {
+    pub image: image::ColorImage,
+    /// prefix of the `_width`, `_height` and `_bits` identifiers
+    pub name: String,
+}
+impl XbmImage {
+    pub fn encode(&self) -> Result<Vec<u8>, std::io::Error> {
+        if !is_c_identifier(&self.name) {
+            return Err(invalid_input("xbm name must be a c identifier"));
+        }
+        let width = self.image.canvas.width;
+        let height = self.image.canvas.height;
+        // rows are padded to whole bytes with the leftmost pixel in the lowest bit
+        let mut bytes = Vec::new();
+        for y in 0..height {
+            for byte_x in (0..width).step_by(8) {
+                let mut byte = 0u8;
+                for bit in 0..(width - byte_x).min(8) {
+                    if netpbm::luminance(&self.image.pixel(byte_x + bit, y)) < 128 {
+                        byte |= 1 << bit;
+                    }
+                }
+                bytes.push(byte);
+            }
+        }
+
+        let mut text = format!("#define {0}_width {1}\n#define {0}_height {2}\nstatic unsigned char {0}_bits[] = {{", self.name, width, height);
+        for (i, byte) in bytes.iter().enumerate() {
+            text.push_str(if i % VALUES_PER_LINE == 0 { "\n   " } else { " " });
+            text.push_str(&format!("0x{:02x}", byte));
+            if i + 1 < bytes.len() {
+                text.push(',');
+            }
+        }
+        text.push_str(" };\n");
+        Ok(text.into_bytes())
+    }
+}
+impl Image for XbmImage {
+    fn write(self, path: &str) -> Result<fs::File, std::io::Error> {
+        let data = self.encode()?;
+        let mut file_image = fs::File::create(path)?;
+        file_image.write_all(&data)?;
+        Ok(file_image)
+    }
+}
+
+fn parse_define(text: &str, suffix: &str) -> Option<u32> {
+    text.lines().find_map(|line| {
+        let mut words = line.split_whitespace();
+        if words.next() != Some("#define") || !words.next()?.ends_with(suffix) {
+            return None;
+        }
+        words.next()?.parse().ok()
+    })
+}
+
+pub fn read(path: &str) -> Result<image::ColorImage, std::io::Error> {
+    decode(&fs::read(path)?)
+}
+
+/// Decodes x11 bitmaps with 8 bit `char` values and older x10 bitmaps with 16 bit `short` values.
+/// Set bits are black and the rest are white.
+pub fn decode(bytes: &[u8]) -> Result<image::ColorImage, std::io::Error> {
+    decode_with_limits(bytes, &Limits::default())
+}
+
+/// Decodes an xbm, failing with `LimitExceeded` if its dimensions go past the limits.
+pub fn decode_with_limits(bytes: &[u8], limits: &Limits) -> Result<image::ColorImage, std::io::Error> {
+    let text = match std::str::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(_) => return Err(invalid_data("xbm is not valid text")),
+    };
+    let (width, height) = match (parse_define(text, "_width"), parse_define(text, "_height")) {
+        (Some(width), Some(height)) => (width, height),
+        _ => return Err(invalid_data("xbm is missing its width or height")),
+    };
+    limits.check_dimensions(width, height)?;
+    let (start, end) = match (text.find('{'), text.rfind('}')) {
+        (Some(start), Some(end)) if start < end => (start, end),
+        _ => return Err(invalid_data("xbm is missing its bit array")),
+    };
+    let value_bits = if text[..start].contains("short") { 16 } else { 8 };
+    let mut values = Vec::new();
+    for value in text[start + 1..end].split(',').map(str::trim).filter(|value| !value.is_empty()) {
+        let digits = value.trim_start_matches("0x").trim_start_matches("0X");
+        match u32::from_str_radix(digits, 16) {
+            Ok(value) => values.push(value),
+            Err(_) => return Err(invalid_data("xbm contains an invalid value")),
+        }
+    }
+
+    let values_per_row = (width as usize).div_ceil(value_bits);
+    if values.len() < values_per_row * height as usize {
+        return Err(invalid_data("xbm has fewer values than its size needs"));
+    }
+    let mut image = image::ColorImage::new(width, height, WHITE);
+    for y in 0..height {
+        for x in 0..width {
+            let value = values[y as usize * values_per_row + x as usize / value_bits];
+            if (value >> (x as usize % value_bits)) & 1 == 1 {
+                image.set_pixel(x, y, BLACK);
+            }
+        }
+    }
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn xbm_writes_c_source() {
+        let mut image = image::ColorImage::new(10, 2, WHITE);
+        image.set_pixel(0, 0, BLACK);
+        image.set_pixel(9, 0, BLACK);
+        image.set_pixel(3, 1, BLACK);
+        let xbm = XbmImage { image, name: String::from("glyph") };
+        let expected = "#define glyph_width 10\n#define glyph_height 2\nstatic unsigned char glyph_bits[] = {\n   0x01, 0x02, 0x08, 0x00 };\n";
+        assert_eq!(String::from_utf8(xbm.encode().unwrap()).unwrap(), expected);
+        let decoded = decode(expected.as_bytes()).unwrap();
+        for x in 0..10 {
+            for y in 0..2 {
+                assert_eq!(decoded.pixel(x, y), xbm.image.pixel(x, y));
+            }
+        }
+    }
+    #[test]
+    fn decode_reads_x10_short_values() {
+        let source = "#define old_width 17\n#define old_height 1\nstatic short old_bits[] = {\n   0x8001, 0x0001};\n";
+        let image = decode(source.as_bytes()).unwrap();
+        let set: Vec<u32> = (0..17).filter(|x| image.pixel(*x, 0) == BLACK).collect();
+        assert_eq!(set, vec![0, 15, 16]);
+    }
+    #[test]
+    fn xbm_rejects_names_that_are_not_identifiers() {
+        let xbm = XbmImage { image: image::ColorImage::new(1, 1, WHITE), name: String::from("2d-glyph") };
+        assert_eq!(xbm.encode().unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,255 @@
+use super::*;
+use image;
+use std::collections::HashMap;
+
+pub(crate) const XPM_SIGNATURE: &[u8] = b"/* XPM */";
+// the characters used by the xpm library for pixel keys, which avoid quotes and backslashes
+const KEY_CHARACTERS: &[u8] = b" .XoO+@#$%&*=-;:>,<1234567890qwertyuipasdfghjklzxcvbnmMNBVCZASDFGHJKLPIUYTREWQ!~^/()_`'][{}|";
+// colour contexts in order of preference: colour, greyscale, 4 level greyscale, mono
+const COLOR_CONTEXTS: [&str; 4] = ["c", "g", "g4", "m"];
+const SYMBOLIC_CONTEXT: &str = "s";
+
+fn key(index: usize, characters_per_pixel: usize) -> String {
+    let mut key = String::new();
+    let mut remaining = index;
+    for _ in 0..characters_per_pixel {
+        key.push(KEY_CHARACTERS[remaining % KEY_CHARACTERS.len()] as char);
+        remaining /= KEY_CHARACTERS.len();
+    }
+    key
+}
+
+/// A pixmap written as c source, with one colour line for each palette entry.
+pub struct XpmImage {
+    pub image: image::ColorImage,
+    /// the array is named `<name>_xpm`
+    pub name: String,
+}
+impl XpmImage {
+    pub fn encode(&self) -> Result<Vec<u8>, std::io::Error> {
+        if !xbm::is_c_identifier(&self.name) {
+            return Err(invalid_input("xpm name must be a c identifier"));
+        }
+        let colors = palette_colors(&self.image.palette);
+        let mut characters_per_pixel = 1;
+        while KEY_CHARACTERS.len().pow(characters_per_pixel as u32) < colors.len() {
+            characters_per_pixel += 1;
+        }
+        let keys: Vec<String> = (0..colors.len()).map(|index| key(index, characters_per_pixel)).collect();
+
+        let mut lines = vec![format!("{} {} {} {}", self.image.canvas.width, self.image.canvas.height, colors.len(), characters_per_pixel)];
+        for (key, color) in keys.iter().zip(colors.iter()) {
+            lines.push(format!("{} c #{:02X}{:02X}{:02X}", key, color.red, color.green, color.blue));
+        }
+        for y in 0..self.image.canvas.height {
+            lines.push((0..self.image.canvas.width).map(|x| keys[*self.image.canvas.pixel(x, y)].as_str()).collect());
+        }
+        let quoted: Vec<String> = lines.iter().map(|line| format!("\"{}\"", line)).collect();
+        let text = format!("/* XPM */\nstatic char * {}_xpm[] = {{\n{}}};\n", self.name, quoted.join(",\n") + "\n");
+        Ok(text.into_bytes())
+    }
+}
+impl Image for XpmImage {
+    fn write(self, path: &str) -> Result<fs::File, std::io::Error> {
+        let data = self.encode()?;
+        let mut file_image = fs::File::create(path)?;
+        file_image.write_all(&data)?;
+        Ok(file_image)
+    }
+}
+
+// the contents of each string literal, skipping comments
+fn string_literals(text: &str) -> Result<Vec<&str>, std::io::Error> {
+    let mut literals = Vec::new();
+    let mut rest = text;
+    loop {
+        let quote = rest.find('"');
+        let comment = rest.find("/*");
+        match (quote, comment) {
+            (Some(quote), Some(comment)) if comment < quote => match rest[comment..].find("*/") {
+                Some(end) => rest = &rest[comment + end + 2..],
+                None => return Err(invalid_data("xpm has an unterminated comment")),
+            },
+            (Some(quote), _) => match rest[quote + 1..].find('"') {
+                Some(end) => {
+                    literals.push(&rest[quote + 1..quote + 1 + end]);
+                    rest = &rest[quote + end + 2..];
+                },
+                None => return Err(invalid_data("xpm has an unterminated string")),
+            },
+            (None, _) => return Ok(literals),
+        }
+    }
+}
+
+fn parse_color(value: &str) -> Result<image::RGB, std::io::Error> {
+    if let Some(hex) = value.strip_prefix('#') {
+        // 4, 8 or 16 bits per channel, keeping the top 8 bits
+        let digits = hex.len() / 3;
+        if hex.len() % 3 != 0 || ![1, 2, 4].contains(&digits) {
+            return Err(invalid_data("xpm colour has an unsupported number of digits"));
+        }
+        let mut channels = [0u8; 3];
+        for (i, channel) in channels.iter_mut().enumerate() {
+            let value = match u16::from_str_radix(&hex[i * digits..(i + 1) * digits], 16) {
+                Ok(value) => value,
+                Err(_) => return Err(invalid_data("xpm colour is not hexadecimal")),
+            };
+            *channel = match digits {
+                1 => value as u8 * 17,
+                2 => value as u8,
+                _ => (value >> 8) as u8,
+            };
+        }
+        return Ok(image::RGB { red: channels[0], green: channels[1], blue: channels[2] });
+    }
+    let (red, green, blue) = match value.to_ascii_lowercase().replace(' ', "").as_str() {
+        // there is no alpha to put transparency in
+        "none" | "black" => (0, 0, 0),
+        "white" => (255, 255, 255),
+        "red" => (255, 0, 0),
+        "green" => (0, 255, 0),
+        "blue" => (0, 0, 255),
+        "yellow" => (255, 255, 0),
+        "cyan" => (0, 255, 255),
+        "magenta" => (255, 0, 255),
+        "gray" | "grey" => (190, 190, 190),
+        _ => return Err(invalid_data("unknown xpm colour name")),
+    };
+    Ok(image::RGB { red, green, blue })
+}
+
+pub fn read(path: &str) -> Result<image::ColorImage, std::io::Error> {
+    decode(&fs::read(path)?)
+}
+
+/// Decodes an xpm3 pixmap. Hex colours and a few basic colour names are understood, and transparent entries are black.
+pub fn decode(bytes: &[u8]) -> Result<image::ColorImage, std::io::Error> {
+    decode_with_limits(bytes, &Limits::default())
+}
+
+/// Decodes an xpm, failing with `LimitExceeded` if its dimensions go past the limits.
+pub fn decode_with_limits(bytes: &[u8], limits: &Limits) -> Result<image::ColorImage, std::io::Error> {
+    let text = match std::str::from_utf8(bytes) {
+        Ok(text) => text,
+        Err(_) => return Err(invalid_data("xpm is not valid text")),
+    };
+    let literals = string_literals(text)?;
+    let values: Vec<usize> = match literals.first() {
+        Some(values) => values.split_whitespace().take(4).filter_map(|value| value.parse().ok()).collect(),
+        None => return Err(invalid_data("xpm is missing its values line")),
+    };
+    if values.len() != 4 || values[3] == 0 {
+        return Err(invalid_data("xpm values line is invalid"));
+    }
+    let (width, height, color_count, characters_per_pixel) = (values[0], values[1], values[2], values[3]);
+    if width > u32::MAX as usize || height > u32::MAX as usize {
+        return Err(invalid_data("xpm dimensions are too large"));
+    }
+    limits.check_dimensions(width as u32, height as u32)?;
+    if color_count.checked_add(height).is_none_or(|count| literals.len() <= count) {
+        return Err(invalid_data("xpm has fewer lines than its values line gives"));
+    }
+    // every pixel line has to hold the whole row, which bounds the image by the length of the text
+    let pixel_lines = &literals[1 + color_count..1 + color_count + height];
+    let line_length = width.checked_mul(characters_per_pixel);
+    if pixel_lines.iter().any(|line| Some(line.len()) != line_length) {
+        return Err(invalid_data("xpm pixel line does not match the width"));
+    }
+
+    let mut colors = Vec::with_capacity(color_count);
+    let mut indices_by_key = HashMap::new();
+    for (index, line) in literals[1..1 + color_count].iter().enumerate() {
+        let key = match line.get(..characters_per_pixel) {
+            Some(key) => key,
+            None => return Err(invalid_data("xpm colour line is shorter than its key")),
+        };
+        // each context is followed by a value, which may be a name with spaces in it
+        let mut contexts: Vec<(&str, String)> = Vec::new();
+        for word in line[characters_per_pixel..].split_whitespace() {
+            match contexts.last_mut() {
+                Some((_, value)) if value.is_empty() || !(COLOR_CONTEXTS.contains(&word) || word == SYMBOLIC_CONTEXT) => {
+                    if !value.is_empty() {
+                        value.push(' ');
+                    }
+                    value.push_str(word);
+                },
+                _ => contexts.push((word, String::new())),
+            }
+        }
+        let value = COLOR_CONTEXTS.iter().find_map(|context| {
+            contexts.iter().find(|(name, _)| name == context).map(|(_, value)| value)
+        });
+        match value {
+            Some(value) => colors.push(parse_color(value)?),
+            None => return Err(invalid_data("xpm colour line has no colour")),
+        }
+        indices_by_key.insert(key, index);
+    }
+
+    let mut indices = Vec::with_capacity(width * height);
+    for line in pixel_lines {
+        for x in 0..width {
+            let key = line.get(x * characters_per_pixel..(x + 1) * characters_per_pixel);
+            match key.and_then(|key| indices_by_key.get(key)) {
+                Some(index) => indices.push(*index),
+                None => return Err(invalid_data("xpm pixel does not match any colour")),
+            }
+        }
+    }
+    image_from_indices(width as u32, height as u32, &colors, indices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn xpm_writes_one_colour_line_per_palette_entry() {
+        let mut image = image::ColorImage::new(3, 2, image::RGB { red: 0, green: 0, blue: 0 });
+        image.set_pixel(1, 0, image::RGB { red: 255, green: 128, blue: 0 });
+        image.set_pixel(2, 1, image::RGB { red: 255, green: 128, blue: 0 });
+        let xpm = XpmImage { image, name: String::from("glyph") };
+        let expected = "/* XPM */\nstatic char * glyph_xpm[] = {\n\"3 2 2 1\",\n\"  c #000000\",\n\". c #FF8000\",\n\" . \",\n\"  .\"\n};\n";
+        assert_eq!(String::from_utf8(xpm.encode().unwrap()).unwrap(), expected);
+    }
+    #[test]
+    fn xpm_uses_longer_keys_for_large_palettes() {
+        let mut image = image::ColorImage::new(100, 1, image::RGB { red: 0, green: 0, blue: 0 });
+        for x in 0..100 {
+            image.set_pixel(x, 0, image::RGB { red: x as u8, green: 0, blue: 0 });
+        }
+        let xpm = XpmImage { image, name: String::from("ramp") };
+        let data = xpm.encode().unwrap();
+        assert!(data.windows(12).any(|window| window == b"\"100 1 100 2"));
+        let decoded = decode(&data).unwrap();
+        for x in 0..100 {
+            assert_eq!(decoded.pixel(x, 0), xpm.image.pixel(x, 0));
+        }
+    }
+    #[test]
+    fn decode_prefers_colour_context_and_reads_names() {
+        let source = concat!(
+            "/* XPM */\n/* a comment with a \" in it */\nstatic const char *icon[] = {\n",
+            "\"2 2 3 2 0 0\",\n",
+            "\"aa m black c #FFF s background\",\n",
+            "\"bb c None\",\n",
+            "\"cc g4 grey c light grey m white\",\n",
+            "\"aabb\",\n\"ccaa\"};\n",
+        );
+        assert_eq!(decode(source.as_bytes()).err().unwrap().to_string(), "unknown xpm colour name");
+        let source = source.replace("light grey", "#123456789abc");
+        let image = decode(source.as_bytes()).unwrap();
+        assert_eq!(image.pixel(0, 0), image::RGB { red: 255, green: 255, blue: 255 });
+        assert_eq!(image.pixel(1, 0), image::RGB { red: 0, green: 0, blue: 0 });
+        assert_eq!(image.pixel(0, 1), image::RGB { red: 0x12, green: 0x56, blue: 0x9a });
+    }
+    #[test]
+    fn decode_checks_the_values_line_against_the_pixels() {
+        let line_error = |source: &str| decode(source.as_bytes()).err().unwrap().to_string();
+        assert_eq!(line_error("\"1000000000 1 1 1\", \"a c #000000\", \"a\""), "xpm pixel line does not match the width");
+        assert_eq!(line_error("\"100000000000 1 1 1\", \"a c #000000\", \"a\""), "xpm dimensions are too large");
+        assert_eq!(line_error("\"2 1 1 18446744073709551615\", \"a c #000000\", \"aa\""), "xpm pixel line does not match the width");
+        assert_eq!(line_error("\"1 1 18446744073709551615 1\", \"a c #000000\", \"a\""), "xpm has fewer lines than its values line gives");
+        assert_eq!(line_error("\"2 1 1 1\", \"a c #000000\", \"aaa\""), "xpm pixel line does not match the width");
+    }
+}
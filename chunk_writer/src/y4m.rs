@@ -0,0 +1,143 @@
+use super::*;
+use image;
+
+const STREAM_SIGNATURE: &str = "YUV4MPEG2";
+const FRAME_SIGNATURE: &[u8] = b"FRAME\n";
+
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum Subsampling {
+    /// one chroma sample for each 2x2 block, centred between the luma samples
+    Yuv420,
+    Yuv444,
+}
+
+// bt.601 with luma from 16 to 235 and chroma from 16 to 240, which is what video encoders expect by default
+fn rgb_to_ycbcr(color: image::RGB) -> (u8, u8, u8) {
+    let (red, green, blue) = (color.red as i32, color.green as i32, color.blue as i32);
+    let y = ((66 * red + 129 * green + 25 * blue + 128) >> 8) + 16;
+    let cb = ((-38 * red - 74 * green + 112 * blue + 128) >> 8) + 128;
+    let cr = ((112 * red - 94 * green - 18 * blue + 128) >> 8) + 128;
+    (y as u8, cb as u8, cr as u8)
+}
+
+/// Streams frames as yuv4mpeg2, ready to pipe into a video encoder.
+///
+/// The stream header is written when the writer is created, and each frame is written as soon as it is given.
+pub struct Y4mWriter<W: Write> {
+    writer: W,
+    width: u32,
+    height: u32,
+    subsampling: Subsampling,
+}
+impl<W: Write> Y4mWriter<W> {
+    /// `frame_rate` is a fraction of frames per second, such as `(30000, 1001)`.
+    pub fn new(mut writer: W, width: u32, height: u32, frame_rate: (u32, u32), subsampling: Subsampling) -> Result<Self, std::io::Error> {
+        if width == 0 || height == 0 {
+            return Err(invalid_input("y4m frames must be at least 1 pixel across"));
+        }
+        if frame_rate.0 == 0 || frame_rate.1 == 0 {
+            return Err(invalid_input("y4m frame rate must be positive"));
+        }
+        let colorspace = match subsampling {
+            Subsampling::Yuv420 => "420jpeg",
+            Subsampling::Yuv444 => "444",
+        };
+        // progressive with square pixels
+        writeln!(writer, "{} W{} H{} F{}:{} Ip A1:1 C{}", STREAM_SIGNATURE, width, height, frame_rate.0, frame_rate.1, colorspace)?;
+        Ok(Y4mWriter { writer, width, height, subsampling })
+    }
+    pub fn write_frame(&mut self, frame: &image::ColorImage) -> Result<(), std::io::Error> {
+        if frame.canvas.width != self.width || frame.canvas.height != self.height {
+            return Err(invalid_input("y4m frames must all be the size given in the stream header"));
+        }
+        let (width, height) = (self.width as usize, self.height as usize);
+        // converting each palette entry once is far cheaper than converting each pixel
+        let colors: Vec<(u8, u8, u8)> = palette_colors(&frame.palette).into_iter().map(rgb_to_ycbcr).collect();
+        let samples: Vec<(u8, u8, u8)> = frame.canvas.pixels.iter().map(|index| colors[*index]).collect();
+
+        let mut data = Vec::with_capacity(FRAME_SIGNATURE.len() + width * height * 3);
+        data.extend(FRAME_SIGNATURE);
+        data.extend(samples.iter().map(|sample| sample.0));
+        match self.subsampling {
+            Subsampling::Yuv444 => {
+                data.extend(samples.iter().map(|sample| sample.1));
+                data.extend(samples.iter().map(|sample| sample.2));
+            },
+            Subsampling::Yuv420 => {
+                // blocks on an odd right or bottom edge average the samples they do cover
+                let mut cb_plane = Vec::with_capacity(width.div_ceil(2) * height.div_ceil(2));
+                let mut cr_plane = Vec::with_capacity(cb_plane.capacity());
+                for block_y in (0..height).step_by(2) {
+                    for block_x in (0..width).step_by(2) {
+                        let (mut cb, mut cr, mut count) = (0, 0, 0);
+                        for y in block_y..(block_y + 2).min(height) {
+                            for x in block_x..(block_x + 2).min(width) {
+                                let sample = samples[y * width + x];
+                                cb += sample.1 as u32;
+                                cr += sample.2 as u32;
+                                count += 1;
+                            }
+                        }
+                        cb_plane.push(((cb + count / 2) / count) as u8);
+                        cr_plane.push(((cr + count / 2) / count) as u8);
+                    }
+                }
+                data.extend(cb_plane);
+                data.extend(cr_plane);
+            },
+        }
+        self.writer.write_all(&data)
+    }
+    /// Flushes and returns the underlying writer.
+    pub fn finish(mut self) -> Result<W, std::io::Error> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn rgb_to_ycbcr_uses_studio_range() {
+        assert_eq!(rgb_to_ycbcr(image::RGB { red: 0, green: 0, blue: 0 }), (16, 128, 128));
+        assert_eq!(rgb_to_ycbcr(image::RGB { red: 255, green: 255, blue: 255 }), (235, 128, 128));
+        assert_eq!(rgb_to_ycbcr(image::RGB { red: 0, green: 0, blue: 255 }), (41, 240, 110));
+    }
+    #[test]
+    fn yuv420_frames_average_chroma_over_blocks() {
+        // a 3x2 frame with a blue column on the left, so the odd column gets its own block
+        let mut frame = image::ColorImage::new(3, 2, image::RGB { red: 0, green: 0, blue: 0 });
+        frame.set_pixel(0, 0, image::RGB { red: 0, green: 0, blue: 255 });
+        frame.set_pixel(0, 1, image::RGB { red: 0, green: 0, blue: 255 });
+        let mut writer = Y4mWriter::new(Vec::new(), 3, 2, (25, 1), Subsampling::Yuv420).unwrap();
+        writer.write_frame(&frame).unwrap();
+        writer.write_frame(&frame).unwrap();
+        let data = writer.finish().unwrap();
+
+        let header = b"YUV4MPEG2 W3 H2 F25:1 Ip A1:1 C420jpeg\n";
+        assert_eq!(&data[..header.len()], &header[..]);
+        let frame_data = &data[header.len()..];
+        let mut expected = b"FRAME\n".to_vec();
+        expected.extend(&[41, 16, 16, 41, 16, 16]);
+        // cb then cr for the two blocks
+        expected.extend(&[184, 128, 119, 128]);
+        assert_eq!(frame_data, [expected.clone(), expected].concat());
+    }
+    #[test]
+    fn yuv444_frames_keep_every_sample() {
+        let frame = image::ColorImage::new(4, 3, image::RGB { red: 255, green: 255, blue: 255 });
+        let mut writer = Y4mWriter::new(Vec::new(), 4, 3, (30000, 1001), Subsampling::Yuv444).unwrap();
+        writer.write_frame(&frame).unwrap();
+        let data = writer.finish().unwrap();
+        let header = b"YUV4MPEG2 W4 H3 F30000:1001 Ip A1:1 C444\nFRAME\n";
+        assert_eq!(&data[..header.len()], &header[..]);
+        assert_eq!(data.len(), header.len() + 4 * 3 * 3);
+    }
+    #[test]
+    fn write_frame_rejects_mismatched_sizes() {
+        let mut writer = Y4mWriter::new(Vec::new(), 4, 4, (25, 1), Subsampling::Yuv420).unwrap();
+        let frame = image::ColorImage::new(4, 5, image::RGB::default());
+        assert_eq!(writer.write_frame(&frame).unwrap_err().kind(), std::io::ErrorKind::InvalidInput);
+    }
+}
@@ -13,7 +13,7 @@ impl Crc {
                     c = POLYNOMIAL ^ (c >> 1);
                 }
                 else {
-                    c = c >> 1;
+                    c >>= 1;
                 }
             }
             table[n as usize] = c;
@@ -22,8 +22,12 @@ impl Crc {
             table
         }
     }
-    pub fn calculate(&self, data: &Vec<u8>) -> u32 {
-        let mut c: u32 = 0xffffffff;
+    pub fn calculate(&self, data: &[u8]) -> u32 {
+        self.update(0, data)
+    }
+    /// Continues a checksum, so `update(calculate(a), b)` is the checksum of `a` followed by `b`.
+    pub fn update(&self, checksum: u32, data: &[u8]) -> u32 {
+        let mut c: u32 = checksum ^ 0xffffffff;
         for byte in data {
             let table_index = (c ^ *byte as u32) & 0xff;
             c = self.table[table_index as usize] ^ (c >> 8);
@@ -32,6 +36,12 @@ impl Crc {
     }
 }
 
+impl Default for Crc {
+    fn default() -> Self {
+        Crc::new()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -79,4 +89,10 @@ mod tests {
         assert_eq!(crc.calculate(&values[3]), 0xf5b137);
         assert_eq!(crc.calculate(&values[4]), 0x3d359f36);
     }
+    #[test]
+    fn update_continues_a_checksum() {
+        let crc = Crc::new();
+        let data: Vec<u8> = (0..100).collect();
+        assert_eq!(crc.update(crc.calculate(&data[..30]), &data[30..]), crc.calculate(&data));
+    }
 }
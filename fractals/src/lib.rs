@@ -2,6 +2,8 @@ use std::collections::HashSet;
 use image::ColorImage;
 use image::RGB;
 
+pub mod svg;
+
 #[derive(Debug,Clone,Copy,Hash,PartialEq,Eq)]
 struct Coord {
     pub x: i32,
@@ -18,17 +20,23 @@ struct Triangle {
     c: Coord,
 }
 pub fn sierpinski_triangle(image: &mut ColorImage, iterations: usize, width: u32, height: u32, triangle_color: RGB, background_color: RGB) {
+    let initial_triangle = sierpinski_initial_triangle(width, height);
+    draw_triangle(image, initial_triangle.a, initial_triangle.b, initial_triangle.c, triangle_color);
+    sierpinski_triangle_rec(initial_triangle, iterations, 1, &mut |triangle, _| {
+        draw_triangle(image, triangle.a, triangle.b, triangle.c, background_color);
+    });
+}
+fn sierpinski_initial_triangle(width: u32, height: u32) -> Triangle {
     let width = width as i32;
     let height = height as i32;
-    let initial_triangle = Triangle {
+    Triangle {
         a: Coord::new(width / 2, height),
         b: Coord::new(0, 0),
         c: Coord::new(width, 0),
-    };
-    draw_triangle(image, initial_triangle.a, initial_triangle.b, initial_triangle.c, triangle_color);
-    sierpinski_triangle_rec(image, initial_triangle, iterations, background_color);
+    }
 }
-fn sierpinski_triangle_rec(image: &mut ColorImage, triangle: Triangle, iterations: usize, remove_color: RGB) {
+// calls remove with each triangle cut out of the middle of another, along with the level of recursion it was cut at
+fn sierpinski_triangle_rec<F>(triangle: Triangle, iterations: usize, level: usize, remove: &mut F) where F: FnMut(&Triangle, usize) {
     match iterations {
         0 => (),
         _ => {
@@ -37,14 +45,14 @@ fn sierpinski_triangle_rec(image: &mut ColorImage, triangle: Triangle, iteration
                 Coord::new((triangle.b.x + triangle.c.x) / 2, (triangle.b.y + triangle.c.y) / 2),
                 Coord::new((triangle.c.x + triangle.a.x) / 2, (triangle.c.y + triangle.a.y) / 2),
             ];
-            draw_triangle(image, line_midpoints[0], line_midpoints[1], line_midpoints[2], remove_color);
+            remove(&Triangle { a: line_midpoints[0], b: line_midpoints[1], c: line_midpoints[2] }, level);
             let rec_triangles = vec![
                 Triangle { a: triangle.a, b: line_midpoints[0], c: line_midpoints[2] },
                 Triangle { a: triangle.b, b: line_midpoints[0], c: line_midpoints[1] },
                 Triangle { a: triangle.c, b: line_midpoints[1], c: line_midpoints[2] },
             ];
             for triangle in rec_triangles {
-                sierpinski_triangle_rec(image, triangle, iterations - 1, remove_color);
+                sierpinski_triangle_rec(triangle, iterations - 1, level + 1, remove);
             }
         },
     }
@@ -74,7 +82,7 @@ fn get_triangle_coord_set(set: &mut HashSet<Coord>, triangle: &Triangle) {
     get_line_coord_set(set, triangle.a, triangle.b);
     get_line_coord_set(set, triangle.a, triangle.c);
     get_line_coord_set(set, triangle.b, triangle.c);
-    let initial_point = get_triangle_midpoint(&triangle);
+    let initial_point = get_triangle_midpoint(triangle);
     flood_fill_coord_set(set, initial_point);
 }
 fn get_triangle_midpoint(triangle: &Triangle) -> Coord {
@@ -105,12 +113,12 @@ fn flood_fill_coord_set(set: &mut HashSet<Coord>, initial_point: Coord) {
 fn draw_line(image: &mut ColorImage, a: Coord, b: Coord, color: RGB) {
     let mut set = HashSet::new();
     get_line_coord_set(&mut set, a, b);
-    set_image_pixels_from_coords(image, &mut set, color);
+    set_image_pixels_from_coords(image, &set, color);
 }
 fn draw_triangle(image: &mut ColorImage, a: Coord, b: Coord, c: Coord, color: RGB) {
     let mut set = HashSet::new();
     get_triangle_coord_set(&mut set, &Triangle { a, b, c });
-    set_image_pixels_from_coords(image, &mut set, color);
+    set_image_pixels_from_coords(image, &set, color);
 }
 fn set_image_pixels_from_coords(image: &mut ColorImage, set: &HashSet<Coord>, color: RGB) {
     let image_width = image.canvas.width as i32;
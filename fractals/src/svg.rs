@@ -0,0 +1,73 @@
+use super::*;
+use std::fmt::Write;
+
+fn hex_color(color: RGB) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.red, color.green, color.blue)
+}
+fn polygon_points(triangle: &Triangle) -> String {
+    format!("{},{} {},{} {},{}", triangle.a.x, triangle.a.y, triangle.b.x, triangle.b.y, triangle.c.x, triangle.c.y)
+}
+
+/// Draws the same sierpinski triangle as the raster version, as svg polygons.
+///
+/// With `group_levels` each level of recursion is placed in its own `<g id="level-n">` sharing a fill,
+/// the outer triangle being level 0. Otherwise every polygon carries its own fill.
+pub fn sierpinski_triangle(iterations: usize, width: u32, height: u32, triangle_color: RGB, background_color: RGB, group_levels: bool) -> String {
+    let initial_triangle = sierpinski_initial_triangle(width, height);
+    let mut levels = vec![vec![polygon_points(&initial_triangle)]];
+    sierpinski_triangle_rec(initial_triangle, iterations, 1, &mut |triangle, level| {
+        if levels.len() <= level {
+            levels.push(Vec::new());
+        }
+        levels[level].push(polygon_points(triangle));
+    });
+
+    let mut svg = String::new();
+    // writing to a string cannot fail
+    writeln!(svg, "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{0}\" height=\"{1}\" viewBox=\"0 0 {0} {1}\">", width, height).unwrap();
+    writeln!(svg, "<rect width=\"{}\" height=\"{}\" fill=\"{}\"/>", width, height, hex_color(background_color)).unwrap();
+    for (level, polygons) in levels.iter().enumerate() {
+        let fill = hex_color(if level == 0 { triangle_color } else { background_color });
+        if group_levels {
+            writeln!(svg, "<g id=\"level-{}\" fill=\"{}\">", level, fill).unwrap();
+            for points in polygons {
+                writeln!(svg, "<polygon points=\"{}\"/>", points).unwrap();
+            }
+            writeln!(svg, "</g>").unwrap();
+        }
+        else {
+            for points in polygons {
+                writeln!(svg, "<polygon points=\"{}\" fill=\"{}\"/>", points, fill).unwrap();
+            }
+        }
+    }
+    svg.push_str("</svg>\n");
+    svg
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    const BLUE: RGB = RGB { red: 0, green: 0, blue: 200 };
+    const BLACK: RGB = RGB { red: 0, green: 0, blue: 0 };
+    #[test]
+    fn each_level_removes_three_times_as_many_triangles_as_the_last() {
+        let svg = sierpinski_triangle(3, 800, 800, BLUE, BLACK, true);
+        assert!(svg.starts_with("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"800\" height=\"800\" viewBox=\"0 0 800 800\">"));
+        assert!(svg.contains("<g id=\"level-0\" fill=\"#0000c8\">\n<polygon points=\"400,800 0,0 800,0\"/>\n</g>"));
+        let groups: Vec<&str> = svg.split("<g ").skip(1).collect();
+        assert_eq!(groups.len(), 4);
+        // the outer triangle, then one removed triangle that splits into three at each level below
+        let counts: Vec<usize> = groups.iter().map(|group| group.matches("<polygon").count()).collect();
+        assert_eq!(counts, vec![1, 1, 3, 9]);
+        assert!(groups[1].starts_with("id=\"level-1\" fill=\"#000000\">\n<polygon points=\"200,400 400,0 600,400\"/>"));
+    }
+    #[test]
+    fn ungrouped_polygons_carry_their_own_fill() {
+        let svg = sierpinski_triangle(2, 100, 100, BLUE, BLACK, false);
+        assert!(!svg.contains("<g"));
+        assert_eq!(svg.matches("<polygon").count(), 1 + 1 + 3);
+        assert_eq!(svg.matches("fill=\"#0000c8\"").count(), 1);
+        assert!(svg.ends_with("</svg>\n"));
+    }
+}
@@ -5,6 +5,23 @@ pub struct RGB {
     pub blue: u8,
 }
 
+#[derive(Clone,Copy,Default,PartialEq,Eq,Hash,Debug)]
+pub struct RGBA {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+    pub alpha: u8,
+}
+
+/// Linear light with no upper limit, for accumulating renders before tone mapping.
+#[derive(Clone,Copy,Default,PartialEq,Debug)]
+pub struct RGBF {
+    pub red: f32,
+    pub green: f32,
+    pub blue: f32,
+}
+
+#[derive(Clone)]
 pub struct Canvas<T> {
     pub width: u32,
     pub height: u32,
@@ -26,23 +43,25 @@ impl<T> Canvas<T> where T : Default + Clone {
     }
 }
 
-struct GreyScaleImage {
-    canvas: Canvas<u8>,
+#[derive(Clone)]
+pub struct GreyScaleImage {
+    pub canvas: Canvas<u8>,
 }
 impl GreyScaleImage {
-    fn new(width: u32, height: u32) -> Self {
+    pub fn new(width: u32, height: u32) -> Self {
         GreyScaleImage {
             canvas: Canvas::new(width, height),
         }
     }
-    fn pixel(&self, x: u32, y: u32) -> u8 {
+    pub fn pixel(&self, x: u32, y: u32) -> u8 {
         *self.canvas.pixel(x, y)
     }
-    fn set_pixel(&mut self, x: u32, y: u32, value: u8) {
+    pub fn set_pixel(&mut self, x: u32, y: u32, value: u8) {
         self.canvas.set_pixel(x, y, value);
     }
 }
 
+#[derive(Clone)]
 pub struct Palette {
     color_index_map: std::collections::HashMap<RGB,usize>,
     index_color_map: std::collections::HashMap<usize,RGB>,
@@ -54,11 +73,21 @@ impl Palette {
             index_color_map: std::collections::HashMap::new(),
         }
     }
+    /// Builds a palette with the given colours at their positions in the slice.
+    /// Repeated colours keep their own index, but looking the colour up returns the first.
+    pub fn from_colors(colors: &[RGB]) -> Self {
+        let mut palette = Palette::new();
+        for (idx, rgb) in colors.iter().enumerate() {
+            palette.color_index_map.entry(*rgb).or_insert(idx);
+            palette.index_color_map.insert(idx, *rgb);
+        }
+        palette
+    }
     fn index(&mut self, rgb: RGB) -> usize {
         match self.color_index_map.get(&rgb) {
             Some(idx) => *idx,
             None => {
-                let idx = self.color_index_map.len();
+                let idx = self.index_color_map.len();
                 self.color_index_map.insert(rgb, idx);
                 self.index_color_map.insert(idx, rgb);
                 idx
@@ -69,10 +98,11 @@ impl Palette {
         self.index_color_map.get(&index)
     }
     pub fn size(&self) -> usize {
-        self.color_index_map.len()
+        self.index_color_map.len()
     }
 }
 
+#[derive(Clone)]
 pub struct ColorImage {
     pub palette: Palette,
     pub canvas: Canvas<usize>,
@@ -93,6 +123,76 @@ impl ColorImage {
         let index = self.canvas.pixel(x, y);
         *self.palette.color(*index).unwrap()
     }
+    /// Builds an image from interleaved bytes, where each row starts `stride` bytes after the last.
+    /// Alpha in rgba buffers is dropped.
+    pub fn from_raw(width: u32, height: u32, bytes: &[u8], layout: PixelLayout, stride: usize) -> Result<Self, RawBufferError> {
+        let pixel_size = layout.bytes_per_pixel();
+        check_raw_buffer(width, height, bytes.len(), pixel_size, stride)?;
+        let first = if bytes.len() >= 3 { RGB { red: bytes[0], green: bytes[1], blue: bytes[2] } } else { RGB::default() };
+        let mut image = ColorImage::new(width, height, first);
+        for y in 0..height {
+            let row = &bytes[y as usize * stride..];
+            for x in 0..width {
+                let pixel = &row[x as usize * pixel_size..];
+                image.set_pixel(x, y, RGB { red: pixel[0], green: pixel[1], blue: pixel[2] });
+            }
+        }
+        Ok(image)
+    }
+    /// Writes the pixels as interleaved bytes with rows `stride` bytes apart, leaving any padding as zero.
+    /// Rgba buffers are fully opaque.
+    pub fn to_raw(&self, layout: PixelLayout, stride: usize) -> Result<Vec<u8>, RawBufferError> {
+        let pixel_size = layout.bytes_per_pixel();
+        let (width, height) = (self.canvas.width, self.canvas.height);
+        let length = if height == 0 { 0 } else { stride * (height as usize - 1) + width as usize * pixel_size };
+        check_raw_buffer(width, height, length, pixel_size, stride)?;
+        let mut bytes = vec![0; length];
+        for y in 0..height {
+            for x in 0..width {
+                let color = self.pixel(x, y);
+                let offset = y as usize * stride + x as usize * pixel_size;
+                bytes[offset..offset + 3].copy_from_slice(&[color.red, color.green, color.blue]);
+                if layout == PixelLayout::Rgba {
+                    bytes[offset + 3] = 255;
+                }
+            }
+        }
+        Ok(bytes)
+    }
+}
+
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum PixelLayout {
+    Rgb,
+    Rgba,
+}
+impl PixelLayout {
+    pub fn bytes_per_pixel(&self) -> usize {
+        match self {
+            PixelLayout::Rgb => 3,
+            PixelLayout::Rgba => 4,
+        }
+    }
+}
+
+#[derive(Debug,PartialEq)]
+pub enum RawBufferError {
+    /// the stride is shorter than a row of pixels
+    StrideTooShort,
+    /// the buffer ends before the last row does
+    BufferTooShort,
+}
+
+// the last row does not need to be padded out to the full stride
+fn check_raw_buffer(width: u32, height: u32, length: usize, pixel_size: usize, stride: usize) -> Result<(), RawBufferError> {
+    let row_length = width as usize * pixel_size;
+    if stride < row_length {
+        return Err(RawBufferError::StrideTooShort);
+    }
+    if height > 0 && length < stride * (height as usize - 1) + row_length {
+        return Err(RawBufferError::BufferTooShort);
+    }
+    Ok(())
 }
 
 #[cfg(test)]
@@ -134,7 +234,7 @@ mod tests {
             RGB {red: 6, green: 2, blue: 30},
             RGB {red: 36, green: 21, blue: 11}
         ];
-        let indexes: Vec<usize> = colors.into_iter().map(|color| palette.index(*color)).collect();
+        let indexes: Vec<usize> = colors.iter().map(|color| palette.index(*color)).collect();
         assert_ne!(indexes[0],indexes[1]);
         assert_ne!(indexes[0],indexes[2]);
         assert_ne!(indexes[1],indexes[2]);
@@ -151,13 +251,27 @@ mod tests {
             RGB {red: 6, green: 2, blue: 30},
             RGB {red: 36, green: 21, blue: 11}
         ];
-        let indexes: Vec<usize> = colors.into_iter().map(|color| palette.index(*color)).collect();
+        let indexes: Vec<usize> = colors.iter().map(|color| palette.index(*color)).collect();
         let returned_colors: Vec<RGB> = indexes.into_iter().map(|index| *palette.color(index).unwrap()).collect();
         for i in 0..5 {
             assert_eq!(colors[i], returned_colors[i]);
         }
     }
 
+    #[test]
+    fn palette_from_colors_keeps_repeated_entries() {
+        let colors = [
+            RGB {red: 5, green: 2, blue: 3},
+            RGB {red: 6, green: 2, blue: 30},
+            RGB {red: 5, green: 2, blue: 3},
+        ];
+        let mut palette = Palette::from_colors(&colors);
+        assert_eq!(palette.size(), 3);
+        assert_eq!(palette.color(2), Some(&colors[0]));
+        assert_eq!(palette.index(colors[0]), 0);
+        assert_eq!(palette.index(RGB {red: 1, green: 1, blue: 1}), 3);
+    }
+
     #[test]
     fn color_image_uses_default_color() {
         let image = ColorImage::new(60, 90, RGB { red: 60, green: 30, blue: 45});
@@ -181,4 +295,31 @@ mod tests {
             }
         }
     }
+    #[test]
+    fn raw_buffers_round_trip_with_padded_rows() {
+        let mut image = ColorImage::new(3, 2, RGB { red: 0, green: 0, blue: 0 });
+        image.set_pixel(1, 0, RGB { red: 10, green: 20, blue: 30 });
+        image.set_pixel(2, 1, RGB { red: 40, green: 50, blue: 60 });
+        let bytes = image.to_raw(PixelLayout::Rgba, 16).unwrap();
+        assert_eq!(bytes, vec![
+            0, 0, 0, 255, 10, 20, 30, 255, 0, 0, 0, 255, 0, 0, 0, 0,
+            0, 0, 0, 255, 0, 0, 0, 255, 40, 50, 60, 255,
+        ]);
+        let decoded = ColorImage::from_raw(3, 2, &bytes, PixelLayout::Rgba, 16).unwrap();
+        for x in 0..3 {
+            for y in 0..2 {
+                assert_eq!(decoded.pixel(x, y), image.pixel(x, y));
+            }
+        }
+        let packed = image.to_raw(PixelLayout::Rgb, 9).unwrap();
+        assert_eq!(packed.len(), 18);
+        assert_eq!(&packed[3..6], &[10, 20, 30]);
+    }
+    #[test]
+    fn raw_buffers_are_checked_against_the_stride() {
+        let image = ColorImage::new(4, 4, RGB { red: 0, green: 0, blue: 0 });
+        assert_eq!(image.to_raw(PixelLayout::Rgb, 11).unwrap_err(), RawBufferError::StrideTooShort);
+        assert_eq!(ColorImage::from_raw(4, 4, &[0; 47], PixelLayout::Rgb, 12).err(), Some(RawBufferError::BufferTooShort));
+    }
+
 }
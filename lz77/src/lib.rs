@@ -1,22 +1,211 @@
-const WINDOW_SIZE: u32 = 32 * 1024;
-const MIN_MATCH: u32 = 3;
-const MAX_MATCH: u32 = 258;
+pub const WINDOW_SIZE: u32 = 32 * 1024;
+pub const MIN_MATCH: u32 = 3;
+pub const MAX_MATCH: u32 = 258;
+
+const HASH_BITS: u32 = 15;
+const NO_POSITION: u32 = u32::MAX;
+
+/// How hard to look for matches, trading speed against how much is found.
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub struct Effort {
+    /// how many earlier positions with the same hash are tried before settling for the best so far
+    pub max_chain: usize,
+    /// a match this long is not worth searching past
+    pub nice_length: u32,
+    /// matches shorter than this are checked against one starting a byte later, and 0 turns that off
+    pub lazy_length: u32,
+}
+impl Default for Effort {
+    fn default() -> Self {
+        Effort { max_chain: 128, nice_length: 32, lazy_length: 32 }
+    }
+}
+
+#[derive(Clone,Copy,PartialEq,Eq,Debug)]
+pub enum Token {
+    Literal(u8),
+    /// a copy of `length` bytes starting `distance` bytes back
+    Match { length: u32, distance: u32 },
+}
 
 struct StringMatch {
-    offset: u32,
+    distance: u32,
     length: u32,
-    byte_after: u32,
 }
 
-fn find_match(input: &[u8], search: &[u8]) -> StringMatch {
-    unimplemented!();
+// positions are chained by the hash of their first three bytes, most recent first
+struct HashChains {
+    head: Vec<u32>,
+    previous: Vec<u32>,
+}
+impl HashChains {
+    fn new() -> Self {
+        HashChains {
+            head: vec![NO_POSITION; 1 << HASH_BITS],
+            previous: vec![NO_POSITION; WINDOW_SIZE as usize],
+        }
+    }
+    fn hash(data: &[u8], position: usize) -> usize {
+        let value = (data[position] as u32) << 16 | (data[position + 1] as u32) << 8 | data[position + 2] as u32;
+        (value.wrapping_mul(2654435761) >> (32 - HASH_BITS)) as usize
+    }
+    fn insert(&mut self, data: &[u8], position: usize) {
+        if position + MIN_MATCH as usize > data.len() {
+            return;
+        }
+        let hash = HashChains::hash(data, position);
+        self.previous[position % WINDOW_SIZE as usize] = self.head[hash];
+        self.head[hash] = position as u32;
+    }
+    fn find_match(&self, data: &[u8], position: usize, effort: &Effort) -> Option<StringMatch> {
+        if position + MIN_MATCH as usize > data.len() {
+            return None;
+        }
+        let max_length = (data.len() - position).min(MAX_MATCH as usize);
+        let mut best: Option<StringMatch> = None;
+        let mut candidate = self.head[HashChains::hash(data, position)];
+        for _ in 0..effort.max_chain {
+            // the window is a ring, so a later position means the chain has run past it
+            if candidate == NO_POSITION || candidate as usize >= position || position - candidate as usize > WINDOW_SIZE as usize {
+                break;
+            }
+            let start = candidate as usize;
+            let length = (0..max_length).take_while(|i| data[start + i] == data[position + i]).count() as u32;
+            if length >= MIN_MATCH && best.as_ref().is_none_or(|best| length > best.length) {
+                best = Some(StringMatch { distance: (position - start) as u32, length });
+                if length >= effort.nice_length.min(max_length as u32) {
+                    break;
+                }
+            }
+            let next = self.previous[start % WINDOW_SIZE as usize];
+            if next != NO_POSITION && next >= candidate {
+                break;
+            }
+            candidate = next;
+        }
+        best
+    }
+}
+
+/// Splits data into literals and back references, deferring a match by a byte whenever that finds a longer one.
+pub fn compress(data: &[u8]) -> Vec<Token> {
+    compress_from(data, 0, &Effort::default())
+}
+
+/// Compresses data as if it followed the dictionary, so matches can refer back into the dictionary's last window.
+pub fn compress_with_dictionary(dictionary: &[u8], data: &[u8], effort: &Effort) -> Vec<Token> {
+    compress_from(&[dictionary, data].concat(), dictionary.len(), effort)
+}
+
+// tokens for the data from `start`, with the bytes before it only available to match against
+fn compress_from(data: &[u8], start: usize, effort: &Effort) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chains = HashChains::new();
+    for position in start.saturating_sub(WINDOW_SIZE as usize)..start {
+        chains.insert(data, position);
+    }
+    let mut position = start;
+    while position < data.len() {
+        let found = chains.find_match(data, position, effort);
+        chains.insert(data, position);
+        let found = match found {
+            Some(found) => found,
+            None => {
+                tokens.push(Token::Literal(data[position]));
+                position += 1;
+                continue;
+            },
+        };
+        // lazy matching: a literal now can be cheaper when the next byte starts a longer match
+        if found.length < effort.lazy_length {
+            if let Some(next) = chains.find_match(data, position + 1, effort) {
+                if next.length > found.length {
+                    tokens.push(Token::Literal(data[position]));
+                    position += 1;
+                    continue;
+                }
+            }
+        }
+        tokens.push(Token::Match { length: found.length, distance: found.distance });
+        for skipped in position + 1..position + found.length as usize {
+            chains.insert(data, skipped);
+        }
+        position += found.length as usize;
+    }
+    tokens
 }
 
-fn compress(data: &Vec<u8>) -> Vec<u8> {
-    let mut compression = Vec::new();
-    compression
+pub fn decompress(tokens: &[Token]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for token in tokens {
+        match *token {
+            Token::Literal(byte) => data.push(byte),
+            // copies can overlap the bytes they produce
+            Token::Match { length, distance } => {
+                let start = data.len() - distance as usize;
+                for i in 0..length as usize {
+                    data.push(data[start + i]);
+                }
+            },
+        }
+    }
+    data
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    #[test]
+    fn compress_finds_overlapping_matches() {
+        let tokens = compress(b"aaaaaaaaaa");
+        assert_eq!(tokens, vec![Token::Literal(b'a'), Token::Match { length: 9, distance: 1 }]);
+    }
+    #[test]
+    fn compress_prefers_the_longer_match_one_byte_later() {
+        // "bcd" first matches 3 bytes back at "bcd", but "cdefg" from the next byte is longer
+        let tokens = compress(b"cdefgabcdxbcdefg");
+        assert_eq!(decompress(&tokens), b"cdefgabcdxbcdefg");
+        assert_eq!(tokens[tokens.len() - 1], Token::Match { length: 5, distance: 11 });
+        assert_eq!(tokens[tokens.len() - 2], Token::Literal(b'b'));
+    }
+    #[test]
+    fn less_effort_takes_the_first_match() {
+        let data = b"cdefgabcdxbcdefg";
+        let effort = Effort { max_chain: 1, nice_length: 8, lazy_length: 0 };
+        let tokens = compress_with_dictionary(&[], data, &effort);
+        assert_eq!(decompress(&tokens), data);
+        assert_eq!(tokens[tokens.len() - 2], Token::Match { length: 3, distance: 4 });
+    }
+    #[test]
+    fn compress_limits_matches_to_the_window_and_maximum_length() {
+        let noise = |count: usize, seed: u32| {
+            let mut state = seed;
+            (0..count).map(move |_| {
+                state = state.wrapping_mul(1103515245).wrapping_add(12345);
+                (state >> 16) as u8
+            })
+        };
+        // a long repeat, then a copy of it from further back than the window reaches
+        let mut data: Vec<u8> = noise(500, 0).collect();
+        data.extend_from_within(0..500);
+        data.extend(noise(40000, 7));
+        data.extend_from_within(0..500);
+        let tokens = compress(&data);
+        assert_eq!(decompress(&tokens), data);
+        let matches: Vec<(u32, u32)> = tokens.iter().filter_map(|token| match *token {
+            Token::Match { length, distance } => Some((length, distance)),
+            Token::Literal(_) => None,
+        }).collect();
+        assert!(matches.iter().all(|(length, distance)| (MIN_MATCH..=MAX_MATCH).contains(length) && *distance <= WINDOW_SIZE));
+        assert_eq!(&matches[..2], &[(MAX_MATCH, 500), (500 - MAX_MATCH, 500)]);
+    }
+    #[test]
+    fn compress_with_dictionary_matches_into_the_dictionary() {
+        let tokens = compress_with_dictionary(b"sierpinski triangle", b"triangle!", &Effort::default());
+        assert_eq!(tokens, vec![Token::Match { length: 8, distance: 8 }, Token::Literal(b'!')]);
+        // only the last window of a long dictionary is searched
+        let mut dictionary = b"koch curve".to_vec();
+        dictionary.extend(vec![0; WINDOW_SIZE as usize]);
+        assert_eq!(compress_with_dictionary(&dictionary, b"koch", &Effort::default())[0], Token::Literal(b'k'));
+    }
 }
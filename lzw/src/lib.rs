@@ -33,10 +33,10 @@ impl BitArray {
             let mut i = 0;
             while current_bit < length && i < 8 {
                 if bits[current_bit] != 0 {
-                    byte = byte | BIT_MASK_U8[i];
+                    byte |= BIT_MASK_U8[i];
                 }
-                current_bit = current_bit + 1;
-                i = i + 1;
+                current_bit += 1;
+                i += 1;
             }
             packed_bits.push(byte);
         }
@@ -46,7 +46,7 @@ impl BitArray {
         }
     }
     // counts the rightmost bit_count bits
-    fn from_u32(int: u32, bit_count: usize) -> Self {
+    pub fn from_u32(int: u32, bit_count: usize) -> Self {
         let mut bits = vec![0; bit_count];
         for i in 0..bit_count {
             if int & (0x01 << i) != 0 {
@@ -55,9 +55,9 @@ impl BitArray {
         }
         BitArray::new(&bits)
     }
-    fn append(&mut self, from: &BitArray) {
+    pub fn append(&mut self, from: &BitArray) {
         let byte_offset = self.length % 8;
-        self.length = self.length + from.length;
+        self.length += from.length;
         if byte_offset == 0 {
             self.packed_bits.extend(from.packed_bits.iter());
         }
@@ -66,17 +66,17 @@ impl BitArray {
             for byte in from.packed_bits.iter() {
                 let byte = *byte;
                 let last = self.packed_bits.last_mut().unwrap();
-                *last = *last | (byte >> byte_offset);
+                *last |= byte >> byte_offset;
                 let next_byte = byte << rev_byte_offset;
                 self.packed_bits.push(next_byte);
             }
-            let byte_count = if self.length % 8 == 0 { self.length / 8 } else { (self.length / 8) + 1 };
+            let byte_count = if self.length.is_multiple_of(8) { self.length / 8 } else { (self.length / 8) + 1 };
             if self.packed_bits.len() > byte_count {
                 self.packed_bits.pop();
             }
         }
     }
-    fn range(&self, start: usize, end: usize) -> Self {
+    pub fn range(&self, start: usize, end: usize) -> Self {
         let mut bits = Vec::new();
         let mut current_byte = start / 8;
         let mut current_offset = start % 8;
@@ -86,16 +86,16 @@ impl BitArray {
             let bit = (self.packed_bits[current_byte] >> (7 - current_offset)) & 1;
             bits.push(bit);
             if current_offset == 7 {
-                current_byte = current_byte + 1;
+                current_byte += 1;
                 current_offset = 0;
             }
             else {
-                current_offset = current_offset + 1;
+                current_offset += 1;
             }
         }
         BitArray::new(&bits)
     }
-    fn to_u32(&self) -> u32 {
+    pub fn to_u32(&self) -> u32 {
         let byte_shift = ((32 - self.length) / 8) + 1;
         let bit_shift = (8 - (self.length % 8)) % 8;
         let mut int = 0;
@@ -103,10 +103,10 @@ impl BitArray {
             let total_offset: i32 = ((4 - (i + byte_shift)) * 8) as i32 - bit_shift as i32;
             let byte = self.packed_bits[i] as u32;
             if total_offset > 0 {
-                int = int | byte << total_offset;
+                int |= byte << total_offset;
             }
             else {
-                int = int | byte >> -total_offset;
+                int |= byte >> -total_offset;
             }
         }
         int
@@ -116,11 +116,18 @@ impl BitArray {
 fn get_code_length(code: u32) -> usize {
     let mut right_shift = 31;
     while ((code >> right_shift) & 1 == 0) && (right_shift != 0) {
-        right_shift = right_shift - 1;
+        right_shift -= 1;
     }
     right_shift + 1
 }
 pub fn encode(data: &[u8], max_encodings: u32, reserved_codes: u32) -> EncodingResult {
+    encode_codes(data, max_encodings, reserved_codes, false)
+}
+// with early change, as used by tiff, the code length increases one code before it is needed
+pub fn encode_early_change(data: &[u8], max_encodings: u32, reserved_codes: u32) -> EncodingResult {
+    encode_codes(data, max_encodings, reserved_codes, true)
+}
+fn encode_codes(data: &[u8], max_encodings: u32, reserved_codes: u32, early_change: bool) -> EncodingResult {
     let mut encoding = Vec::new();
     let mut dictionary: std::collections::HashMap<&[u8],u32> = std::collections::HashMap::new();
     let initial_substrings: Vec<u8> = (0..=255).collect();
@@ -140,7 +147,7 @@ pub fn encode(data: &[u8], max_encodings: u32, reserved_codes: u32) -> EncodingR
                 return EncodingResult { codes: encoding, bytes_processed: scan_end - 1 };
             }
             dictionary.insert(scan, new_code);
-            code_length = get_code_length(new_code);
+            code_length = get_code_length(if early_change { new_code + 1 } else { new_code });
             scan_start = scan_end - 1;
         }
         else if scan_end == data.len() {
@@ -149,27 +156,32 @@ pub fn encode(data: &[u8], max_encodings: u32, reserved_codes: u32) -> EncodingR
             scan_start = scan_end;
         }
         else {
-            scan_end = scan_end + 1;
+            scan_end += 1;
         }
     }
     EncodingResult { codes: encoding, bytes_processed: data.len() }
 }
-pub fn encode_all(data: &Vec<u8>, max_encodings: u32, reserved_codes: u32) -> Vec<EncodingResult> {
+pub fn encode_all(data: &[u8], max_encodings: u32, reserved_codes: u32) -> Vec<EncodingResult> {
+    encode_all_codes(data, max_encodings, reserved_codes, false)
+}
+pub fn encode_all_early_change(data: &[u8], max_encodings: u32, reserved_codes: u32) -> Vec<EncodingResult> {
+    encode_all_codes(data, max_encodings, reserved_codes, true)
+}
+fn encode_all_codes(data: &[u8], max_encodings: u32, reserved_codes: u32, early_change: bool) -> Vec<EncodingResult> {
     let mut results = Vec::new();
     let mut processed = 0;
     while processed < data.len() {
-        let result = encode(&data[processed..], max_encodings, reserved_codes);
+        let result = encode_codes(&data[processed..], max_encodings, reserved_codes, early_change);
         processed += result.bytes_processed;
         results.push(result);
     }
     results
 }
-pub fn decode(codes: &Vec<Code>, reserved_codes: u32) -> Vec<u8> {
+pub fn decode(codes: &[Code], reserved_codes: u32) -> Vec<u8> {
     let mut data = Vec::new();
     let mut dictionary: std::collections::HashMap<u32,Vec<u8>> = std::collections::HashMap::new();
-    let initial_substrings: Vec<u8> = (0..=255).collect();
     for i in 0..=255 {
-        dictionary.insert(i as u32, vec![initial_substrings[i]]);
+        dictionary.insert(i as u32, vec![i as u8]);
     }
     let previous_code = codes.first().unwrap();
     let mut previous_substring = dictionary.get(&previous_code.value).unwrap().clone();
@@ -199,6 +211,108 @@ pub fn decode(codes: &Vec<Code>, reserved_codes: u32) -> Vec<u8> {
     data
 }
 
+pub enum BitOrder {
+    // gif packs codes starting from the lowest bit of each byte
+    LsbFirst,
+    // tiff packs codes starting from the highest bit of each byte
+    MsbFirst,
+}
+
+#[derive(Debug,PartialEq)]
+pub enum DecodeError {
+    // a code that is neither in the dictionary nor the next code to be added
+    InvalidCode(u32),
+    // the data decodes to more bytes than the caller allowed
+    LimitExceeded,
+}
+
+// largest code length allowed by both gif and tiff
+const MAX_CODE_LENGTH: usize = 12;
+
+struct CodeReader<'a> {
+    data: &'a [u8],
+    bit_index: usize,
+    bit_order: BitOrder,
+}
+impl<'a> CodeReader<'a> {
+    fn read(&mut self, length: usize) -> Option<u32> {
+        if self.bit_index + length > self.data.len() * 8 {
+            return None;
+        }
+        let mut value = 0;
+        for i in 0..length {
+            let byte = self.data[self.bit_index / 8];
+            let offset = self.bit_index % 8;
+            match self.bit_order {
+                BitOrder::LsbFirst => value |= ((byte >> offset) as u32 & 1) << i,
+                BitOrder::MsbFirst => value = (value << 1) | ((byte >> (7 - offset)) as u32 & 1),
+            }
+            self.bit_index += 1;
+        }
+        Some(value)
+    }
+}
+
+/// Decodes codes packed into bytes with clear and end of information codes following the literals,
+/// so a `min_code_length` of 8 uses 256 to clear and 257 to finish.
+/// Decoding stops at the end of information code or when the data runs out.
+pub fn decode_packed(data: &[u8], min_code_length: usize, bit_order: BitOrder, early_change: bool) -> Result<Vec<u8>, DecodeError> {
+    decode_packed_with_limit(data, min_code_length, bit_order, early_change, usize::MAX)
+}
+
+/// As `decode_packed`, but fails with `LimitExceeded` rather than produce more than `max_output` bytes.
+/// A 12 bit code can stand for thousands of bytes, so a small input can otherwise decode to a huge output.
+pub fn decode_packed_with_limit(data: &[u8], min_code_length: usize, bit_order: BitOrder, early_change: bool, max_output: usize) -> Result<Vec<u8>, DecodeError> {
+    let clear_code = 1 << min_code_length;
+    let eoi_code = clear_code + 1;
+    let mut reader = CodeReader { data, bit_index: 0, bit_order };
+    let mut output = Vec::new();
+    let mut dictionary: Vec<Vec<u8>> = (0..clear_code).map(|i| vec![i as u8]).collect();
+    // the clear and end of information codes take up places in the dictionary
+    dictionary.push(Vec::new());
+    dictionary.push(Vec::new());
+    let mut code_length = min_code_length + 1;
+    let mut previous: Option<usize> = None;
+    while let Some(code) = reader.read(code_length) {
+        if code == clear_code {
+            dictionary.truncate(eoi_code as usize + 1);
+            code_length = min_code_length + 1;
+            previous = None;
+            continue;
+        }
+        if code == eoi_code {
+            break;
+        }
+        let code = code as usize;
+        let entry = match previous {
+            Some(previous) if code == dictionary.len() => {
+                let mut entry = dictionary[previous].clone();
+                entry.push(entry[0]);
+                entry
+            },
+            _ if code < dictionary.len() && code != clear_code as usize => dictionary[code].clone(),
+            _ => return Err(DecodeError::InvalidCode(code as u32)),
+        };
+        if output.len() + entry.len() > max_output {
+            return Err(DecodeError::LimitExceeded);
+        }
+        output.extend(entry.iter());
+        if let Some(previous) = previous {
+            if dictionary.len() < 1 << MAX_CODE_LENGTH {
+                let mut new_entry = dictionary[previous].clone();
+                new_entry.push(entry[0]);
+                dictionary.push(new_entry);
+            }
+        }
+        let next_code = dictionary.len() + if early_change { 1 } else { 0 };
+        if next_code >= 1 << code_length && code_length < MAX_CODE_LENGTH {
+            code_length += 1;
+        }
+        previous = Some(code);
+    }
+    Ok(output)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -426,4 +540,78 @@ mod tests {
         }
         assert_eq!(decoding, data);
     }
+
+    fn pack_lsb_first(codes: &[(u32, usize)]) -> Vec<u8> {
+        let mut data = Vec::new();
+        let mut bit_index = 0;
+        for (value, length) in codes {
+            for i in 0..*length {
+                if bit_index % 8 == 0 {
+                    data.push(0);
+                }
+                *data.last_mut().unwrap() |= (((value >> i) & 1) as u8) << (bit_index % 8);
+                bit_index += 1;
+            }
+        }
+        data
+    }
+    fn pack_msb_first(codes: &[(u32, usize)]) -> Vec<u8> {
+        let mut array = BitArray::new(&[]);
+        for (value, length) in codes {
+            array.append(&BitArray::from_u32(*value, *length));
+        }
+        array.packed_bits
+    }
+    fn packed_codes(encodings: &[EncodingResult], early_change: bool) -> Vec<(u32, usize)> {
+        let mut codes = vec![(256, 9)];
+        for (i, encoding) in encodings.iter().enumerate() {
+            codes.extend(encoding.codes.iter().map(|code| (code.value, code.length)));
+            if i < encodings.len() - 1 {
+                codes.push((256, 12));
+            }
+        }
+        let next_code = 258 + encodings.last().unwrap().codes.len() as u32 - 1;
+        let eoi_length = get_code_length(if early_change { next_code + 1 } else { next_code });
+        codes.push((257, eoi_length.min(12)));
+        codes
+    }
+    #[test]
+    fn decode_packed_reads_lsb_first_codes() {
+        let data: Vec<u8> = vec![5,6,7,8,5,6,7,5,6,7,7,6,5,4];
+        let packed = pack_lsb_first(&packed_codes(&encode_all(&data, 4095, 2), false));
+        assert_eq!(decode_packed(&packed, 8, BitOrder::LsbFirst, false), Ok(data));
+    }
+    #[test]
+    fn decode_packed_reads_msb_first_early_change_codes_across_clears() {
+        let mut state: u32 = 1;
+        let mut data = Vec::new();
+        for _ in 0..30000 {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            data.push((state >> 16) as u8 % 16);
+        }
+        let encodings = encode_all_early_change(&data, 4094, 2);
+        assert!(encodings.len() > 1);
+        let packed = pack_msb_first(&packed_codes(&encodings, true));
+        assert_eq!(decode_packed(&packed, 8, BitOrder::MsbFirst, true), Ok(data));
+    }
+    #[test]
+    fn encode_early_change_lengthens_codes_one_code_sooner() {
+        let data: Vec<u8> = (0..=255).chain(0..=255).collect();
+        let late = encode(&data, 4095, 2);
+        let early = encode_early_change(&data, 4095, 2);
+        let first_long_code = |result: &EncodingResult| result.codes.iter().position(|code| code.length == 10).unwrap();
+        assert_eq!(first_long_code(&early) + 1, first_long_code(&late));
+    }
+    #[test]
+    fn decode_packed_rejects_codes_past_the_dictionary() {
+        let packed = pack_lsb_first(&[(256, 9), (5, 9), (300, 9)]);
+        assert_eq!(decode_packed(&packed, 8, BitOrder::LsbFirst, false), Err(DecodeError::InvalidCode(300)));
+    }
+    #[test]
+    fn decode_packed_with_limit_stops_before_the_limit() {
+        let data = vec![9; 5000];
+        let packed = pack_lsb_first(&packed_codes(&encode_all(&data, 4095, 2), false));
+        assert_eq!(decode_packed_with_limit(&packed, 8, BitOrder::LsbFirst, false, 5000), Ok(data));
+        assert_eq!(decode_packed_with_limit(&packed, 8, BitOrder::LsbFirst, false, 4999), Err(DecodeError::LimitExceeded));
+    }
 }
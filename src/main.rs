@@ -1,5 +1,3 @@
-use chunk_writer::Image;
-
 fn main() {
     /*let mut colour_image = image::ColorImage::new(256, 256, image::RGB { red: 0, green: 0, blue: 0 });
     for i in 0..256 {
@@ -12,6 +10,9 @@ fn main() {
     fractals::sierpinski_triangle(&mut colour_image, 5, 1200, 1200, image::RGB { red: 0, green: 0, blue: 200 }, image::RGB { red: 200, green: 200, blue: 0 });
 
     println!("encoding...");
-    let writer = chunk_writer::gif::GifImage { image: colour_image };
-    writer.write("test.gif").unwrap();
+    chunk_writer::save(&colour_image, "test.gif").unwrap();
+    // sixel capable terminals can show the result straight away
+    if std::env::args().any(|arg| arg == "--preview") {
+        print!("{}", chunk_writer::terminal::sixel(&colour_image).unwrap());
+    }
 }
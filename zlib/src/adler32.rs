@@ -0,0 +1,133 @@
+use std::io;
+
+const BASE: u32 = 65521;
+// the most bytes that can be summed before s2 could overflow a u32, starting from sums below BASE
+const NMAX: usize = 5552;
+const UNROLL: usize = 16;
+
+pub(crate) fn adler(data: &[u8]) -> u32 {
+    adler32_update(1, data)
+}
+
+/// Continues the Adler-32 `checksum` over more data; a new checksum starts from 1.
+pub fn adler32_update(checksum: u32, data: &[u8]) -> u32 {
+    let mut s1 = checksum & 0xffff;
+    let mut s2 = (checksum >> 16) & 0xffff;
+    for block in data.chunks(NMAX) {
+        let (groups, rest) = block.as_chunks::<UNROLL>();
+        for group in groups {
+            // one byte at a time, s2 gains s1 once for each byte and each byte again for every byte
+            // from it to the end of the group, so the group can be summed without one add waiting on the last.
+            // neither sum can pass 16 * 17 / 2 * 255, so they fit 16 bit lanes
+            let mut sum = 0u16;
+            let mut weighted = 0u16;
+            for (i, byte) in group.iter().enumerate() {
+                sum += *byte as u16;
+                weighted += (UNROLL - i) as u16 * *byte as u16;
+            }
+            s2 += UNROLL as u32 * s1 + weighted as u32;
+            s1 += sum as u32;
+        }
+        for byte in rest {
+            s1 += *byte as u32;
+            s2 += s1;
+        }
+        s1 %= BASE;
+        s2 %= BASE;
+    }
+    (s2 << 16) | s1
+}
+
+/// The Adler-32 of two pieces of data one after the other, from the checksum of each and the length of the second.
+pub fn adler32_combine(first: u32, second: u32, second_length: usize) -> u32 {
+    let base = BASE as u64;
+    let length = second_length as u64 % base;
+    let (first_s1, first_s2) = ((first & 0xffff) as u64, (first >> 16) as u64);
+    let (second_s1, second_s2) = ((second & 0xffff) as u64, (second >> 16) as u64);
+    // both sums of the second piece start from 1 rather than where the first left off,
+    // and every byte of it adds the first piece's s1 to s2 once more
+    let s1 = (first_s1 + second_s1 + base - 1) % base;
+    let s2 = (first_s2 + second_s2 + length * first_s1 + base - length) % base;
+    (s2 << 16 | s1) as u32
+}
+
+/// An Adler-32 checksum fed a piece at a time, which also takes data through `io::Write`.
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub struct Adler32 {
+    checksum: u32,
+}
+impl Adler32 {
+    pub fn new() -> Self {
+        Adler32 { checksum: 1 }
+    }
+    pub fn update(&mut self, data: &[u8]) {
+        self.checksum = adler32_update(self.checksum, data);
+    }
+    /// Appends the data another checksum covers, given how many bytes that was.
+    pub fn combine(&mut self, other: &Adler32, other_length: usize) {
+        self.checksum = adler32_combine(self.checksum, other.checksum, other_length);
+    }
+    pub fn checksum(&self) -> u32 {
+        self.checksum
+    }
+}
+impl Default for Adler32 {
+    fn default() -> Self {
+        Adler32::new()
+    }
+}
+impl io::Write for Adler32 {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.update(data);
+        Ok(data.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn bytewise(data: &[u8]) -> u32 {
+        let (mut s1, mut s2) = (1, 0);
+        for byte in data {
+            s1 = (s1 + *byte as u32) % BASE;
+            s2 = (s1 + s2) % BASE;
+        }
+        (s2 << 16) | s1
+    }
+    #[test]
+    fn adler_matches_known_value() {
+        assert_eq!(adler(b"Wikipedia"), 0x11e60398);
+        assert_eq!(adler(&[]), 1);
+    }
+    #[test]
+    fn deferred_sums_match_the_bytewise_checksum() {
+        // all 0xff is the input that brings the sums closest to overflowing
+        for length in [1, 15, 16, 17, NMAX - 1, NMAX, NMAX + 1, 3 * NMAX + 100] {
+            let data = vec![0xff; length];
+            assert_eq!(adler(&data), bytewise(&data), "{}", length);
+        }
+        let data: Vec<u8> = (0..50000u32).map(|i| (i.wrapping_mul(2654435761) >> 24) as u8).collect();
+        assert_eq!(adler(&data), bytewise(&data));
+        assert_eq!(adler32_update(adler(&data[..777]), &data[777..]), bytewise(&data));
+    }
+    #[test]
+    fn adler32_combine_matches_the_joined_data() {
+        let data: Vec<u8> = (0..20000u32).map(|i| (i.wrapping_mul(i) >> 5) as u8).collect();
+        for split in [0, 1, 5552, 12345, 20000] {
+            let (first, second) = data.split_at(split);
+            assert_eq!(adler32_combine(adler(first), adler(second), second.len()), adler(&data));
+        }
+    }
+    #[test]
+    fn hashers_take_data_in_pieces() {
+        let mut hasher = Adler32::default();
+        io::copy(&mut &b"Wiki"[..], &mut hasher).unwrap();
+        let mut rest = Adler32::new();
+        rest.update(b"pedia");
+        hasher.combine(&rest, 5);
+        assert_eq!(hasher.checksum(), 0x11e60398);
+    }
+}
@@ -0,0 +1,144 @@
+// deflate packs values from the least significant bit of each byte
+pub(crate) struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u64,
+    count: u32,
+}
+impl BitWriter {
+    pub(crate) fn new() -> Self {
+        BitWriter { bytes: Vec::new(), buffer: 0, count: 0 }
+    }
+    pub(crate) fn write_bits(&mut self, value: u32, count: u32) {
+        self.buffer |= (value as u64 & ((1 << count) - 1)) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.count -= 8;
+        }
+    }
+    /// Huffman codes are written starting from their most significant bit.
+    pub(crate) fn write_code(&mut self, code: u16, length: u8) {
+        let reversed = code.reverse_bits() >> (16 - length as u32);
+        self.write_bits(reversed as u32, length as u32);
+    }
+    /// Pads with zeros up to the next byte boundary.
+    pub(crate) fn align(&mut self) {
+        if self.count > 0 {
+            self.write_bits(0, 8 - self.count);
+        }
+    }
+    pub(crate) fn write_aligned_bytes(&mut self, bytes: &[u8]) {
+        self.align();
+        self.bytes.extend(bytes);
+    }
+    /// Hands over the completed bytes, keeping any bits of a partly filled byte.
+    pub(crate) fn take_bytes(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.bytes)
+    }
+    pub(crate) fn finish(mut self) -> Vec<u8> {
+        self.align();
+        self.bytes
+    }
+}
+
+// reads values packed by BitWriter, returning None when the data runs out
+#[derive(Clone)]
+pub(crate) struct BitReader<'a> {
+    data: &'a [u8],
+    position: usize,
+    buffer: u64,
+    count: u32,
+}
+impl<'a> BitReader<'a> {
+    #[cfg(test)]
+    pub(crate) fn new(data: &'a [u8]) -> Self {
+        BitReader { data, position: 0, buffer: 0, count: 0 }
+    }
+    /// Carries on from bits left over when an earlier reader was suspended.
+    pub(crate) fn resume(data: &'a [u8], bits: u64, count: u32) -> Self {
+        BitReader { data, position: 0, buffer: bits, count }
+    }
+    /// The bytes used so far and the unused bits of the last one, handing back any whole bytes still buffered.
+    pub(crate) fn suspend(mut self) -> (usize, u64, u32) {
+        let partial = self.count % 8;
+        self.position -= (self.count / 8) as usize;
+        (self.position, self.buffer & ((1 << partial) - 1), partial)
+    }
+    pub(crate) fn available_bits(&self) -> usize {
+        self.count as usize + (self.data.len() - self.position) * 8
+    }
+    fn refill(&mut self) {
+        while self.count <= 56 && self.position < self.data.len() {
+            self.buffer |= (self.data[self.position] as u64) << self.count;
+            self.position += 1;
+            self.count += 8;
+        }
+    }
+    /// The next bits without consuming them, padded with zeros past the end of the data.
+    pub(crate) fn peek(&mut self, count: u32) -> u32 {
+        if self.count < count {
+            self.refill();
+        }
+        (self.buffer & ((1 << count) - 1)) as u32
+    }
+    pub(crate) fn consume(&mut self, count: u32) -> Option<()> {
+        if self.count < count {
+            self.refill();
+            if self.count < count {
+                return None;
+            }
+        }
+        self.buffer >>= count;
+        self.count -= count;
+        Some(())
+    }
+    pub(crate) fn read_bits(&mut self, count: u32) -> Option<u32> {
+        let value = self.peek(count);
+        self.consume(count)?;
+        Some(value)
+    }
+    /// Skips to the next byte boundary, handing back any whole bytes still buffered.
+    pub(crate) fn align(&mut self) {
+        self.buffer = 0;
+        self.position -= (self.count / 8) as usize;
+        self.count = 0;
+    }
+    pub(crate) fn read_aligned_bytes(&mut self, length: usize) -> Option<&'a [u8]> {
+        self.align();
+        let bytes = self.data.get(self.position..self.position + length)?;
+        self.position += length;
+        Some(bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn bits_fill_bytes_from_the_lowest_bit() {
+        let mut writer = BitWriter::new();
+        writer.write_bits(0b1, 1);
+        writer.write_bits(0b01, 2);
+        writer.write_code(0b0011, 4);
+        writer.write_bits(0x1ff, 9);
+        assert_eq!(writer.finish(), vec![0b1110_0011, 0xff]);
+    }
+    #[test]
+    fn reader_returns_what_the_writer_wrote() {
+        let mut writer = BitWriter::new();
+        writer.write_bits(0b101, 3);
+        writer.write_bits(0x1234, 16);
+        writer.write_aligned_bytes(&[7, 8, 9]);
+        writer.write_bits(0b11, 2);
+        let bytes = writer.finish();
+        let mut reader = BitReader::new(&bytes);
+        assert_eq!(reader.read_bits(3), Some(0b101));
+        assert_eq!(reader.read_bits(16), Some(0x1234));
+        assert_eq!(reader.read_aligned_bytes(3), Some(&[7, 8, 9][..]));
+        assert_eq!(reader.peek(8), 0b11);
+        assert_eq!(reader.read_bits(2), Some(0b11));
+        assert_eq!(reader.read_bits(7), None);
+        assert_eq!(reader.suspend(), (bytes.len(), 0, 6));
+    }
+}
@@ -0,0 +1,362 @@
+use crate::bits::BitWriter;
+use crate::huffman::{canonical_codes, code_lengths};
+use crate::CompressionLevel;
+use lz77::Token;
+
+pub(crate) const BTYPE_STORED: u8 = 0;
+pub(crate) const BTYPE_FIXED: u8 = 1;
+pub(crate) const BTYPE_DYNAMIC: u8 = 2;
+// stored blocks hold at most this many bytes, the largest length that fits in LEN
+pub(crate) const MAX_STORED_BLOCK: usize = 0xffff;
+// each block gets its own codes, so they can follow changes in the data
+const BLOCK_TOKENS: usize = 16384;
+
+pub(crate) const END_OF_BLOCK: u16 = 256;
+pub(crate) const FIRST_LENGTH_SYMBOL: u16 = 257;
+pub(crate) const LITERAL_SYMBOLS: usize = 286;
+pub(crate) const DISTANCE_SYMBOLS: usize = 30;
+pub(crate) const MAX_CODE_LENGTH: u8 = 15;
+pub(crate) const MAX_CODE_LENGTH_CODE_LENGTH: u8 = 7;
+pub(crate) const CODE_LENGTH_SYMBOLS: usize = 19;
+// code length code lengths are sent in this order, so the rarely used ones can be trimmed from the end
+pub(crate) const CODE_LENGTH_ORDER: [usize; CODE_LENGTH_SYMBOLS] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+pub(crate) const REPEAT_PREVIOUS: u8 = 16;
+pub(crate) const REPEAT_ZERO: u8 = 17;
+pub(crate) const REPEAT_ZERO_LONG: u8 = 18;
+
+// match lengths 3..=258 map to symbols 257..=285, each a base plus extra bits
+pub(crate) const LENGTH_BASES: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+pub(crate) const LENGTH_EXTRA_BITS: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+pub(crate) const DISTANCE_BASES: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+pub(crate) const DISTANCE_EXTRA_BITS: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+pub(crate) const FIXED_DISTANCE_CODE_LENGTH: u8 = 5;
+
+/// The symbol for a value from a base table, and the extra bits that follow it.
+pub(crate) fn base_and_extra(value: u32, bases: &[u16], extra_bits: &[u8]) -> (usize, u8, u32) {
+    let index = bases.iter().rposition(|base| *base as u32 <= value).unwrap();
+    (index, extra_bits[index], value - bases[index] as u32)
+}
+
+/// The code and length of a literal/length symbol in the fixed Huffman table.
+pub(crate) fn fixed_literal_code(symbol: u16) -> (u16, u8) {
+    match symbol {
+        0..=143 => (0x30 + symbol, 8),
+        144..=255 => (0x190 + symbol - 144, 9),
+        256..=279 => (symbol - 256, 7),
+        _ => (0xc0 + symbol - 280, 8),
+    }
+}
+
+/// Writes tokens and the end of block symbol with the given codes for each alphabet.
+pub(crate) fn write_tokens<L, D>(writer: &mut BitWriter, tokens: &[Token], literal_code: L, distance_code: D)
+where L: Fn(u16) -> (u16, u8), D: Fn(u16) -> (u16, u8) {
+    for token in tokens {
+        match *token {
+            Token::Literal(byte) => {
+                let (code, length) = literal_code(byte as u16);
+                writer.write_code(code, length);
+            },
+            Token::Match { length, distance } => {
+                let (index, extra_bits, extra) = base_and_extra(length, &LENGTH_BASES, &LENGTH_EXTRA_BITS);
+                let (code, code_length) = literal_code(FIRST_LENGTH_SYMBOL + index as u16);
+                writer.write_code(code, code_length);
+                writer.write_bits(extra, extra_bits as u32);
+                let (index, extra_bits, extra) = base_and_extra(distance, &DISTANCE_BASES, &DISTANCE_EXTRA_BITS);
+                let (code, code_length) = distance_code(index as u16);
+                writer.write_code(code, code_length);
+                writer.write_bits(extra, extra_bits as u32);
+            },
+        }
+    }
+    let (code, length) = literal_code(END_OF_BLOCK);
+    writer.write_code(code, length);
+}
+
+fn write_block_header(writer: &mut BitWriter, last: bool, btype: u8) {
+    writer.write_bits(last as u32, 1);
+    writer.write_bits(btype as u32, 2);
+}
+
+pub(crate) fn write_stored_blocks(writer: &mut BitWriter, data: &[u8], last: bool) {
+    let mut chunks: Vec<&[u8]> = data.chunks(MAX_STORED_BLOCK).collect();
+    // empty data still needs a block
+    if chunks.is_empty() {
+        chunks.push(&[]);
+    }
+    let final_chunk = chunks.len() - 1;
+    for (i, chunk) in chunks.into_iter().enumerate() {
+        write_block_header(writer, last && i == final_chunk, BTYPE_STORED);
+        let length = chunk.len() as u16;
+        writer.write_aligned_bytes(&length.to_le_bytes());
+        writer.write_aligned_bytes(&(!length).to_le_bytes());
+        writer.write_aligned_bytes(chunk);
+    }
+}
+
+fn fixed_distance_code(symbol: u16) -> (u16, u8) {
+    (symbol, FIXED_DISTANCE_CODE_LENGTH)
+}
+
+fn write_fixed_block(writer: &mut BitWriter, tokens: &[Token], last: bool) {
+    write_block_header(writer, last, BTYPE_FIXED);
+    write_tokens(writer, tokens, fixed_literal_code, fixed_distance_code);
+}
+
+/// Deflate data made of stored blocks, which frame the bytes without compressing them.
+pub fn stored_blocks(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+    write_stored_blocks(&mut writer, data, true);
+    writer.finish()
+}
+
+/// Deflate data as a single block using the fixed Huffman codes, with repeats found by lz77.
+pub fn fixed_blocks(data: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+    write_fixed_block(&mut writer, &lz77::compress(data), true);
+    writer.finish()
+}
+
+/// Code lengths as the symbols 0 to 15, and runs as 16 (repeat the previous length),
+/// 17 and 18 (repeat zero), each paired with the value of its extra bits.
+pub(crate) fn run_length_encode(lengths: &[u8]) -> Vec<(u8, u8)> {
+    let mut runs = Vec::new();
+    let mut previous = None;
+    let mut i = 0;
+    while i < lengths.len() {
+        let value = lengths[i];
+        let run = lengths[i..].iter().take_while(|length| **length == value).count();
+        if value == 0 && run >= 3 {
+            let count = run.min(138);
+            if count >= 11 {
+                runs.push((REPEAT_ZERO_LONG, (count - 11) as u8));
+            } else {
+                runs.push((REPEAT_ZERO, (count - 3) as u8));
+            }
+            previous = Some(0);
+            i += count;
+        } else if previous == Some(value) && run >= 3 {
+            let count = run.min(6);
+            runs.push((REPEAT_PREVIOUS, (count - 3) as u8));
+            i += count;
+        } else {
+            runs.push((value, 0));
+            previous = Some(value);
+            i += 1;
+        }
+    }
+    runs
+}
+
+pub(crate) fn code_length_extra_bits(symbol: u8) -> u8 {
+    match symbol {
+        REPEAT_PREVIOUS => 2,
+        REPEAT_ZERO => 3,
+        REPEAT_ZERO_LONG => 7,
+        _ => 0,
+    }
+}
+
+// the code lengths a dynamic block starts with, and the code they are sent in
+struct DynamicHeader {
+    literal_lengths: Vec<u8>,
+    distance_lengths: Vec<u8>,
+    runs: Vec<(u8, u8)>,
+    code_length_lengths: Vec<u8>,
+    code_length_count: usize,
+}
+impl DynamicHeader {
+    fn new(literal_frequencies: &[u32], distance_frequencies: &[u32]) -> Self {
+        let mut literal_lengths = code_lengths(literal_frequencies, MAX_CODE_LENGTH);
+        let mut distance_lengths = code_lengths(distance_frequencies, MAX_CODE_LENGTH);
+        // a block without matches still sends one distance code
+        if distance_lengths.iter().all(|length| *length == 0) {
+            distance_lengths[0] = 1;
+        }
+        let used = |lengths: &[u8]| lengths.iter().rposition(|length| *length > 0).map_or(0, |last| last + 1);
+        literal_lengths.truncate(used(&literal_lengths).max(FIRST_LENGTH_SYMBOL as usize));
+        distance_lengths.truncate(used(&distance_lengths).max(1));
+
+        let runs = run_length_encode(&[&literal_lengths[..], &distance_lengths[..]].concat());
+        let mut run_frequencies = [0u32; CODE_LENGTH_SYMBOLS];
+        for (symbol, _) in &runs {
+            run_frequencies[*symbol as usize] += 1;
+        }
+        let code_length_lengths = code_lengths(&run_frequencies, MAX_CODE_LENGTH_CODE_LENGTH);
+        let in_order: Vec<u8> = CODE_LENGTH_ORDER.iter().map(|symbol| code_length_lengths[*symbol]).collect();
+        let code_length_count = used(&in_order).max(4);
+        DynamicHeader { literal_lengths, distance_lengths, runs, code_length_lengths, code_length_count }
+    }
+    fn bits(&self) -> u64 {
+        let runs: u64 = self.runs.iter()
+            .map(|(symbol, _)| (self.code_length_lengths[*symbol as usize] + code_length_extra_bits(*symbol)) as u64)
+            .sum();
+        5 + 5 + 4 + 3 * self.code_length_count as u64 + runs
+    }
+    fn write(&self, writer: &mut BitWriter) {
+        writer.write_bits((self.literal_lengths.len() - FIRST_LENGTH_SYMBOL as usize) as u32, 5);
+        writer.write_bits((self.distance_lengths.len() - 1) as u32, 5);
+        writer.write_bits((self.code_length_count - 4) as u32, 4);
+        for symbol in &CODE_LENGTH_ORDER[..self.code_length_count] {
+            writer.write_bits(self.code_length_lengths[*symbol] as u32, 3);
+        }
+        let codes = canonical_codes(&self.code_length_lengths);
+        for (symbol, extra) in &self.runs {
+            writer.write_code(codes[*symbol as usize], self.code_length_lengths[*symbol as usize]);
+            writer.write_bits(*extra as u32, code_length_extra_bits(*symbol) as u32);
+        }
+    }
+}
+
+fn symbol_frequencies(tokens: &[Token]) -> (Vec<u32>, Vec<u32>) {
+    let mut literals = vec![0; LITERAL_SYMBOLS];
+    let mut distances = vec![0; DISTANCE_SYMBOLS];
+    for token in tokens {
+        match *token {
+            Token::Literal(byte) => literals[byte as usize] += 1,
+            Token::Match { length, distance } => {
+                literals[FIRST_LENGTH_SYMBOL as usize + base_and_extra(length, &LENGTH_BASES, &LENGTH_EXTRA_BITS).0] += 1;
+                distances[base_and_extra(distance, &DISTANCE_BASES, &DISTANCE_EXTRA_BITS).0] += 1;
+            },
+        }
+    }
+    literals[END_OF_BLOCK as usize] = 1;
+    (literals, distances)
+}
+
+// the size of the symbols and their extra bits, with the given code length for each symbol
+fn data_bits<L, D>(literal_frequencies: &[u32], distance_frequencies: &[u32], literal_length: L, distance_length: D) -> u64
+where L: Fn(usize) -> u8, D: Fn(usize) -> u8 {
+    let literals: u64 = literal_frequencies.iter().enumerate().map(|(symbol, frequency)| {
+        let extra = symbol.checked_sub(FIRST_LENGTH_SYMBOL as usize).map_or(0, |index| LENGTH_EXTRA_BITS[index]);
+        *frequency as u64 * (literal_length(symbol) + extra) as u64
+    }).sum();
+    let distances: u64 = distance_frequencies.iter().enumerate().map(|(symbol, frequency)| {
+        *frequency as u64 * (distance_length(symbol) + DISTANCE_EXTRA_BITS[symbol]) as u64
+    }).sum();
+    literals + distances
+}
+
+// writes whichever of a stored, fixed or dynamic block is smallest for these tokens
+fn write_block(writer: &mut BitWriter, tokens: &[Token], data: &[u8], last: bool) {
+    let (literal_frequencies, distance_frequencies) = symbol_frequencies(tokens);
+    let header = DynamicHeader::new(&literal_frequencies, &distance_frequencies);
+    let dynamic_bits = header.bits() + data_bits(&literal_frequencies, &distance_frequencies,
+        |symbol| header.literal_lengths.get(symbol).copied().unwrap_or(0),
+        |symbol| header.distance_lengths.get(symbol).copied().unwrap_or(0));
+    let fixed_bits = data_bits(&literal_frequencies, &distance_frequencies,
+        |symbol| fixed_literal_code(symbol as u16).1,
+        |_| FIXED_DISTANCE_CODE_LENGTH);
+    // allowing for the most padding before each LEN
+    let stored_bits = (data.len() / MAX_STORED_BLOCK + 1) as u64 * (7 + 32) + 8 * data.len() as u64;
+
+    if stored_bits <= fixed_bits.min(dynamic_bits) {
+        write_stored_blocks(writer, data, last);
+    } else if fixed_bits <= dynamic_bits {
+        write_fixed_block(writer, tokens, last);
+    } else {
+        write_block_header(writer, last, BTYPE_DYNAMIC);
+        header.write(writer);
+        let literal_codes = canonical_codes(&header.literal_lengths);
+        let distance_codes = canonical_codes(&header.distance_lengths);
+        write_tokens(writer, tokens,
+            |symbol| (literal_codes[symbol as usize], header.literal_lengths[symbol as usize]),
+            |symbol| (distance_codes[symbol as usize], header.distance_lengths[symbol as usize]));
+    }
+}
+
+fn token_length(token: &Token) -> usize {
+    match *token {
+        Token::Literal(_) => 1,
+        Token::Match { length, .. } => length as usize,
+    }
+}
+
+/// Writes data as blocks that can refer back into `history`, the data written before it.
+pub(crate) fn write_blocks(writer: &mut BitWriter, history: &[u8], data: &[u8], last: bool, level: CompressionLevel) {
+    let effort = match level.effort() {
+        Some(effort) => effort,
+        None => return write_stored_blocks(writer, data, last),
+    };
+    let tokens = lz77::compress_with_dictionary(history, data, &effort);
+    let mut blocks: Vec<&[Token]> = tokens.chunks(BLOCK_TOKENS).collect();
+    if blocks.is_empty() {
+        blocks.push(&[]);
+    }
+    let mut position = 0;
+    let final_block = blocks.len() - 1;
+    for (i, block) in blocks.into_iter().enumerate() {
+        let length: usize = block.iter().map(token_length).sum();
+        write_block(writer, block, &data[position..position + length], last && i == final_block);
+        position += length;
+    }
+}
+
+/// Deflate data split into blocks, each stored, or coded with the fixed or its own dynamic Huffman codes, whichever is smallest.
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    deflate_with_level(data, CompressionLevel::DEFAULT)
+}
+
+/// Deflate data from a search for repeats as thorough as `level` asks for.
+pub fn deflate_with_level(data: &[u8], level: CompressionLevel) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+    write_blocks(&mut writer, &[], data, true, level);
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn fixed_blocks_encode_literals_and_copies() {
+        assert_eq!(fixed_blocks(b""), vec![0x03, 0x00]);
+        assert_eq!(fixed_blocks(b"a"), vec![0x4b, 0x04, 0x00]);
+        // copies that overlap the bytes they produce
+        assert_eq!(fixed_blocks(b"aaaaaaaaaa"), vec![0x4b, 0x84, 0x03, 0x00]);
+        assert_eq!(fixed_blocks(b"abcabcabcabc"), vec![0x4b, 0x4c, 0x4a, 0x86, 0x23, 0x00]);
+    }
+    #[test]
+    fn lengths_and_distances_split_into_symbols_and_extra_bits() {
+        assert_eq!(base_and_extra(3, &LENGTH_BASES, &LENGTH_EXTRA_BITS), (0, 0, 0));
+        assert_eq!(base_and_extra(12, &LENGTH_BASES, &LENGTH_EXTRA_BITS), (8, 1, 1));
+        assert_eq!(base_and_extra(257, &LENGTH_BASES, &LENGTH_EXTRA_BITS), (27, 5, 30));
+        assert_eq!(base_and_extra(258, &LENGTH_BASES, &LENGTH_EXTRA_BITS), (28, 0, 0));
+        assert_eq!(base_and_extra(32768, &DISTANCE_BASES, &DISTANCE_EXTRA_BITS), (29, 13, 8191));
+    }
+    #[test]
+    fn run_length_encode_uses_repeat_symbols() {
+        let lengths = [3, 3, 3, 3, 3, 3, 3, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 5, 0, 0, 2, 2];
+        let mut runs = run_length_encode(&lengths);
+        assert_eq!(runs, vec![(3, 0), (REPEAT_PREVIOUS, 3), (3, 0), (REPEAT_ZERO_LONG, 1), (5, 0), (0, 0), (0, 0), (2, 0), (2, 0)]);
+        runs = run_length_encode(&[0; 145]);
+        assert_eq!(runs, vec![(REPEAT_ZERO_LONG, 127), (REPEAT_ZERO, 4)]);
+    }
+    #[test]
+    fn deflate_picks_the_smallest_block_type() {
+        let mut state = 1u32;
+        let mut random = move || {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            state >> 16
+        };
+        let btype = |data: &[u8]| (deflate(data)[0] >> 1) & 3;
+        assert_eq!(btype(b"a"), BTYPE_FIXED);
+        // words in a random order have skewed literals but few long matches
+        let words = ["fractal ", "triangle ", "sierpinski ", "carpet ", "curve ", "dragon "];
+        let text: Vec<u8> = (0..300).flat_map(|_| words[random() as usize % words.len()].bytes()).collect();
+        assert_eq!(btype(&text), BTYPE_DYNAMIC);
+        assert!(deflate(&text).len() < fixed_blocks(&text).len());
+        let noise: Vec<u8> = (0..1000).map(|_| random() as u8).collect();
+        assert_eq!(deflate(&noise), stored_blocks(&noise));
+    }
+}
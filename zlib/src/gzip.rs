@@ -0,0 +1,344 @@
+use crate::stream::{finished_error, Compressor, Flush};
+use crate::{deflate, inflate, CompressionLevel, DecodeError, Limits};
+use std::io::{self, Write};
+
+const SIGNATURE: [u8; 2] = [0x1f, 0x8b];
+const CM_DEFLATE: u8 = 8;
+const FHCRC: u8 = 0x02;
+const FEXTRA: u8 = 0x04;
+const FNAME: u8 = 0x08;
+const FCOMMENT: u8 = 0x10;
+// the top three flag bits are reserved and must be zero
+const RESERVED_FLAGS: u8 = 0xe0;
+pub const OS_UNKNOWN: u8 = 255;
+// XFL values claiming the slowest and the fastest compression
+const XFL_BEST: u8 = 2;
+const XFL_FASTEST: u8 = 4;
+// the fixed part of the header, before any optional fields
+const HEADER_SIZE: usize = 10;
+const TRAILER_SIZE: usize = 8;
+
+/// The fields of a gzip member header.
+#[derive(Clone,PartialEq,Debug)]
+pub struct GzipHeader {
+    /// seconds since the unix epoch, or 0 when there is no time
+    pub mtime: u32,
+    /// the original file name in latin-1, without its terminating zero
+    pub name: Option<Vec<u8>>,
+    /// a latin-1 comment, without its terminating zero
+    pub comment: Option<Vec<u8>>,
+    /// extra field subfields, stored as given
+    pub extra: Option<Vec<u8>>,
+    /// whether the header ends with the low 16 bits of its own CRC-32
+    pub header_crc: bool,
+    pub os: u8,
+}
+impl Default for GzipHeader {
+    fn default() -> Self {
+        GzipHeader { mtime: 0, name: None, comment: None, extra: None, header_crc: false, os: OS_UNKNOWN }
+    }
+}
+impl GzipHeader {
+    // XLEN is 16 bits, so a longer extra field cannot be written
+    fn to_bytes(&self, level: CompressionLevel) -> io::Result<Vec<u8>> {
+        let mut flags = 0;
+        if self.header_crc {
+            flags |= FHCRC;
+        }
+        if self.extra.is_some() {
+            flags |= FEXTRA;
+        }
+        if self.name.is_some() {
+            flags |= FNAME;
+        }
+        if self.comment.is_some() {
+            flags |= FCOMMENT;
+        }
+        let mut bytes = vec![SIGNATURE[0], SIGNATURE[1], CM_DEFLATE, flags];
+        bytes.extend(&self.mtime.to_le_bytes());
+        // XFL describes the compression level, and 0 claims nothing about it
+        bytes.push(match level {
+            CompressionLevel::BEST => XFL_BEST,
+            CompressionLevel::FASTEST => XFL_FASTEST,
+            _ => 0,
+        });
+        bytes.push(self.os);
+        if let Some(extra) = &self.extra {
+            if extra.len() > u16::MAX as usize {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "gzip extra field is longer than 65535 bytes"));
+            }
+            bytes.extend(&(extra.len() as u16).to_le_bytes());
+            bytes.extend(extra);
+        }
+        // text fields end at their first zero, so anything after one would be lost
+        for text in self.name.iter().chain(self.comment.iter()) {
+            bytes.extend(text.iter().take_while(|byte| **byte != 0));
+            bytes.push(0);
+        }
+        if self.header_crc {
+            let checksum = crc::Crc::new().calculate(&bytes) as u16;
+            bytes.extend(&checksum.to_le_bytes());
+        }
+        Ok(bytes)
+    }
+}
+
+/// One member of a gzip stream, and the data it decodes to.
+#[derive(Clone,PartialEq,Debug)]
+pub struct GzipMember {
+    pub header: GzipHeader,
+    pub data: Vec<u8>,
+}
+
+/// A gzip stream holding the data in a single member.
+pub fn encode(data: &[u8], header: &GzipHeader) -> io::Result<Vec<u8>> {
+    let mut bytes = header.to_bytes(CompressionLevel::DEFAULT)?;
+    bytes.extend(deflate(data));
+    bytes.extend(&crc::Crc::new().calculate(data).to_le_bytes());
+    // ISIZE is the length modulo 2^32
+    bytes.extend(&(data.len() as u32).to_le_bytes());
+    Ok(bytes)
+}
+
+/// Compresses everything written to it as a single gzip member, passing the output on to `writer`.
+pub struct GzipEncoder<W: Write> {
+    writer: W,
+    compressor: Compressor,
+    crc: crc::Crc,
+    checksum: u32,
+    length: u32,
+    finished: bool,
+}
+impl<W: Write> GzipEncoder<W> {
+    pub fn new(writer: W, header: &GzipHeader) -> io::Result<Self> {
+        GzipEncoder::with_level(writer, header, CompressionLevel::DEFAULT)
+    }
+    pub fn with_level(writer: W, header: &GzipHeader, level: CompressionLevel) -> io::Result<Self> {
+        let compressor = Compressor::new(&header.to_bytes(level)?, level);
+        Ok(GzipEncoder { writer, compressor, crc: crc::Crc::new(), checksum: 0, length: 0, finished: false })
+    }
+    pub fn flush_as(&mut self, flush: Flush) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        let mut bytes = self.compressor.flush(flush);
+        if flush == Flush::Finish {
+            bytes.extend(&self.checksum.to_le_bytes());
+            bytes.extend(&self.length.to_le_bytes());
+            self.finished = true;
+        }
+        self.writer.write_all(&bytes)
+    }
+    /// Writes the rest of the member and gives back the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.flush_as(Flush::Finish)?;
+        Ok(self.writer)
+    }
+}
+impl<W: Write> Write for GzipEncoder<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.finished {
+            return Err(finished_error());
+        }
+        self.checksum = self.crc.update(self.checksum, data);
+        self.length = self.length.wrapping_add(data.len() as u32);
+        self.compressor.write(data);
+        let bytes = self.compressor.flush(Flush::None);
+        self.writer.write_all(&bytes)?;
+        Ok(data.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_as(Flush::Sync)?;
+        self.writer.flush()
+    }
+}
+
+fn read_text(bytes: &[u8], offset: &mut usize) -> Result<Vec<u8>, DecodeError> {
+    let rest = bytes.get(*offset..).ok_or(DecodeError::UnexpectedEnd)?;
+    let length = rest.iter().position(|byte| *byte == 0).ok_or(DecodeError::UnexpectedEnd)?;
+    *offset += length + 1;
+    Ok(rest[..length].to_vec())
+}
+
+fn read_le_u32(bytes: &[u8], offset: usize) -> Result<u32, DecodeError> {
+    match bytes.get(offset..offset + 4) {
+        Some(value) => Ok(u32::from_le_bytes([value[0], value[1], value[2], value[3]])),
+        None => Err(DecodeError::UnexpectedEnd),
+    }
+}
+
+// the header of the member at the start of `bytes`, and the length of that header
+fn read_header(bytes: &[u8]) -> Result<(GzipHeader, usize), DecodeError> {
+    let fixed = bytes.get(..HEADER_SIZE).ok_or(DecodeError::UnexpectedEnd)?;
+    if fixed[..2] != SIGNATURE {
+        return Err(DecodeError::MissingGzipSignature);
+    }
+    if fixed[2] != CM_DEFLATE {
+        return Err(DecodeError::UnsupportedMethod);
+    }
+    let flags = fixed[3];
+    if flags & RESERVED_FLAGS != 0 {
+        return Err(DecodeError::ReservedFlags);
+    }
+    let mut header = GzipHeader { mtime: read_le_u32(fixed, 4)?, os: fixed[9], ..GzipHeader::default() };
+    let mut offset = HEADER_SIZE;
+    if flags & FEXTRA != 0 {
+        let length = match bytes.get(offset..offset + 2) {
+            Some(length) => u16::from_le_bytes([length[0], length[1]]) as usize,
+            None => return Err(DecodeError::UnexpectedEnd),
+        };
+        header.extra = Some(bytes.get(offset + 2..offset + 2 + length).ok_or(DecodeError::UnexpectedEnd)?.to_vec());
+        offset += 2 + length;
+    }
+    if flags & FNAME != 0 {
+        header.name = Some(read_text(bytes, &mut offset)?);
+    }
+    if flags & FCOMMENT != 0 {
+        header.comment = Some(read_text(bytes, &mut offset)?);
+    }
+    if flags & FHCRC != 0 {
+        let expected = match bytes.get(offset..offset + 2) {
+            Some(checksum) => u16::from_le_bytes([checksum[0], checksum[1]]),
+            None => return Err(DecodeError::UnexpectedEnd),
+        };
+        if crc::Crc::new().calculate(&bytes[..offset]) as u16 != expected {
+            return Err(DecodeError::HeaderChecksumMismatch);
+        }
+        header.header_crc = true;
+        offset += 2;
+    }
+    Ok((header, offset))
+}
+
+/// Decodes every member of a gzip stream, checking the CRC-32 and length of each.
+pub fn decode_members(bytes: &[u8]) -> Result<Vec<GzipMember>, DecodeError> {
+    decode_members_with_limits(bytes, &Limits::default())
+}
+
+/// Decodes every member of a gzip stream, stopping with `LimitExceeded` once all of them together
+/// would decode to more than the limits allow.
+pub fn decode_members_with_limits(bytes: &[u8], limits: &Limits) -> Result<Vec<GzipMember>, DecodeError> {
+    let mut remaining = limits.output_limit(bytes.len());
+    let mut members = Vec::new();
+    let mut offset = 0;
+    // members are simply concatenated, as `cat a.gz b.gz` would produce
+    while offset < bytes.len() || members.is_empty() {
+        let (header, header_length) = read_header(&bytes[offset..])?;
+        offset += header_length;
+        let mut data = Vec::new();
+        offset += inflate::inflate_all(&bytes[offset..], &mut data, remaining)?;
+        remaining -= data.len();
+        let expected = read_le_u32(bytes, offset)?;
+        let actual = crc::Crc::new().calculate(&data);
+        if actual != expected {
+            return Err(DecodeError::ChecksumMismatch { expected, actual });
+        }
+        let expected = read_le_u32(bytes, offset + 4)?;
+        if data.len() as u32 != expected {
+            return Err(DecodeError::LengthMismatch { expected, actual: data.len() as u32 });
+        }
+        offset += TRAILER_SIZE;
+        members.push(GzipMember { header, data });
+    }
+    Ok(members)
+}
+
+/// Decodes a gzip stream into the data of all of its members, one after another.
+pub fn decode(bytes: &[u8]) -> Result<Vec<u8>, DecodeError> {
+    Ok(decode_members(bytes)?.into_iter().flat_map(|member| member.data).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn decode_reads_output_from_system_gzip() {
+        // a member named frame.ppm with a modification time
+        let bytes = [
+            0x1f, 0x8b, 0x08, 0x08, 0x00, 0xf1, 0x53, 0x65, 0x02, 0xff, 0x66, 0x72, 0x61, 0x6d, 0x65, 0x2e,
+            0x70, 0x70, 0x6d, 0x00, 0x0b, 0x30, 0x53, 0x30, 0x04, 0x42, 0x23, 0x53, 0x53, 0x85, 0xc4, 0xa4,
+            0x64, 0x00, 0xe6, 0x58, 0x45, 0x47, 0x0e, 0x00, 0x00, 0x00,
+        ];
+        let members = decode_members(&bytes).unwrap();
+        assert_eq!(members.len(), 1);
+        assert_eq!(members[0].data, b"P6 1 1 255 abc");
+        assert_eq!(members[0].header.name, Some(b"frame.ppm".to_vec()));
+        assert_eq!(members[0].header.mtime, 1700000000);
+        assert_eq!(members[0].header.os, OS_UNKNOWN);
+    }
+    #[test]
+    fn every_header_field_round_trips() {
+        let header = GzipHeader {
+            mtime: 1234567890,
+            name: Some(b"carpet.pgm".to_vec()),
+            comment: Some(b"level 5".to_vec()),
+            extra: Some(vec![b'A', b'P', 2, 0, 0xab, 0xcd]),
+            header_crc: true,
+            os: 3,
+        };
+        let data = b"P5 3 1 255 abc".repeat(100);
+        let members = decode_members(&encode(&data, &header).unwrap()).unwrap();
+        assert_eq!(members, vec![GzipMember { header, data }]);
+    }
+    #[test]
+    fn extra_fields_must_fit_in_xlen() {
+        let header = GzipHeader { extra: Some(vec![0; 65536]), ..GzipHeader::default() };
+        assert_eq!(encode(b"abc", &header).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert!(GzipEncoder::new(Vec::new(), &header).is_err());
+        let header = GzipHeader { extra: Some(vec![0; 65535]), ..GzipHeader::default() };
+        assert_eq!(decode_members(&encode(b"abc", &header).unwrap()).unwrap()[0].header, header);
+    }
+    #[test]
+    fn decode_joins_members() {
+        let mut bytes = encode(b"first frame, ", &GzipHeader::default()).unwrap();
+        bytes.extend(encode(b"", &GzipHeader::default()).unwrap());
+        bytes.extend(encode(b"second frame", &GzipHeader { name: Some(b"two".to_vec()), ..GzipHeader::default() }).unwrap());
+        assert_eq!(decode_members(&bytes).unwrap().len(), 3);
+        assert_eq!(decode(&bytes).unwrap(), b"first frame, second frame");
+    }
+    #[test]
+    fn encoder_streams_a_member() {
+        let header = GzipHeader { name: Some(b"frames.ppm".to_vec()), ..GzipHeader::default() };
+        let mut encoder = GzipEncoder::with_level(Vec::new(), &header, CompressionLevel::BEST).unwrap();
+        encoder.write_all(b"P6 1 1 255 ").unwrap();
+        encoder.flush().unwrap();
+        for _ in 0..30000 {
+            encoder.write_all(b"abc").unwrap();
+        }
+        let bytes = encoder.finish().unwrap();
+        assert_eq!(bytes[8], XFL_BEST);
+        let mut data = b"P6 1 1 255 ".to_vec();
+        data.extend(b"abc".repeat(30000));
+        assert_eq!(decode_members(&bytes).unwrap(), vec![GzipMember { header, data }]);
+    }
+    #[test]
+    fn decode_limits_the_members_together() {
+        let mut bytes = encode(&[1; 3000], &GzipHeader::default()).unwrap();
+        bytes.extend(encode(&[2; 3000], &GzipHeader::default()).unwrap());
+        let limits = |max_output| Limits { max_output, ..Limits::default() };
+        assert_eq!(decode_members_with_limits(&bytes, &limits(6000)).unwrap().len(), 2);
+        assert_eq!(decode_members_with_limits(&bytes, &limits(5999)), Err(DecodeError::LimitExceeded));
+    }
+    #[test]
+    fn decode_checks_the_header_and_trailer() {
+        let header = GzipHeader { header_crc: true, ..GzipHeader::default() };
+        let bytes = encode(b"abc", &header).unwrap();
+        assert_eq!(decode(&[]), Err(DecodeError::UnexpectedEnd));
+        assert_eq!(decode(&bytes[1..]), Err(DecodeError::MissingGzipSignature));
+        let corrupt = |index: usize, value: u8| {
+            let mut bytes = bytes.clone();
+            bytes[index] ^= value;
+            decode(&bytes)
+        };
+        assert_eq!(corrupt(2, 1), Err(DecodeError::UnsupportedMethod));
+        assert_eq!(corrupt(3, 0x80), Err(DecodeError::ReservedFlags));
+        assert_eq!(corrupt(4, 1), Err(DecodeError::HeaderChecksumMismatch));
+        let trailer = bytes.len() - TRAILER_SIZE;
+        assert_eq!(corrupt(trailer, 1), Err(DecodeError::ChecksumMismatch { expected: 0x352441c2 ^ 1, actual: 0x352441c2 }));
+        assert_eq!(corrupt(trailer + 4, 1), Err(DecodeError::LengthMismatch { expected: 2, actual: 3 }));
+        // a second member that stops short
+        let mut bytes = bytes.clone();
+        bytes.extend(&SIGNATURE);
+        assert_eq!(decode(&bytes), Err(DecodeError::UnexpectedEnd));
+    }
+}
@@ -0,0 +1,157 @@
+use crate::bits::BitReader;
+use crate::DecodeError;
+
+/// Code lengths for the given symbol frequencies, none longer than `max_length`, found by package-merge.
+/// Unused symbols get length 0, and a lone used symbol gets length 1.
+pub(crate) fn code_lengths(frequencies: &[u32], max_length: u8) -> Vec<u8> {
+    let mut lengths = vec![0; frequencies.len()];
+    let mut leaves: Vec<(u64, Vec<u16>)> = frequencies.iter().enumerate()
+        .filter(|(_, frequency)| **frequency > 0)
+        .map(|(symbol, frequency)| (*frequency as u64, vec![symbol as u16]))
+        .collect();
+    match leaves.len() {
+        0 => return lengths,
+        1 => {
+            lengths[leaves[0].1[0] as usize] = 1;
+            return lengths;
+        },
+        _ => {},
+    }
+    debug_assert!(leaves.len() <= 1 << max_length);
+    leaves.sort_by_key(|(weight, _)| *weight);
+    // each round pairs up the cheapest items into packages one level deeper, then merges the leaves back in
+    let mut items = leaves.clone();
+    for _ in 1..max_length {
+        let packages: Vec<(u64, Vec<u16>)> = items.chunks_exact(2)
+            .map(|pair| (pair[0].0 + pair[1].0, [&pair[0].1[..], &pair[1].1[..]].concat()))
+            .collect();
+        items = leaves.iter().cloned().chain(packages).collect();
+        items.sort_by_key(|(weight, _)| *weight);
+    }
+    // every time a symbol appears in the cheapest 2n - 2 items its code gets a bit longer
+    for (_, symbols) in &items[..2 * leaves.len() - 2] {
+        for symbol in symbols {
+            lengths[*symbol as usize] += 1;
+        }
+    }
+    lengths
+}
+
+/// Canonical codes for the given lengths: shorter codes first, and codes of the same length in symbol order.
+pub(crate) fn canonical_codes(lengths: &[u8]) -> Vec<u16> {
+    let max_length = lengths.iter().copied().max().unwrap_or(0) as usize;
+    let mut length_counts = vec![0u16; max_length + 1];
+    for length in lengths.iter().filter(|length| **length > 0) {
+        length_counts[*length as usize] += 1;
+    }
+    let mut next_code = vec![0u16; max_length + 1];
+    let mut code = 0;
+    for length in 1..=max_length {
+        code = (code + length_counts[length - 1]) << 1;
+        next_code[length] = code;
+    }
+    lengths.iter().map(|length| {
+        if *length == 0 {
+            return 0;
+        }
+        let code = next_code[*length as usize];
+        next_code[*length as usize] += 1;
+        code
+    }).collect()
+}
+
+// no code reaches this entry of the lookup table
+const UNUSED: (u16, u8) = (0, 0);
+
+/// Decodes canonical codes by looking up as many bits as the longest code at once.
+pub(crate) struct Decoder {
+    table: Vec<(u16, u8)>,
+    bits: u32,
+}
+impl Decoder {
+    /// None when the lengths give more codes than fit, or leave codes unused;
+    /// a single code of length 1, or no codes at all, are allowed as deflate encoders write them.
+    pub(crate) fn new(lengths: &[u8]) -> Option<Self> {
+        let bits = lengths.iter().copied().max().unwrap_or(0).max(1) as u32;
+        let used = lengths.iter().filter(|length| **length > 0).count();
+        let kraft: u64 = lengths.iter().filter(|length| **length > 0).map(|length| 1u64 << (bits - *length as u32)).sum();
+        let complete = kraft == 1 << bits;
+        if kraft > 1 << bits || !(complete || used == 0 || (used == 1 && bits == 1)) {
+            return None;
+        }
+        let mut table = vec![UNUSED; 1 << bits];
+        for (symbol, (code, length)) in canonical_codes(lengths).into_iter().zip(lengths).enumerate() {
+            if *length == 0 {
+                continue;
+            }
+            // codes arrive starting from their most significant bit, so the table is indexed by the reversed code
+            let reversed = (code.reverse_bits() >> (16 - *length as u32)) as usize;
+            for entry in table.iter_mut().skip(reversed).step_by(1 << length) {
+                *entry = (symbol as u16, *length);
+            }
+        }
+        Some(Decoder { table, bits })
+    }
+    pub(crate) fn decode(&self, reader: &mut BitReader) -> Result<u16, DecodeError> {
+        let (symbol, length) = self.table[reader.peek(self.bits) as usize];
+        if length == 0 {
+            // the missing bits past the end of the data were taken as zeros
+            if reader.available_bits() < self.bits as usize {
+                return Err(DecodeError::UnexpectedEnd);
+            }
+            return Err(DecodeError::InvalidCode);
+        }
+        reader.consume(length as u32).ok_or(DecodeError::UnexpectedEnd)?;
+        Ok(symbol)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn canonical_codes_follow_the_rfc_example() {
+        let codes = canonical_codes(&[3, 3, 3, 3, 3, 2, 4, 4]);
+        assert_eq!(codes, vec![0b010, 0b011, 0b100, 0b101, 0b110, 0b00, 0b1110, 0b1111]);
+    }
+    #[test]
+    fn code_lengths_are_optimal_without_a_limit() {
+        assert_eq!(code_lengths(&[0, 5, 0, 0], 15), vec![0, 1, 0, 0]);
+        assert_eq!(code_lengths(&[10, 1, 1, 2, 0], 15), vec![1, 3, 3, 2, 0]);
+    }
+    #[test]
+    fn code_lengths_respect_the_limit() {
+        // fibonacci frequencies would give a 19 bit code without the limit
+        let mut frequencies = vec![1u32, 1];
+        while frequencies.len() < 20 {
+            frequencies.push(frequencies[frequencies.len() - 1] + frequencies[frequencies.len() - 2]);
+        }
+        assert_eq!(code_lengths(&frequencies, 32).iter().max(), Some(&19));
+        for max_length in [7, 15] {
+            let lengths = code_lengths(&frequencies, max_length);
+            assert_eq!(lengths.iter().max(), Some(&max_length));
+            // the code is still complete
+            let kraft: f64 = lengths.iter().map(|length| 0.5f64.powi(*length as i32)).sum();
+            assert_eq!(kraft, 1.0);
+        }
+    }
+    #[test]
+    fn decoder_reads_canonical_codes_and_rejects_bad_lengths() {
+        let lengths = [3, 3, 3, 3, 3, 2, 4, 4];
+        let mut writer = crate::bits::BitWriter::new();
+        let codes = canonical_codes(&lengths);
+        for symbol in [5, 7, 0, 6] {
+            writer.write_code(codes[symbol], lengths[symbol]);
+        }
+        let bytes = writer.finish();
+        let decoder = Decoder::new(&lengths).unwrap();
+        let mut reader = BitReader::new(&bytes);
+        let symbols: Vec<u16> = (0..4).map(|_| decoder.decode(&mut reader).unwrap()).collect();
+        assert_eq!(symbols, vec![5, 7, 0, 6]);
+        // over-subscribed, then incomplete
+        assert!(Decoder::new(&[1, 1, 1]).is_none());
+        assert!(Decoder::new(&[1, 2]).is_none());
+        assert!(Decoder::new(&[0, 1]).is_some());
+        assert!(Decoder::new(&[0, 2]).is_none());
+    }
+}
@@ -0,0 +1,298 @@
+use crate::bits::BitReader;
+use crate::deflate::*;
+use crate::huffman::Decoder;
+use crate::{DecodeError, Limits};
+
+const FIXED_LITERAL_SYMBOLS: usize = 288;
+const FIXED_DISTANCE_SYMBOLS: usize = 32;
+
+fn read_bits(reader: &mut BitReader, count: u32) -> Result<u32, DecodeError> {
+    reader.read_bits(count).ok_or(DecodeError::UnexpectedEnd)
+}
+
+fn fixed_decoders() -> (Decoder, Decoder) {
+    let literal_lengths: Vec<u8> = (0..FIXED_LITERAL_SYMBOLS as u16).map(|symbol| fixed_literal_code(symbol).1).collect();
+    // the fixed codes include two literal/length and two distance symbols that never appear in valid data
+    (Decoder::new(&literal_lengths).unwrap(), Decoder::new(&[FIXED_DISTANCE_CODE_LENGTH; FIXED_DISTANCE_SYMBOLS]).unwrap())
+}
+
+fn read_dynamic_decoders(reader: &mut BitReader) -> Result<(Decoder, Decoder), DecodeError> {
+    let literal_count = read_bits(reader, 5)? as usize + FIRST_LENGTH_SYMBOL as usize;
+    let distance_count = read_bits(reader, 5)? as usize + 1;
+    let code_length_count = read_bits(reader, 4)? as usize + 4;
+    if literal_count > LITERAL_SYMBOLS || distance_count > DISTANCE_SYMBOLS {
+        return Err(DecodeError::InvalidCodeLengths);
+    }
+    let mut code_length_lengths = [0u8; CODE_LENGTH_SYMBOLS];
+    for symbol in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_length_lengths[*symbol] = read_bits(reader, 3)? as u8;
+    }
+    let code_length_decoder = Decoder::new(&code_length_lengths).ok_or(DecodeError::InvalidCodeLengths)?;
+
+    // literal/length and distance code lengths are sent as one sequence, and runs can cross between them
+    let mut lengths = Vec::with_capacity(literal_count + distance_count);
+    while lengths.len() < literal_count + distance_count {
+        let symbol = code_length_decoder.decode(reader)? as u8;
+        let extra = read_bits(reader, code_length_extra_bits(symbol) as u32)? as usize;
+        let (value, count) = match symbol {
+            REPEAT_PREVIOUS => match lengths.last() {
+                Some(previous) => (*previous, 3 + extra),
+                None => return Err(DecodeError::InvalidCodeLengths),
+            },
+            REPEAT_ZERO => (0, 3 + extra),
+            REPEAT_ZERO_LONG => (0, 11 + extra),
+            _ => (symbol, 1),
+        };
+        if lengths.len() + count > literal_count + distance_count {
+            return Err(DecodeError::InvalidCodeLengths);
+        }
+        lengths.extend(std::iter::repeat_n(value, count));
+    }
+    // without an end of block code the block could never finish
+    if lengths[END_OF_BLOCK as usize] == 0 {
+        return Err(DecodeError::InvalidCodeLengths);
+    }
+    let literals = Decoder::new(&lengths[..literal_count]).ok_or(DecodeError::InvalidCodeLengths)?;
+    let distances = Decoder::new(&lengths[literal_count..]).ok_or(DecodeError::InvalidCodeLengths)?;
+    Ok((literals, distances))
+}
+
+enum State {
+    BlockHeader,
+    Stored(usize),
+    Codes(Decoder, Decoder),
+    Done,
+}
+
+/// Deflate decoding that can stop at the end of its input and carry on when given more.
+pub(crate) struct Inflater {
+    state: State,
+    last_block: bool,
+    // bits of the last byte used that have not been read yet
+    bits: u64,
+    bit_count: u32,
+}
+impl Inflater {
+    pub(crate) fn new() -> Self {
+        Inflater { state: State::BlockHeader, last_block: false, bits: 0, bit_count: 0 }
+    }
+    pub(crate) fn is_done(&self) -> bool {
+        matches!(self.state, State::Done)
+    }
+    fn end_block(&mut self) {
+        self.state = if self.last_block { State::Done } else { State::BlockHeader };
+    }
+    // a block header, a run of stored bytes, or one literal, copy or end of block
+    fn step(&mut self, reader: &mut BitReader, output: &mut Vec<u8>, output_limit: usize) -> Result<(), DecodeError> {
+        match &mut self.state {
+            State::BlockHeader => {
+                self.last_block = read_bits(reader, 1)? == 1;
+                self.state = match read_bits(reader, 2)? as u8 {
+                    BTYPE_STORED => {
+                        reader.align();
+                        let length = read_bits(reader, 16)?;
+                        let complement = read_bits(reader, 16)?;
+                        if length != !complement & 0xffff {
+                            return Err(DecodeError::InvalidStoredLength);
+                        }
+                        State::Stored(length as usize)
+                    },
+                    BTYPE_FIXED => {
+                        let (literals, distances) = fixed_decoders();
+                        State::Codes(literals, distances)
+                    },
+                    BTYPE_DYNAMIC => {
+                        let (literals, distances) = read_dynamic_decoders(reader)?;
+                        State::Codes(literals, distances)
+                    },
+                    _ => return Err(DecodeError::InvalidBlockType),
+                };
+            },
+            State::Stored(remaining) => {
+                if *remaining == 0 {
+                    self.end_block();
+                    return Ok(());
+                }
+                let length = (*remaining).min(reader.available_bits() / 8).min(output_limit - output.len());
+                if length == 0 {
+                    return Err(DecodeError::UnexpectedEnd);
+                }
+                output.extend(reader.read_aligned_bytes(length).unwrap());
+                *remaining -= length;
+            },
+            State::Codes(literals, distances) => {
+                let symbol = literals.decode(reader)?;
+                if symbol < END_OF_BLOCK {
+                    output.push(symbol as u8);
+                    return Ok(());
+                }
+                if symbol == END_OF_BLOCK {
+                    self.end_block();
+                    return Ok(());
+                }
+                let index = (symbol - FIRST_LENGTH_SYMBOL) as usize;
+                if index >= LENGTH_BASES.len() {
+                    return Err(DecodeError::InvalidCode);
+                }
+                let length = LENGTH_BASES[index] as usize + read_bits(reader, LENGTH_EXTRA_BITS[index] as u32)? as usize;
+                let index = distances.decode(reader)? as usize;
+                if index >= DISTANCE_BASES.len() {
+                    return Err(DecodeError::InvalidCode);
+                }
+                let distance = DISTANCE_BASES[index] as usize + read_bits(reader, DISTANCE_EXTRA_BITS[index] as u32)? as usize;
+                if distance > output.len() {
+                    return Err(DecodeError::DistanceTooFar(distance as u32));
+                }
+                let start = output.len() - distance;
+                if distance >= length {
+                    output.extend_from_within(start..start + length);
+                } else {
+                    // the copy overlaps the bytes it produces
+                    for i in 0..length {
+                        output.push(output[start + i]);
+                    }
+                }
+            },
+            State::Done => {},
+        }
+        Ok(())
+    }
+    /// Decodes from `input`, appending to `output`, whose earlier bytes copies may refer back into.
+    /// Stops after the final block, when the input runs out, or once `output` reaches `output_limit` bytes,
+    /// and returns how many bytes of input were used.
+    pub(crate) fn inflate(&mut self, input: &[u8], output: &mut Vec<u8>, output_limit: usize) -> Result<usize, DecodeError> {
+        let mut reader = BitReader::resume(input, self.bits, self.bit_count);
+        while !self.is_done() && output.len() < output_limit {
+            // nothing is written until a step has all of its bits, so running out means going back to its start
+            let checkpoint = reader.clone();
+            match self.step(&mut reader, output, output_limit) {
+                Ok(()) => {},
+                Err(DecodeError::UnexpectedEnd) => {
+                    reader = checkpoint;
+                    break;
+                },
+                Err(error) => return Err(error),
+            }
+        }
+        let (used, bits, bit_count) = reader.suspend();
+        self.bits = bits;
+        self.bit_count = bit_count;
+        Ok(used)
+    }
+}
+
+/// Decodes blocks up to and including the final one, appending at most `limit` bytes to `output`,
+/// and returns the bytes used.
+pub(crate) fn inflate_all(data: &[u8], output: &mut Vec<u8>, limit: usize) -> Result<usize, DecodeError> {
+    let mut inflater = Inflater::new();
+    let end = output.len().saturating_add(limit);
+    // decoding one byte past the limit tells a stream that ends right at it from one that carries on
+    let used = inflater.inflate(data, output, end.saturating_add(1))?;
+    if output.len() > end {
+        return Err(DecodeError::LimitExceeded);
+    }
+    if !inflater.is_done() {
+        return Err(DecodeError::UnexpectedEnd);
+    }
+    Ok(used)
+}
+
+/// Decodes raw deflate data, without a zlib header or checksum.
+pub fn inflate(data: &[u8]) -> Result<Vec<u8>, DecodeError> {
+    inflate_with_limits(data, &Limits::default())
+}
+
+/// Decodes raw deflate data, stopping with `LimitExceeded` once it would decode to more than the limits allow.
+pub fn inflate_with_limits(data: &[u8], limits: &Limits) -> Result<Vec<u8>, DecodeError> {
+    let mut output = Vec::new();
+    inflate_all(data, &mut output, limits.output_limit(data.len()))?;
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn noise(count: usize) -> Vec<u8> {
+        let mut state = 1u32;
+        (0..count).map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 16) as u8
+        }).collect()
+    }
+    #[test]
+    fn inflate_reverses_each_block_type() {
+        let mut data = b"sierpinski carpet, sierpinski triangle, koch snowflake, ".repeat(50);
+        data.extend(noise(70000));
+        data.extend(vec![0; 1000]);
+        assert_eq!(inflate(&stored_blocks(&data)).unwrap(), data);
+        assert_eq!(inflate(&fixed_blocks(&data)).unwrap(), data);
+        assert_eq!(inflate(&deflate(&data)).unwrap(), data);
+        assert_eq!(inflate(&deflate(&[])).unwrap(), Vec::<u8>::new());
+    }
+    #[test]
+    fn inflate_reads_output_from_other_encoders() {
+        // a dynamic block from system zlib, and the fixed codes for "abcabcabcabc"
+        let dynamic = [
+            0x3d, 0x8b, 0x5b, 0x0e, 0xc2, 0x30, 0x0c, 0x04, 0xaf, 0xb2, 0x07, 0xa8, 0x7a, 0x27, 0x93, 0x98,
+            0xc6, 0x52, 0x12, 0x83, 0xed, 0xb6, 0xe2, 0xf6, 0xb8, 0x95, 0xe0, 0x6f, 0x1f, 0x33, 0xd1, 0x18,
+            0x4f, 0xa3, 0x12, 0xd4, 0x21, 0x8e, 0x6a, 0x74, 0x4e, 0x3c, 0x3e, 0x30, 0x1e, 0x7a, 0xc8, 0xdc,
+            0x10, 0x09, 0x0c, 0xa9, 0xb5, 0x33, 0xc2, 0x84, 0xe6, 0xd6, 0x2f, 0x41, 0x07, 0x98, 0x4a, 0xfb,
+            0x4f, 0x0b, 0xf4, 0x60, 0x03, 0xcd, 0x7a, 0x87, 0x05, 0xfb, 0x0c, 0xe9, 0xb7, 0xfc, 0x43, 0x1c,
+            0x64, 0xd9, 0x54, 0xe1, 0x83, 0x7a, 0x7e, 0x19, 0x98, 0xd7, 0x9b, 0x29, 0x64, 0x2f, 0x0e, 0x54,
+            0x4d, 0xea, 0xea, 0x4e, 0x83, 0x71, 0x4a, 0x34, 0xf8, 0x7b, 0x4f, 0xcd, 0xd7, 0x2f,
+        ];
+        let text = b"the fractal is drawn by removing the middle triangle from each triangle, over and over, \
+            until the triangles are too small to see. the carpet does the same with squares.";
+        assert_eq!(inflate(&dynamic).unwrap(), &text[..]);
+        assert_eq!(inflate(&[0x4b, 0x4c, 0x4a, 0x4e, 0x84, 0x21, 0x00]).unwrap(), b"abcabcabcabc");
+    }
+    #[test]
+    fn inflate_reports_corrupt_data() {
+        assert_eq!(inflate(&[0x07]), Err(DecodeError::InvalidBlockType));
+        assert_eq!(inflate(&[0x01, 0x03, 0x00, 0xfc, 0xfe]), Err(DecodeError::InvalidStoredLength));
+        assert_eq!(inflate(&[0x01, 0x03, 0x00, 0xfc, 0xff, b'a']), Err(DecodeError::UnexpectedEnd));
+        assert_eq!(inflate(&[0x00, 0x00, 0x00, 0xff, 0xff]), Err(DecodeError::UnexpectedEnd));
+        assert_eq!(inflate(&[0x4b, 0x04]), Err(DecodeError::UnexpectedEnd));
+        // a copy of one byte back before anything has been written
+        assert_eq!(inflate(&[0x03, 0x02, 0x00]), Err(DecodeError::DistanceTooFar(1)));
+        // literal/length symbol 286 in a fixed block
+        assert_eq!(inflate(&[0x1b, 0x03]), Err(DecodeError::InvalidCode));
+    }
+    #[test]
+    fn inflate_stops_at_the_limits() {
+        let data = vec![0; 100000];
+        let compressed = deflate(&data);
+        let limits = |max_output, max_ratio| Limits { max_output, max_ratio };
+        assert_eq!(inflate_with_limits(&compressed, &limits(100000, usize::MAX)).unwrap(), data);
+        assert_eq!(inflate_with_limits(&compressed, &limits(99999, usize::MAX)), Err(DecodeError::LimitExceeded));
+        assert_eq!(inflate_with_limits(&compressed, &limits(usize::MAX, 100)), Err(DecodeError::LimitExceeded));
+        let ratio = data.len().div_ceil(compressed.len());
+        assert_eq!(inflate_with_limits(&compressed, &limits(usize::MAX, ratio)).unwrap(), data);
+        // the limit is checked before the stream is known to be cut short
+        assert_eq!(inflate_with_limits(&compressed[..compressed.len() / 2], &limits(10, usize::MAX)), Err(DecodeError::LimitExceeded));
+    }
+    #[test]
+    fn inflater_resumes_across_input_pieces() {
+        let data = b"sierpinski carpet, sierpinski triangle, koch snowflake, ".repeat(20);
+        let mut stream = stored_blocks(b"a stored block first, ");
+        stream[0] = 0;
+        stream.extend(deflate(&data));
+        let mut inflater = Inflater::new();
+        let mut output = Vec::new();
+        let mut input: Vec<u8> = Vec::new();
+        // one byte at a time, with at most 7 bytes of output from each call
+        for byte in &stream {
+            input.push(*byte);
+            let limit = output.len() + 7;
+            let used = inflater.inflate(&input, &mut output, limit).unwrap();
+            input.drain(..used);
+        }
+        while !inflater.is_done() {
+            let limit = output.len() + 7;
+            let used = inflater.inflate(&input, &mut output, limit).unwrap();
+            input.drain(..used);
+        }
+        assert_eq!(output, [&b"a stored block first, "[..], &data[..]].concat());
+        assert!(input.is_empty());
+    }
+}
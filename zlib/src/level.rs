@@ -0,0 +1,85 @@
+use lz77::Effort;
+
+/// A compression level from 0, which stores the data as it is, to 9, which searches hardest for repeats.
+#[derive(Clone,Copy,PartialEq,Eq,PartialOrd,Ord,Debug)]
+pub struct CompressionLevel(u8);
+impl CompressionLevel {
+    pub const NONE: CompressionLevel = CompressionLevel(0);
+    pub const FASTEST: CompressionLevel = CompressionLevel(1);
+    pub const DEFAULT: CompressionLevel = CompressionLevel(6);
+    pub const BEST: CompressionLevel = CompressionLevel(9);
+
+    /// None for levels above 9.
+    pub fn new(level: u8) -> Option<Self> {
+        if level <= CompressionLevel::BEST.0 {
+            Some(CompressionLevel(level))
+        } else {
+            None
+        }
+    }
+    pub fn value(&self) -> u8 {
+        self.0
+    }
+    /// The FLEVEL header bits, which only tell a decoder roughly how hard the encoder tried.
+    pub(crate) fn flevel(&self) -> u8 {
+        match self.0 {
+            0..=1 => 0,
+            2..=5 => 1,
+            6 => 2,
+            _ => 3,
+        }
+    }
+    /// How hard lz77 looks for matches, or None when the data is only stored.
+    pub(crate) fn effort(&self) -> Option<Effort> {
+        // the fast levels take the first good match they find rather than waiting a byte for a better one
+        let (max_chain, nice_length, lazy_length) = match self.0 {
+            0 => return None,
+            1 => (4, 8, 0),
+            2 => (8, 16, 0),
+            3 => (32, 32, 0),
+            4 => (16, 16, 4),
+            5 => (32, 32, 16),
+            6 => return Some(Effort::default()),
+            7 => (256, 128, 64),
+            8 => (1024, 258, 128),
+            _ => (4096, 258, 258),
+        };
+        Some(Effort { max_chain, nice_length, lazy_length })
+    }
+}
+impl Default for CompressionLevel {
+    fn default() -> Self {
+        CompressionLevel::DEFAULT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{deflate_with_level, inflate};
+    #[test]
+    fn levels_stop_at_nine() {
+        assert_eq!(CompressionLevel::new(9), Some(CompressionLevel::BEST));
+        assert_eq!(CompressionLevel::new(10), None);
+        let flevels: Vec<u8> = (0..=9).map(|level| CompressionLevel(level).flevel()).collect();
+        assert_eq!(flevels, vec![0, 0, 1, 1, 1, 1, 2, 3, 3, 3]);
+    }
+    #[test]
+    fn higher_levels_compress_smaller() {
+        let words = ["julia", "fatou", "mandelbrot", "newton", "basin", "orbit", "escape", "period"];
+        let mut state = 3u32;
+        let data: Vec<u8> = (0..20000).flat_map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            let word = words[(state >> 16) as usize % words.len()];
+            format!("{} {} ", word, state >> 28).into_bytes()
+        }).collect();
+        let sizes: Vec<usize> = [0, 1, 6, 9].iter().map(|level| {
+            let compressed = deflate_with_level(&data, CompressionLevel(*level));
+            assert_eq!(inflate(&compressed).unwrap(), data);
+            compressed.len()
+        }).collect();
+        assert!(sizes.windows(2).all(|pair| pair[1] < pair[0]), "{:?}", sizes);
+        // level 0 only frames the data in stored blocks
+        assert_eq!(sizes[0], data.len() + 5 * data.len().div_ceil(0xffff));
+    }
+}
@@ -1,38 +1,316 @@
-const NO_COMPRESSION_HEADER: [u8; 2] = [0x78, 0x01];
-const DEFAULT_COMPRESSION_HEADER: [u8; 2] = [0x78, 0x9c];
-const BEST_COMPRESSION_HEADER: [u8; 2] = [0x78, 0xda];
+mod adler32;
+mod bits;
+mod deflate;
+mod huffman;
+mod inflate;
+mod level;
+mod limits;
+mod parallel;
+pub mod gzip;
+mod stream;
 
-struct ZlibChunk {
-    header: [u8; 2],
+use adler32::adler;
+use std::fmt;
+use std::io;
+
+pub use adler32::{adler32_combine, adler32_update, Adler32};
+pub use deflate::{deflate, deflate_with_level, fixed_blocks, stored_blocks};
+pub use inflate::{inflate, inflate_with_limits};
+pub use level::CompressionLevel;
+pub use limits::Limits;
+pub use parallel::compressed_parallel;
+pub use stream::{Flush, ZlibDecoder, ZlibEncoder};
+
+// compression method 8 (deflate) with a 32k window
+const CMF_DEFLATE: u8 = 0x78;
+// the header as a big-endian number must be a multiple of this
+const FCHECK_DIVISOR: u16 = 31;
+const CM_DEFLATE: u8 = 8;
+// CINFO is the base 2 logarithm of the window size less 8, and deflate windows are at most 32k
+const MAX_CINFO: u8 = 7;
+const FDICT_FLAG: u8 = 0x20;
+const DICTIONARY_ID_SIZE: usize = 4;
+const WINDOW_SIZE: usize = lz77::WINDOW_SIZE as usize;
+
+// a preset dictionary is named in the header by its Adler-32
+fn header(flevel: u8, dictionary: Option<&[u8]>) -> Vec<u8> {
+    let mut flg = flevel << 6;
+    if dictionary.is_some() {
+        flg |= FDICT_FLAG;
+    }
+    let remainder = (CMF_DEFLATE as u16 * 256 + flg as u16) % FCHECK_DIVISOR;
+    let fcheck = (FCHECK_DIVISOR - remainder) % FCHECK_DIVISOR;
+    let mut bytes = vec![CMF_DEFLATE, flg | fcheck as u8];
+    if let Some(dictionary) = dictionary {
+        bytes.extend(&adler(dictionary).to_be_bytes());
+    }
+    bytes
+}
+
+#[derive(Debug,PartialEq)]
+pub enum DecodeError {
+    // the data ended before the final block or the checksum
+    UnexpectedEnd,
+    // a compression method other than deflate, or a window larger than 32k
+    UnsupportedMethod,
+    // the header as a big-endian number is not a multiple of 31
+    InvalidHeaderCheck,
+    // the stream was compressed against a preset dictionary with this Adler-32
+    DictionaryRequired(u32),
+    // BTYPE 3, which is reserved
+    InvalidBlockType,
+    // the length of a stored block does not match its complement
+    InvalidStoredLength,
+    // code lengths that do not make a usable prefix code
+    InvalidCodeLengths,
+    // bits that match no code, or a length or distance symbol that is never used
+    InvalidCode,
+    // a copy from further back than the start of the data
+    DistanceTooFar(u32),
+    // the Adler-32 of a zlib stream or the CRC-32 of a gzip member does not match the decoded data
+    ChecksumMismatch { expected: u32, actual: u32 },
+    // a gzip member that does not start with 1f 8b
+    MissingGzipSignature,
+    // gzip flag bits that are reserved for future use
+    ReservedFlags,
+    // the CRC-16 of a gzip header does not match it
+    HeaderChecksumMismatch,
+    // the length in a gzip trailer, modulo 2^32, does not match the decoded data
+    LengthMismatch { expected: u32, actual: u32 },
+    // the data decodes to more than the limits allow
+    LimitExceeded,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEnd => write!(f, "compressed data ended unexpectedly"),
+            DecodeError::UnsupportedMethod => write!(f, "unsupported compression method"),
+            DecodeError::InvalidHeaderCheck => write!(f, "incorrect zlib header check"),
+            DecodeError::DictionaryRequired(id) => write!(f, "a preset dictionary with adler-32 {:08x} is required", id),
+            DecodeError::InvalidBlockType => write!(f, "invalid deflate block type"),
+            DecodeError::InvalidStoredLength => write!(f, "stored block length does not match its complement"),
+            DecodeError::InvalidCodeLengths => write!(f, "invalid huffman code lengths"),
+            DecodeError::InvalidCode => write!(f, "invalid huffman code"),
+            DecodeError::DistanceTooFar(distance) => write!(f, "copy distance {} reaches before the start of the data", distance),
+            DecodeError::ChecksumMismatch { expected, actual } => write!(f, "checksum {:08x} does not match the data's {:08x}", expected, actual),
+            DecodeError::MissingGzipSignature => write!(f, "missing gzip signature"),
+            DecodeError::ReservedFlags => write!(f, "reserved gzip flags are set"),
+            DecodeError::HeaderChecksumMismatch => write!(f, "incorrect gzip header checksum"),
+            DecodeError::LengthMismatch { expected, actual } => write!(f, "length {} does not match the data's {}", expected, actual),
+            DecodeError::LimitExceeded => write!(f, "decoded data exceeds the output limit"),
+        }
+    }
+}
+impl std::error::Error for DecodeError {}
+impl From<DecodeError> for io::Error {
+    fn from(error: DecodeError) -> Self {
+        let kind = match error {
+            DecodeError::UnexpectedEnd => io::ErrorKind::UnexpectedEof,
+            _ => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, error)
+    }
+}
+
+/// A zlib stream: the header, deflate data, and an Adler-32 checksum of the original data.
+pub struct ZlibChunk {
+    header: Vec<u8>,
     data: Vec<u8>,
     checksum: u32,
 }
+impl ZlibChunk {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.data.len() + 6);
+        bytes.extend(&self.header);
+        bytes.extend(&self.data);
+        bytes.extend(&self.checksum.to_be_bytes());
+        bytes
+    }
+}
+
+pub fn uncompressed(data: &[u8]) -> ZlibChunk {
+    compressed_with_level(data, CompressionLevel::NONE)
+}
 
-fn uncompressed(data: Vec<u8>) -> ZlibChunk {
-    let checksum = adler(&data);
+pub fn compressed(data: &[u8]) -> ZlibChunk {
+    compressed_with_level(data, CompressionLevel::DEFAULT)
+}
+
+pub fn compressed_with_level(data: &[u8], level: CompressionLevel) -> ZlibChunk {
     ZlibChunk {
-        header: NO_COMPRESSION_HEADER,
-        data,
-        checksum,
+        header: header(level.flevel(), None),
+        data: deflate_with_level(data, level),
+        checksum: adler(data),
     }
 }
 
-const ADLER_BASE: u32 = 65521;
+/// Compresses data as if it followed the dictionary, which the decoder must be given too.
+pub fn compressed_with_dictionary(data: &[u8], dictionary: &[u8], level: CompressionLevel) -> ZlibChunk {
+    let mut writer = bits::BitWriter::new();
+    deflate::write_blocks(&mut writer, dictionary, data, true, level);
+    ZlibChunk {
+        header: header(level.flevel(), Some(dictionary)),
+        data: writer.finish(),
+        checksum: adler(data),
+    }
+}
 
-fn adler(data: &Vec<u8>) -> u32 {
-    adler32_update(1, data)
+// whether the header is valid, and if so whether a dictionary id follows it
+fn check_header(cmf: u8, flg: u8) -> Result<bool, DecodeError> {
+    if cmf & 0x0f != CM_DEFLATE || cmf >> 4 > MAX_CINFO {
+        return Err(DecodeError::UnsupportedMethod);
+    }
+    if !(cmf as u16 * 256 + flg as u16).is_multiple_of(FCHECK_DIVISOR) {
+        return Err(DecodeError::InvalidHeaderCheck);
+    }
+    Ok(flg & FDICT_FLAG != 0)
+}
+
+// the dictionary the header names, which has to be the one given
+fn check_dictionary(id: &[u8], given: Option<u32>) -> Result<(), DecodeError> {
+    let id = u32::from_be_bytes([id[0], id[1], id[2], id[3]]);
+    if given != Some(id) {
+        return Err(DecodeError::DictionaryRequired(id));
+    }
+    Ok(())
+}
+
+/// Decodes a zlib stream, checking its header and the Adler-32 of the decoded data.
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, DecodeError> {
+    decompress_from(data, None, &Limits::default())
+}
+
+/// Decodes a zlib stream that may have been compressed against the dictionary.
+pub fn decompress_with_dictionary(data: &[u8], dictionary: &[u8]) -> Result<Vec<u8>, DecodeError> {
+    decompress_from(data, Some(dictionary), &Limits::default())
+}
+
+/// Decodes a zlib stream, stopping with `LimitExceeded` once it would decode to more than the limits allow.
+pub fn decompress_with_limits(data: &[u8], limits: &Limits) -> Result<Vec<u8>, DecodeError> {
+    decompress_from(data, None, limits)
 }
 
-fn adler32_update(checksum: u32, data: &Vec<u8>) -> u32 {
-    let mut s1 = checksum & 0xffff;
-    let mut s2 = (checksum >> 16) & 0xffff;
-    for byte in data {
-        s1 = (s1 + *byte as u32) % ADLER_BASE;
-        s2 = (s1 + s2) % ADLER_BASE;
+fn decompress_from(data: &[u8], dictionary: Option<&[u8]>, limits: &Limits) -> Result<Vec<u8>, DecodeError> {
+    let (cmf, flg) = match data {
+        [cmf, flg, ..] => (*cmf, *flg),
+        _ => return Err(DecodeError::UnexpectedEnd),
+    };
+    let mut start = 2;
+    // copies can reach back into the dictionary as if it had been decoded first
+    let mut output = Vec::new();
+    if check_header(cmf, flg)? {
+        let id = data.get(2..2 + DICTIONARY_ID_SIZE).ok_or(DecodeError::UnexpectedEnd)?;
+        check_dictionary(id, dictionary.map(adler))?;
+        let dictionary = dictionary.unwrap_or_default();
+        output.extend(&dictionary[dictionary.len().saturating_sub(WINDOW_SIZE)..]);
+        start += DICTIONARY_ID_SIZE;
     }
-    (s2 << 16) + s1
+    let primed = output.len();
+    let trailer = start + inflate::inflate_all(&data[start..], &mut output, limits.output_limit(data.len()))?;
+    output.drain(..primed);
+    let expected = match data.get(trailer..trailer + 4) {
+        Some(checksum) => u32::from_be_bytes([checksum[0], checksum[1], checksum[2], checksum[3]]),
+        None => return Err(DecodeError::UnexpectedEnd),
+    };
+    let actual = adler(&output);
+    if actual != expected {
+        return Err(DecodeError::ChecksumMismatch { expected, actual });
+    }
+    Ok(output)
 }
 
 #[cfg(test)]
 mod tests {
+    use super::*;
+    #[test]
+    fn header_has_valid_fcheck() {
+        assert_eq!(header(0, None), [0x78, 0x01]);
+        assert_eq!(header(1, None), [0x78, 0x5e]);
+        assert_eq!(header(2, None), [0x78, 0x9c]);
+        assert_eq!(header(3, None), [0x78, 0xda]);
+        assert_eq!(header(2, Some(b"abc")), [0x78, 0xbb, 0x02, 0x4d, 0x01, 0x27]);
+    }
+    #[test]
+    fn uncompressed_frames_a_single_stored_block() {
+        let bytes = uncompressed(b"abc").to_bytes();
+        assert_eq!(bytes, vec![0x78, 0x01, 0x01, 0x03, 0x00, 0xfc, 0xff, b'a', b'b', b'c', 0x02, 0x4d, 0x01, 0x27]);
+    }
+    #[test]
+    fn uncompressed_handles_empty_data() {
+        assert_eq!(uncompressed(&[]).to_bytes(), vec![0x78, 0x01, 0x01, 0x00, 0x00, 0xff, 0xff, 0x00, 0x00, 0x00, 0x01]);
+    }
+    #[test]
+    fn compressed_uses_the_default_level_header() {
+        let bytes = compressed(b"a").to_bytes();
+        assert_eq!(bytes, vec![0x78, 0x9c, 0x4b, 0x04, 0x00, 0x00, 0x62, 0x00, 0x62]);
+    }
+    #[test]
+    fn compressed_with_level_sets_flevel() {
+        let headers: Vec<[u8; 2]> = [0, 1, 2, 6, 9].iter().map(|level| {
+            let bytes = compressed_with_level(b"abc", CompressionLevel::new(*level).unwrap()).to_bytes();
+            assert_eq!(decompress(&bytes).unwrap(), b"abc");
+            [bytes[0], bytes[1]]
+        }).collect();
+        assert_eq!(headers, vec![[0x78, 0x01], [0x78, 0x01], [0x78, 0x5e], [0x78, 0x9c], [0x78, 0xda]]);
+    }
+    #[test]
+    fn decompress_reverses_compressed_and_uncompressed() {
+        let data: Vec<u8> = (0..100000u32).map(|i| (i.wrapping_mul(i) >> 7) as u8).collect();
+        assert_eq!(decompress(&compressed(&data).to_bytes()).unwrap(), data);
+        assert_eq!(decompress(&uncompressed(&data).to_bytes()).unwrap(), data);
+        // level 9 system zlib output
+        assert_eq!(decompress(&[0x78, 0xda, 0x4b, 0x4c, 0x4a, 0x06, 0x00, 0x02, 0x4d, 0x01, 0x27]).unwrap(), b"abc");
+    }
+    #[test]
+    fn decompress_checks_the_header_and_checksum() {
+        let mut stream = compressed(b"abc").to_bytes();
+        assert_eq!(decompress(&stream[..1]), Err(DecodeError::UnexpectedEnd));
+        assert_eq!(decompress(&[0x79, 0x9c]), Err(DecodeError::UnsupportedMethod));
+        assert_eq!(decompress(&[0x88, 0x98]), Err(DecodeError::UnsupportedMethod));
+        assert_eq!(decompress(&[0x78, 0x9d]), Err(DecodeError::InvalidHeaderCheck));
+        assert_eq!(decompress(&[0x78, 0xbb, 0x02, 0x4d, 0x01, 0x27]), Err(DecodeError::DictionaryRequired(0x024d0127)));
+        assert_eq!(decompress(&stream[..stream.len() - 1]), Err(DecodeError::UnexpectedEnd));
+        let last = stream.len() - 1;
+        stream[last] ^= 1;
+        assert_eq!(decompress(&stream), Err(DecodeError::ChecksumMismatch { expected: 0x024d0126, actual: 0x024d0127 }));
+    }
+    #[test]
+    fn decompress_with_limits_counts_only_decoded_bytes() {
+        let bytes = compressed(&[7; 5000]).to_bytes();
+        let limits = Limits { max_output: 5000, ..Limits::default() };
+        assert_eq!(decompress_with_limits(&bytes, &limits).unwrap(), vec![7; 5000]);
+        let limits = Limits { max_output: 4999, ..Limits::default() };
+        assert_eq!(decompress_with_limits(&bytes, &limits), Err(DecodeError::LimitExceeded));
+    }
+    #[test]
+    fn preset_dictionaries_prime_the_window() {
+        let dictionary = b"P6 64 64 255 tile of the mandelbrot set at ";
+        let data = b"P6 64 64 255 tile of the mandelbrot set at -0.75, 0.1";
+        let chunk = compressed_with_dictionary(data, dictionary, CompressionLevel::DEFAULT);
+        let bytes = chunk.to_bytes();
+        assert!(bytes.len() < compressed(data).to_bytes().len() - 20);
+        assert_eq!(decompress_with_dictionary(&bytes, dictionary).unwrap(), &data[..]);
+        let id = adler(dictionary);
+        assert_eq!(decompress(&bytes), Err(DecodeError::DictionaryRequired(id)));
+        assert_eq!(decompress_with_dictionary(&bytes, b"P6"), Err(DecodeError::DictionaryRequired(id)));
+        // streams without a dictionary ignore one that is given
+        assert_eq!(decompress_with_dictionary(&compressed(data).to_bytes(), dictionary).unwrap(), &data[..]);
+        // system zlib output for "abcabc" against the dictionary "abc"
+        let system = [0x78, 0xbb, 0x02, 0x4d, 0x01, 0x27, 0x4b, 0x04, 0x23, 0x00, 0x08, 0x0c, 0x02, 0x4d];
+        assert_eq!(decompress_with_dictionary(&system, b"abc").unwrap(), b"abcabc");
+    }
+    #[test]
+    fn stored_blocks_split_at_65535_bytes() {
+        let data: Vec<u8> = (0..150000u32).map(|i| (i % 251) as u8).collect();
+        let blocks = stored_blocks(&data);
+        assert_eq!(blocks.len(), data.len() + 3 * 5);
+        // two full blocks, then the final one with the remainder
+        assert_eq!(&blocks[0..5], &[0x00, 0xff, 0xff, 0x00, 0x00]);
+        assert_eq!(&blocks[65540..65545], &[0x00, 0xff, 0xff, 0x00, 0x00]);
+        let remainder = (150000 - 2 * 65535) as u16;
+        assert_eq!(blocks[131080], 0x01);
+        assert_eq!(&blocks[131081..131085], &[remainder.to_le_bytes(), (!remainder).to_le_bytes()].concat()[..]);
+        assert_eq!(&blocks[131085..], &data[131070..]);
+    }
 }
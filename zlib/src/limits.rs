@@ -0,0 +1,20 @@
+/// Bounds on how much decoding may produce, so that a small hostile stream cannot exhaust memory.
+/// The default sets no bounds.
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub struct Limits {
+    /// the most bytes of decoded data
+    pub max_output: usize,
+    /// the most decoded bytes for each byte of input; deflate cannot legitimately go past about 1032
+    pub max_ratio: usize,
+}
+impl Default for Limits {
+    fn default() -> Self {
+        Limits { max_output: usize::MAX, max_ratio: usize::MAX }
+    }
+}
+impl Limits {
+    /// The most bytes that `input_length` bytes of input may decode to.
+    pub(crate) fn output_limit(&self, input_length: usize) -> usize {
+        self.max_output.min(input_length.saturating_mul(self.max_ratio))
+    }
+}
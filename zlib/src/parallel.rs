@@ -0,0 +1,84 @@
+use crate::bits::BitWriter;
+use crate::deflate::{write_blocks, write_stored_blocks};
+use crate::adler32::adler;
+use crate::{adler32_combine, header, CompressionLevel, ZlibChunk, WINDOW_SIZE};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+// large enough that losing matches across the start of each piece costs little
+const PIECE_SIZE: usize = 128 * 1024;
+
+// a piece's deflate data, ending on a byte boundary, and the Adler-32 and length of what it holds
+struct CompressedPiece {
+    data: Vec<u8>,
+    checksum: u32,
+    length: usize,
+}
+
+fn compress_piece(data: &[u8], index: usize, level: CompressionLevel) -> CompressedPiece {
+    let start = index * PIECE_SIZE;
+    let piece = &data[start..(start + PIECE_SIZE).min(data.len())];
+    // the end of the previous piece serves as a preset dictionary, as it would have been the window
+    let window = &data[start.saturating_sub(WINDOW_SIZE)..start];
+    let last = start + piece.len() == data.len();
+    let mut writer = BitWriter::new();
+    write_blocks(&mut writer, window, piece, last, level);
+    // a sync flush ends each piece on a byte boundary, so the pieces can simply be joined
+    if !last {
+        write_stored_blocks(&mut writer, &[], false);
+    }
+    CompressedPiece { data: writer.finish(), checksum: adler(piece), length: piece.len() }
+}
+
+/// Compresses pieces of the data on `threads` threads at once, giving a single zlib stream any decoder reads.
+/// Each piece only looks back into the one before it, so the output does not depend on the number of threads.
+pub fn compressed_parallel(data: &[u8], level: CompressionLevel, threads: usize) -> ZlibChunk {
+    let piece_count = data.len().div_ceil(PIECE_SIZE).max(1);
+    let pieces: Vec<Mutex<Option<CompressedPiece>>> = (0..piece_count).map(|_| Mutex::new(None)).collect();
+    let next = AtomicUsize::new(0);
+    thread::scope(|scope| {
+        for _ in 0..threads.clamp(1, piece_count) {
+            scope.spawn(|| loop {
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                if index >= piece_count {
+                    break;
+                }
+                *pieces[index].lock().unwrap() = Some(compress_piece(data, index, level));
+            });
+        }
+    });
+
+    let mut compressed = Vec::new();
+    let mut checksum = adler(&[]);
+    for piece in pieces {
+        let piece = piece.into_inner().unwrap().unwrap();
+        compressed.extend(piece.data);
+        checksum = adler32_combine(checksum, piece.checksum, piece.length);
+    }
+    ZlibChunk { header: header(level.flevel(), None), data: compressed, checksum }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compressed_with_level, decompress};
+    #[test]
+    fn the_thread_count_does_not_change_the_stream() {
+        let data: Vec<u8> = (0..3 * PIECE_SIZE as u32 + 1000).map(|i| ((i / 7) ^ (i >> 11)) as u8).collect();
+        let single = compressed_parallel(&data, CompressionLevel::DEFAULT, 1).to_bytes();
+        assert_eq!(compressed_parallel(&data, CompressionLevel::DEFAULT, 3).to_bytes(), single);
+        assert_eq!(compressed_parallel(&data, CompressionLevel::DEFAULT, 16).to_bytes(), single);
+        assert_eq!(decompress(&single).unwrap(), data);
+        // the pieces lose only a little to starting their own blocks
+        let serial = compressed_with_level(&data, CompressionLevel::DEFAULT).to_bytes();
+        assert!(single.len() < serial.len() + 100, "{} {}", single.len(), serial.len());
+    }
+    #[test]
+    fn small_inputs_make_a_single_piece() {
+        assert_eq!(compressed_parallel(b"abc", CompressionLevel::DEFAULT, 4).to_bytes(), compressed_with_level(b"abc", CompressionLevel::DEFAULT).to_bytes());
+        assert_eq!(decompress(&compressed_parallel(&[], CompressionLevel::BEST, 0).to_bytes()).unwrap(), b"");
+        let stored = compressed_parallel(&[1; 300000], CompressionLevel::NONE, 2).to_bytes();
+        assert_eq!(decompress(&stored).unwrap(), vec![1; 300000]);
+    }
+}
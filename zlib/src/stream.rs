@@ -0,0 +1,423 @@
+use crate::bits::BitWriter;
+use crate::deflate::{write_blocks, write_stored_blocks};
+use crate::inflate::Inflater;
+use crate::adler32::adler;
+use crate::{adler32_update, check_dictionary, check_header, header, CompressionLevel, DecodeError, Limits, DICTIONARY_ID_SIZE, WINDOW_SIZE};
+use std::io::{self, Read, Write};
+
+// input is held back until there is this much of it, so blocks are not cut short
+const STREAM_BLOCK_SIZE: usize = 1 << 16;
+const READ_SIZE: usize = 1 << 13;
+const CHECKSUM_SIZE: usize = 4;
+
+/// How much of the data written so far an encoder should push through to its writer.
+#[derive(Clone,Copy,PartialEq,Debug)]
+pub enum Flush {
+    /// nothing, leaving the encoder to decide when to write blocks
+    None,
+    /// everything, ending on a byte boundary with an empty stored block so a decoder can read it all
+    Sync,
+    /// as `Sync`, and later data does not refer back past this point, so decoding can restart here
+    Full,
+    /// everything, with the final block and the trailer, after which nothing more can be written
+    Finish,
+}
+
+/// Raw deflate compression that keeps only the window and the input not yet compressed.
+pub(crate) struct Compressor {
+    bits: BitWriter,
+    level: CompressionLevel,
+    history: Vec<u8>,
+    pending: Vec<u8>,
+}
+impl Compressor {
+    /// Starts with `prefix`, the header of whichever format wraps the deflate data.
+    pub(crate) fn new(prefix: &[u8], level: CompressionLevel) -> Self {
+        let mut bits = BitWriter::new();
+        bits.write_aligned_bytes(prefix);
+        Compressor { bits, level, history: Vec::new(), pending: Vec::new() }
+    }
+    /// Lets the first data match against the end of `dictionary`.
+    pub(crate) fn prime(&mut self, dictionary: &[u8]) {
+        self.history = dictionary[dictionary.len().saturating_sub(WINDOW_SIZE)..].to_vec();
+    }
+    fn compress_pending(&mut self, last: bool) {
+        write_blocks(&mut self.bits, &self.history, &self.pending, last, self.level);
+        self.history.append(&mut self.pending);
+        if self.history.len() > WINDOW_SIZE {
+            self.history.drain(..self.history.len() - WINDOW_SIZE);
+        }
+    }
+    /// Takes in `data`, compressing whole pieces of it as they fill up.
+    pub(crate) fn write(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            let length = data.len().min(STREAM_BLOCK_SIZE - self.pending.len());
+            self.pending.extend(&data[..length]);
+            data = &data[length..];
+            if self.pending.len() == STREAM_BLOCK_SIZE {
+                self.compress_pending(false);
+            }
+        }
+    }
+    /// Compresses what `flush` asks for, then hands over the finished bytes.
+    /// After `Flush::Finish` the output is byte aligned, ready for a trailer.
+    pub(crate) fn flush(&mut self, flush: Flush) -> Vec<u8> {
+        match flush {
+            Flush::None => {},
+            Flush::Sync | Flush::Full => {
+                if !self.pending.is_empty() {
+                    self.compress_pending(false);
+                }
+                write_stored_blocks(&mut self.bits, &[], false);
+                if flush == Flush::Full {
+                    self.history.clear();
+                }
+            },
+            Flush::Finish => {
+                self.compress_pending(true);
+                self.bits.align();
+            },
+        }
+        self.bits.take_bytes()
+    }
+}
+
+pub(crate) fn finished_error() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, "the stream has already been finished")
+}
+
+/// Compresses everything written to it as one zlib stream, passing the output on to `writer`.
+pub struct ZlibEncoder<W: Write> {
+    writer: W,
+    compressor: Compressor,
+    checksum: u32,
+    finished: bool,
+}
+impl<W: Write> ZlibEncoder<W> {
+    pub fn new(writer: W) -> Self {
+        ZlibEncoder::with_level(writer, CompressionLevel::DEFAULT)
+    }
+    pub fn with_level(writer: W, level: CompressionLevel) -> Self {
+        ZlibEncoder { writer, compressor: Compressor::new(&header(level.flevel(), None), level), checksum: 1, finished: false }
+    }
+    /// Compresses as if the dictionary came first, naming it in the header so a decoder knows to ask for it.
+    pub fn with_dictionary(writer: W, dictionary: &[u8], level: CompressionLevel) -> Self {
+        let mut compressor = Compressor::new(&header(level.flevel(), Some(dictionary)), level);
+        compressor.prime(dictionary);
+        ZlibEncoder { writer, compressor, checksum: 1, finished: false }
+    }
+    pub fn flush_as(&mut self, flush: Flush) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        let mut bytes = self.compressor.flush(flush);
+        if flush == Flush::Finish {
+            bytes.extend(&self.checksum.to_be_bytes());
+            self.finished = true;
+        }
+        self.writer.write_all(&bytes)
+    }
+    /// Writes the rest of the stream and gives back the writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.flush_as(Flush::Finish)?;
+        Ok(self.writer)
+    }
+}
+impl<W: Write> Write for ZlibEncoder<W> {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        if self.finished {
+            return Err(finished_error());
+        }
+        self.checksum = adler32_update(self.checksum, data);
+        self.compressor.write(data);
+        let bytes = self.compressor.flush(Flush::None);
+        self.writer.write_all(&bytes)?;
+        Ok(data.len())
+    }
+    /// A sync flush, so everything written so far can be decoded from the writer's output.
+    fn flush(&mut self) -> io::Result<()> {
+        self.flush_as(Flush::Sync)?;
+        self.writer.flush()
+    }
+}
+
+/// Decodes a zlib stream read from `reader`, keeping no more of the output than copies can refer back to.
+pub struct ZlibDecoder<R: Read> {
+    reader: R,
+    // the Adler-32 of the dictionary, and the part of it copies can reach
+    dictionary: Option<(u32, Vec<u8>)>,
+    input: Vec<u8>,
+    // where the unused part of `input` starts
+    position: usize,
+    // everything taken from the reader, and everything decoded, for checking the limits
+    input_total: usize,
+    output_total: usize,
+    limits: Limits,
+    header_read: bool,
+    inflater: Inflater,
+    // the recent output, of which everything from `returned` on has not been read yet
+    window: Vec<u8>,
+    returned: usize,
+    checksum: u32,
+    finished: bool,
+}
+impl<R: Read> ZlibDecoder<R> {
+    pub fn new(reader: R) -> Self {
+        ZlibDecoder {
+            reader,
+            dictionary: None,
+            input: Vec::new(),
+            position: 0,
+            input_total: 0,
+            output_total: 0,
+            limits: Limits::default(),
+            header_read: false,
+            inflater: Inflater::new(),
+            window: Vec::new(),
+            returned: 0,
+            checksum: 1,
+            finished: false,
+        }
+    }
+    /// Decodes a stream that may have been compressed against the dictionary.
+    pub fn with_dictionary(reader: R, dictionary: &[u8]) -> Self {
+        let window = dictionary[dictionary.len().saturating_sub(WINDOW_SIZE)..].to_vec();
+        ZlibDecoder { dictionary: Some((adler(dictionary), window)), ..ZlibDecoder::new(reader) }
+    }
+    /// Bounds the output, making reads fail with `LimitExceeded` once the stream decodes to more than they allow.
+    /// The ratio is checked against the input read so far, so it should allow for the larger ratios
+    /// that the start of a stream can reach.
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+    // reads more input, returning false at the end of the reader
+    fn fill(&mut self) -> io::Result<bool> {
+        self.input.drain(..self.position);
+        self.position = 0;
+        let start = self.input.len();
+        self.input.resize(start + READ_SIZE, 0);
+        let read = loop {
+            match self.reader.read(&mut self.input[start..]) {
+                Ok(read) => break read,
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => {},
+                Err(error) => {
+                    self.input.truncate(start);
+                    return Err(error);
+                },
+            }
+        };
+        self.input.truncate(start + read);
+        self.input_total += read;
+        Ok(read > 0)
+    }
+    // makes sure at least `count` unused input bytes are buffered
+    fn fill_to(&mut self, count: usize) -> io::Result<&[u8]> {
+        while self.input.len() - self.position < count {
+            if !self.fill()? {
+                return Err(DecodeError::UnexpectedEnd.into());
+            }
+        }
+        Ok(&self.input[self.position..self.position + count])
+    }
+    fn read_header(&mut self) -> io::Result<()> {
+        let (cmf, flg) = match self.fill_to(2)? {
+            [cmf, flg] => (*cmf, *flg),
+            _ => unreachable!(),
+        };
+        self.position += 2;
+        if check_header(cmf, flg)? {
+            let id = self.fill_to(DICTIONARY_ID_SIZE)?.to_vec();
+            check_dictionary(&id, self.dictionary.as_ref().map(|(id, _)| *id))?;
+            self.window = self.dictionary.take().unwrap_or_default().1;
+            self.returned = self.window.len();
+            self.position += DICTIONARY_ID_SIZE;
+        }
+        self.header_read = true;
+        Ok(())
+    }
+    fn read_trailer(&mut self) -> io::Result<()> {
+        let trailer = self.fill_to(CHECKSUM_SIZE)?;
+        let expected = u32::from_be_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+        if expected != self.checksum {
+            return Err(DecodeError::ChecksumMismatch { expected, actual: self.checksum }.into());
+        }
+        self.position += CHECKSUM_SIZE;
+        self.finished = true;
+        Ok(())
+    }
+}
+impl<R: Read> Read for ZlibDecoder<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        if buffer.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            if self.returned < self.window.len() {
+                let length = buffer.len().min(self.window.len() - self.returned);
+                let output = &self.window[self.returned..self.returned + length];
+                buffer[..length].copy_from_slice(output);
+                self.checksum = adler32_update(self.checksum, output);
+                self.returned += length;
+                return Ok(length);
+            }
+            if self.finished {
+                return Ok(0);
+            }
+            if !self.header_read {
+                self.read_header()?;
+                continue;
+            }
+            if self.inflater.is_done() {
+                self.read_trailer()?;
+                continue;
+            }
+            // everything has been read, so only the last window's worth is needed for copies
+            if self.window.len() > 2 * WINDOW_SIZE {
+                self.window.drain(..self.window.len() - WINDOW_SIZE);
+                self.returned = self.window.len();
+            }
+            let decoded = self.window.len();
+            let limit = decoded + buffer.len().min(STREAM_BLOCK_SIZE);
+            let used = self.inflater.inflate(&self.input[self.position..], &mut self.window, limit)?;
+            self.position += used;
+            self.output_total += self.window.len() - decoded;
+            let input_used = self.input_total - (self.input.len() - self.position);
+            if self.output_total > self.limits.output_limit(input_used) {
+                return Err(DecodeError::LimitExceeded.into());
+            }
+            if self.returned == self.window.len() && !self.inflater.is_done() && !self.fill()? {
+                return Err(DecodeError::UnexpectedEnd.into());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compressed, decompress, inflate};
+    fn noise(count: usize) -> Vec<u8> {
+        let mut state = 7u32;
+        (0..count).map(|_| {
+            state = state.wrapping_mul(1103515245).wrapping_add(12345);
+            (state >> 16) as u8 % 16
+        }).collect()
+    }
+    // reads one byte at a time, to stop after any byte of the stream
+    struct Trickle<'a>(&'a [u8]);
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            let length = self.0.len().min(buffer.len()).min(1);
+            buffer[..length].copy_from_slice(&self.0[..length]);
+            self.0 = &self.0[length..];
+            Ok(length)
+        }
+    }
+    #[test]
+    fn encoder_matches_compressed_for_small_data() {
+        let data = b"mandelbrot set, julia set, ".repeat(20);
+        let mut encoder = ZlibEncoder::new(Vec::new());
+        for byte in &data {
+            encoder.write_all(&[*byte]).unwrap();
+        }
+        assert_eq!(encoder.finish().unwrap(), compressed(&data).to_bytes());
+    }
+    #[test]
+    fn streams_round_trip_in_small_pieces() {
+        let data = noise(300000);
+        let mut encoder = ZlibEncoder::new(Vec::new());
+        for piece in data.chunks(1000) {
+            encoder.write_all(piece).unwrap();
+        }
+        let stream = encoder.finish().unwrap();
+        assert_eq!(decompress(&stream).unwrap(), data);
+        let mut output = Vec::new();
+        ZlibDecoder::new(Trickle(&stream)).read_to_end(&mut output).unwrap();
+        assert_eq!(output, data);
+        let mut output = Vec::new();
+        ZlibDecoder::new(&stream[..]).read_to_end(&mut output).unwrap();
+        assert_eq!(output, data);
+    }
+    #[test]
+    fn flushes_make_the_output_so_far_decodable() {
+        let mut encoder = ZlibEncoder::new(Vec::new());
+        encoder.write_all(b"koch koch koch").unwrap();
+        encoder.flush_as(Flush::None).unwrap();
+        assert_eq!(encoder.writer.len(), 2);
+        encoder.flush().unwrap();
+        let synced = encoder.writer.clone();
+        // a sync flush ends with an empty stored block
+        assert!(synced.ends_with(&[0x00, 0x00, 0xff, 0xff]));
+        let mut decoder = ZlibDecoder::new(&synced[..]);
+        let mut buffer = [0; 14];
+        decoder.read_exact(&mut buffer).unwrap();
+        assert_eq!(&buffer, b"koch koch koch");
+
+        // after a full flush the data is not matched against what came before it
+        encoder.flush_as(Flush::Full).unwrap();
+        let restart = encoder.writer.len();
+        encoder.write_all(b"koch koch koch").unwrap();
+        let stream = encoder.finish().unwrap();
+        assert_eq!(inflate(&stream[restart..stream.len() - CHECKSUM_SIZE]).unwrap(), b"koch koch koch");
+        assert_eq!(decompress(&stream).unwrap(), b"koch koch kochkoch koch koch");
+    }
+    #[test]
+    fn dictionaries_carry_through_streams() {
+        let dictionary = noise(40000);
+        let data = [&dictionary[30000..], &dictionary[..100]].concat();
+        let mut encoder = ZlibEncoder::with_dictionary(Vec::new(), &dictionary, CompressionLevel::DEFAULT);
+        encoder.write_all(&data).unwrap();
+        let stream = encoder.finish().unwrap();
+        // the copy from the end of the dictionary takes a few bytes, but the start is out of reach
+        assert!(stream.len() < 200);
+        assert_eq!(crate::decompress_with_dictionary(&stream, &dictionary).unwrap(), data);
+        let mut output = Vec::new();
+        ZlibDecoder::with_dictionary(Trickle(&stream), &dictionary).read_to_end(&mut output).unwrap();
+        assert_eq!(output, data);
+        let error = ZlibDecoder::new(&stream[..]).read_to_end(&mut output).unwrap_err();
+        assert_eq!(error.to_string(), format!("a preset dictionary with adler-32 {:08x} is required", adler(&dictionary)));
+    }
+    #[test]
+    fn decoder_stops_at_the_limits() {
+        let stream = compressed(&[0; 200000]).to_bytes();
+        let mut decoder = ZlibDecoder::new(&stream[..]);
+        decoder.set_limits(Limits { max_output: 100000, ..Limits::default() });
+        let mut output = Vec::new();
+        let error = decoder.read_to_end(&mut output).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "decoded data exceeds the output limit");
+        assert!(output.len() <= 100000);
+        let mut decoder = ZlibDecoder::new(&stream[..]);
+        decoder.set_limits(Limits { max_output: 200000, max_ratio: 1100 });
+        output.clear();
+        decoder.read_to_end(&mut output).unwrap();
+        assert_eq!(output.len(), 200000);
+    }
+    #[test]
+    fn finished_encoders_take_no_more_data() {
+        let mut encoder = ZlibEncoder::new(Vec::new());
+        encoder.flush_as(Flush::Finish).unwrap();
+        encoder.flush_as(Flush::Finish).unwrap();
+        assert_eq!(encoder.write(b"a").unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(decompress(&encoder.writer).unwrap(), b"");
+    }
+    #[test]
+    fn decoder_reports_errors_as_io_errors() {
+        let read = |stream: &[u8]| {
+            let mut output = Vec::new();
+            ZlibDecoder::new(stream).read_to_end(&mut output).map(|_| output)
+        };
+        let mut stream = compressed(b"abc").to_bytes();
+        assert_eq!(read(&stream).unwrap(), b"abc");
+        assert_eq!(read(&stream[..stream.len() - 1]).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(read(&[0x78, 0x9d]).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        let error = read(&[0x78, 0xbb, 0x02, 0x4d, 0x01, 0x27]).unwrap_err();
+        assert_eq!(error.to_string(), "a preset dictionary with adler-32 024d0127 is required");
+        let last = stream.len() - 1;
+        stream[last] ^= 1;
+        let error = read(&stream).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        let error = error.into_inner().unwrap().downcast::<DecodeError>().unwrap();
+        assert_eq!(*error, DecodeError::ChecksumMismatch { expected: 0x024d0126, actual: 0x024d0127 });
+    }
+}